@@ -0,0 +1,463 @@
+// Content-aware rules for what the clipboard monitor keeps in history. They
+// work on plain strings so they can be tested without a running app.
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+// A copy is trivial when it is a single token (no whitespace) that is either
+// shorter than `min_length` characters or one character repeated, like "----".
+// URLs and email addresses are never trivial, however short.
+pub(crate) fn is_trivial_content(content: &str, min_length: usize) -> bool {
+    let token = content.trim();
+    if token.is_empty() {
+        return true;
+    }
+    if token.chars().any(char::is_whitespace) || is_url(token) || is_email(token) {
+        return false;
+    }
+
+    let mut chars = token.chars();
+    let first = chars.next();
+    let repeated = token.chars().count() > 1 && chars.all(|c| Some(c) == first);
+    token.chars().count() < min_length || repeated
+}
+
+fn is_url(token: &str) -> bool {
+    token.split_once("://").is_some_and(|(scheme, rest)| {
+        !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) && !rest.is_empty()
+    })
+}
+
+fn is_email(token: &str) -> bool {
+    token.split_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty() && !domain.contains('@') && domain.split('.').count() > 1 && domain.split('.').all(|part| !part.is_empty())
+    })
+}
+
+// Removes ANSI escape sequences (CSI like `ESC[31m`, OSC like `ESC]0;title BEL`)
+fn strip_ansi_escapes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            // CSI: parameters until a final byte in '@'..='~'
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            },
+            // OSC: until BEL or the ST terminator (ESC \)
+            Some(']') => {
+                while let Some(c) = chars.next() {
+                    if c == '\u{7}' {
+                        break;
+                    }
+                    if c == '\u{1b}' && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            },
+            // Two-character escape, nothing more to skip
+            _ => {},
+        }
+    }
+
+    result
+}
+
+// True for clipboard content that carries nothing worth keeping: only whitespace,
+// control characters and terminal escape sequences
+pub(crate) fn is_clipboard_noise(text: &str) -> bool {
+    strip_ansi_escapes(text)
+        .chars()
+        .all(|c| c.is_whitespace() || c.is_control())
+}
+
+// Compiles saved patterns once, so capturing text doesn't recompile them for
+// every copy. Patterns are validated when they are saved; one that no longer
+// compiles is skipped rather than matched.
+pub(crate) fn compile_patterns(patterns: &[String]) -> Vec<regex::Regex> {
+    patterns.iter()
+        .filter_map(|pattern| regex::Regex::new(pattern).map_err(|e| eprintln!("Skipping pattern \"{}\": {}", pattern, e)).ok())
+        .collect()
+}
+
+pub(crate) fn matches_any_pattern(content: &str, patterns: &[regex::Regex]) -> bool {
+    patterns.iter().any(|pattern| pattern.is_match(content))
+}
+
+// A user-defined change made to captured text before it is stored. Rules run in
+// order, each on the previous rule's output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct TransformRule {
+    #[serde(default)]
+    pub(crate) matches: Option<String>, // Regex the text must match for the rule to apply; None applies it always
+    pub(crate) action: TransformAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub(crate) enum TransformAction {
+    Trim,
+    Lowercase,
+    RegexReplace { pattern: String, replacement: String }, // `replacement` may refer to groups as $1 or ${name}
+    StripUrlParams {
+        #[serde(default)]
+        params: Vec<String>, // Names to drop from URLs; "utm_*" drops a prefix. Empty drops common tracking parameters
+    },
+}
+
+// Dropped by StripUrlParams when the rule lists no parameters of its own
+const TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "msclkid", "yclid", "mc_cid", "mc_eid", "igshid", "_hsenc", "_hsmi", "ref_src",
+];
+
+// A TransformRule with its regexes compiled, so capturing text doesn't
+// recompile them for every copy
+#[derive(Debug, Clone)]
+pub(crate) struct CompiledTransformRule {
+    matches: Option<regex::Regex>,
+    action: CompiledTransformAction,
+}
+
+#[derive(Debug, Clone)]
+enum CompiledTransformAction {
+    Trim,
+    Lowercase,
+    RegexReplace { pattern: regex::Regex, replacement: String },
+    StripUrlParams { params: Vec<String> },
+}
+
+impl CompiledTransformRule {
+    // Fails when one of the rule's regexes doesn't compile, so a bad rule is refused when it is saved
+    pub(crate) fn new(rule: &TransformRule) -> Result<Self, String> {
+        let matches = rule.matches.as_deref()
+            .map(|pattern| regex::Regex::new(pattern).map_err(|e| format!("Invalid match pattern \"{}\": {}", pattern, e)))
+            .transpose()?;
+        let action = match &rule.action {
+            TransformAction::Trim => CompiledTransformAction::Trim,
+            TransformAction::Lowercase => CompiledTransformAction::Lowercase,
+            TransformAction::RegexReplace { pattern, replacement } => CompiledTransformAction::RegexReplace {
+                pattern: regex::Regex::new(pattern).map_err(|e| format!("Invalid replace pattern \"{}\": {}", pattern, e))?,
+                replacement: replacement.clone(),
+            },
+            TransformAction::StripUrlParams { params } => CompiledTransformAction::StripUrlParams { params: params.clone() },
+        };
+        Ok(CompiledTransformRule { matches, action })
+    }
+}
+
+// Compiles saved rules, skipping any that no longer compile rather than failing them all
+pub(crate) fn compile_transform_rules(rules: &[TransformRule]) -> Vec<CompiledTransformRule> {
+    rules.iter()
+        .filter_map(|rule| CompiledTransformRule::new(rule).map_err(|e| eprintln!("Skipping transform rule: {}", e)).ok())
+        .collect()
+}
+
+pub(crate) fn apply_transform_rules(content: &str, rules: &[CompiledTransformRule]) -> String {
+    let mut text = content.to_string();
+    for rule in rules {
+        if rule.matches.as_ref().is_some_and(|pattern| !pattern.is_match(&text)) {
+            continue;
+        }
+        text = match &rule.action {
+            CompiledTransformAction::Trim => text.trim().to_string(),
+            CompiledTransformAction::Lowercase => text.to_lowercase(),
+            CompiledTransformAction::RegexReplace { pattern, replacement } => pattern.replace_all(&text, replacement.as_str()).into_owned(),
+            CompiledTransformAction::StripUrlParams { params } => strip_url_params(&text, params),
+        };
+    }
+    text
+}
+
+static URL_WITH_QUERY: LazyLock<regex::Regex> =
+    LazyLock::new(|| regex::Regex::new(r"(https?://[^\s?#]+)\?([^\s#]*)").expect("valid URL pattern"));
+
+// Removes the listed query parameters from every http(s) URL in `text`,
+// leaving the rest of each URL, including any #fragment, as it was
+fn strip_url_params(text: &str, params: &[String]) -> String {
+    URL_WITH_QUERY.replace_all(text, |caps: &regex::Captures| {
+        let kept: Vec<&str> = caps[2].split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| !is_stripped_param(pair.split('=').next().unwrap_or(pair), params))
+            .collect();
+        if kept.is_empty() {
+            caps[1].to_string()
+        } else {
+            format!("{}?{}", &caps[1], kept.join("&"))
+        }
+    }).into_owned()
+}
+
+fn is_stripped_param(name: &str, params: &[String]) -> bool {
+    let matches = |param: &str| match param.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == param,
+    };
+    if params.is_empty() {
+        TRACKING_PARAMS.iter().any(|param| matches(param))
+    } else {
+        params.iter().any(|param| matches(param))
+    }
+}
+
+// How far back the conflict detector compares clipboard values
+const CONFLICT_WINDOW: Duration = Duration::from_secs(10);
+// Suspicious changes needed within CONFLICT_SIGNAL_WINDOW before warning
+const CONFLICT_SIGNAL_THRESHOLD: usize = 3;
+const CONFLICT_SIGNAL_WINDOW: Duration = Duration::from_secs(60);
+// Warn at most this often, however long the conflict lasts
+const CONFLICT_WARNING_COOLDOWN: Duration = Duration::from_secs(300);
+
+// Spots another clipboard manager fighting over the clipboard. Every value the
+// monitor sees is recorded, whether this app set it or something else did. An
+// outside change back to a value seen moments ago (one this app just set, or an
+// A, B, A flip-flop) is suspicious; a few of those in a row raise a warning.
+#[derive(Default)]
+pub(crate) struct ConflictDetector {
+    recent: VecDeque<(Instant, u64)>, // Hashes of recently seen values, oldest first
+    signals: VecDeque<Instant>,
+    last_warning: Option<Instant>,
+}
+
+impl ConflictDetector {
+    // Returns true when this change should raise a conflict warning
+    pub(crate) fn record(&mut self, content: &str, self_caused: bool, now: Instant) -> bool {
+        let hash = content_hash(content);
+        self.recent.retain(|(seen, _)| now.duration_since(*seen) <= CONFLICT_WINDOW);
+        self.signals.retain(|seen| now.duration_since(*seen) <= CONFLICT_SIGNAL_WINDOW);
+
+        // The newest entry is the value being replaced, so it can't be a repeat
+        let is_repeat = self.recent.iter().rev().skip(1).any(|(_, seen)| *seen == hash)
+            && self.recent.back().is_some_and(|(_, last)| *last != hash);
+        self.recent.push_back((now, hash));
+
+        if self_caused || !is_repeat {
+            return false;
+        }
+        self.signals.push_back(now);
+
+        let cooled_down = self.last_warning.is_none_or(|warned| now.duration_since(warned) >= CONFLICT_WARNING_COOLDOWN);
+        if self.signals.len() >= CONFLICT_SIGNAL_THRESHOLD && cooled_down {
+            self.last_warning = Some(now);
+            self.signals.clear();
+            return true;
+        }
+        false
+    }
+}
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn short_single_word_is_trivial() {
+        assert!(is_trivial_content("ok", 4));
+        assert!(is_trivial_content("  the\n", 4));
+    }
+
+    #[test]
+    fn word_at_min_length_is_kept() {
+        assert!(!is_trivial_content("word", 4));
+        assert!(is_trivial_content("wor", 4));
+    }
+
+    #[test]
+    fn length_counts_characters_not_bytes() {
+        assert!(!is_trivial_content("日本語", 3));
+    }
+
+    #[test]
+    fn several_words_are_never_trivial() {
+        assert!(!is_trivial_content("a b", 10));
+    }
+
+    #[test]
+    fn content_matching_any_pattern_is_flagged() {
+        let patterns = compile_patterns(&[r"^sk-[A-Za-z0-9]{8,}$".to_string(), r"(?i)password".to_string()]);
+        assert!(matches_any_pattern("sk-abcdef123456", &patterns));
+        assert!(matches_any_pattern("my Password is hunter2", &patterns));
+        assert!(!matches_any_pattern("hello world", &patterns));
+        assert!(!matches_any_pattern("anything", &[]));
+    }
+
+    #[test]
+    fn invalid_pattern_matches_nothing() {
+        let patterns = compile_patterns(&["(".to_string(), "ok".to_string()]);
+        assert_eq!(patterns.len(), 1);
+        assert!(!matches_any_pattern("(", &patterns));
+    }
+
+    fn spec(matches: Option<&str>, action: TransformAction) -> TransformRule {
+        TransformRule { matches: matches.map(str::to_string), action }
+    }
+
+    fn rule(matches: Option<&str>, action: TransformAction) -> CompiledTransformRule {
+        CompiledTransformRule::new(&spec(matches, action)).unwrap()
+    }
+
+    fn strip_tracking() -> CompiledTransformRule {
+        rule(None, TransformAction::StripUrlParams { params: Vec::new() })
+    }
+
+    #[test]
+    fn tracking_params_are_stripped_from_urls() {
+        let rules = [strip_tracking()];
+        assert_eq!(
+            apply_transform_rules("https://example.com/a?id=7&utm_source=x&fbclid=abc", &rules),
+            "https://example.com/a?id=7"
+        );
+        assert_eq!(apply_transform_rules("see https://example.com/?utm_medium=email now", &rules), "see https://example.com/ now");
+    }
+
+    #[test]
+    fn url_fragment_and_untracked_urls_are_kept() {
+        let rules = [strip_tracking()];
+        assert_eq!(apply_transform_rules("https://x.io/p?gclid=1#top", &rules), "https://x.io/p#top");
+        assert_eq!(apply_transform_rules("https://x.io/p?q=rust", &rules), "https://x.io/p?q=rust");
+        assert_eq!(apply_transform_rules("not a url?utm_source=x", &rules), "not a url?utm_source=x");
+    }
+
+    #[test]
+    fn listed_url_params_replace_the_tracking_defaults() {
+        let rules = [rule(None, TransformAction::StripUrlParams { params: vec!["ref".to_string(), "session_*".to_string()] })];
+        assert_eq!(
+            apply_transform_rules("http://x.io/?ref=a&session_id=2&utm_source=b", &rules),
+            "http://x.io/?utm_source=b"
+        );
+    }
+
+    #[test]
+    fn trim_removes_surrounding_whitespace() {
+        assert_eq!(apply_transform_rules("  text with  spaces \n\t", &[rule(None, TransformAction::Trim)]), "text with  spaces");
+    }
+
+    #[test]
+    fn rules_apply_in_order_and_only_when_matching() {
+        let rules = [
+            rule(Some(r"^\s*[A-Z0-9-]+\s*$"), TransformAction::Trim),
+            rule(Some("^ABC"), TransformAction::Lowercase),
+            rule(None, TransformAction::RegexReplace { pattern: r"-(\d+)".to_string(), replacement: "#$1".to_string() }),
+        ];
+        assert_eq!(apply_transform_rules(" ABC-42 ", &rules), "abc#42");
+        assert_eq!(apply_transform_rules(" Mixed-1 ", &rules), " Mixed#1 ");
+    }
+
+    #[test]
+    fn invalid_rule_patterns_are_refused() {
+        assert!(CompiledTransformRule::new(&spec(Some("("), TransformAction::Trim)).is_err());
+        let replace = TransformAction::RegexReplace { pattern: "[".to_string(), replacement: String::new() };
+        assert!(CompiledTransformRule::new(&spec(None, replace.clone())).is_err());
+        assert_eq!(compile_transform_rules(&[spec(None, replace), spec(None, TransformAction::Trim)]).len(), 1);
+    }
+
+    #[test]
+    fn whitespace_and_escape_only_content_is_noise() {
+        assert!(is_clipboard_noise("\t\t"));
+        assert!(is_clipboard_noise("\n \r\n"));
+        assert!(is_clipboard_noise("\x1b[0m"));
+        assert!(is_clipboard_noise("\x1b[1;31m\t\x1b]0;title\x07\x1b[0m"));
+    }
+
+    #[test]
+    fn code_with_internal_whitespace_is_not_noise() {
+        let code = "fn main() {\n\tlet x = 1;\n\n    println!(\"{}\", x);\n}";
+        assert!(!is_clipboard_noise(code));
+        assert_eq!(strip_ansi_escapes(code), code);
+        assert_eq!(strip_ansi_escapes("\x1b[32mok\x1b[0m\tdone"), "ok\tdone");
+    }
+
+    #[test]
+    fn short_urls_and_emails_are_kept() {
+        assert!(!is_trivial_content("http://x.io", 50));
+        assert!(!is_trivial_content("a@b.co", 50));
+    }
+
+    #[test]
+    fn url_and_email_lookalikes_are_still_trivial() {
+        assert!(is_trivial_content("://x", 8));
+        assert!(is_trivial_content("a@b", 8));
+        assert!(is_trivial_content("@b.co", 8));
+        assert!(is_trivial_content("a@b.", 8));
+    }
+
+    #[test]
+    fn repeated_character_is_trivial_at_any_length() {
+        assert!(is_trivial_content("------------------", 4));
+        assert!(!is_trivial_content("x", 1));
+    }
+
+    #[test]
+    fn whitespace_only_is_trivial() {
+        assert!(is_trivial_content(" \t\n", 1));
+    }
+
+    // Feeds `values` one second apart; values prefixed with '>' were set by the app
+    fn warnings(detector: &mut ConflictDetector, start: Instant, values: &[&str]) -> usize {
+        values.iter().enumerate().filter(|(i, value)| {
+            let now = start + Duration::from_secs(*i as u64);
+            match value.strip_prefix('>') {
+                Some(own) => detector.record(own, true, now),
+                None => detector.record(value, false, now),
+            }
+        }).count()
+    }
+
+    #[test]
+    fn ordinary_copies_never_warn() {
+        let mut detector = ConflictDetector::default();
+        assert_eq!(warnings(&mut detector, Instant::now(), &["a", "b", "c", "d", "e", "f"]), 0);
+    }
+
+    #[test]
+    fn flip_flopping_warns_once_threshold_is_reached() {
+        let mut detector = ConflictDetector::default();
+        assert_eq!(warnings(&mut detector, Instant::now(), &["a", "b", "a", "b"]), 0);
+
+        let mut detector = ConflictDetector::default();
+        assert_eq!(warnings(&mut detector, Instant::now(), &["a", "b", "a", "b", "a"]), 1);
+    }
+
+    #[test]
+    fn outside_change_back_to_own_value_is_suspicious() {
+        let mut detector = ConflictDetector::default();
+        let flips = [">mine", "theirs", "mine", ">mine", "theirs", "mine", ">mine", "theirs", "mine"];
+        assert!(warnings(&mut detector, Instant::now(), &flips) >= 1);
+    }
+
+    #[test]
+    fn repeats_outside_the_window_are_not_suspicious() {
+        let mut detector = ConflictDetector::default();
+        let start = Instant::now();
+        for i in 0..6 {
+            let value = if i % 2 == 0 { "a" } else { "b" };
+            assert!(!detector.record(value, false, start + CONFLICT_WINDOW * (i + 1) * 2));
+        }
+    }
+
+    #[test]
+    fn warnings_are_rate_limited() {
+        let mut detector = ConflictDetector::default();
+        let flips: Vec<&str> = ["a", "b"].iter().cycle().take(40).copied().collect();
+        assert_eq!(warnings(&mut detector, Instant::now(), &flips), 1);
+    }
+}
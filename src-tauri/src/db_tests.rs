@@ -0,0 +1,50 @@
+// Runs the database queries against a scratch database
+use super::*;
+
+fn scratch_database(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("cliped-db-tests-{}-{}.db", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    init_database_at(&path).unwrap()
+}
+
+fn item(content_type: &str) -> ClipboardItem {
+    ClipboardItem {
+        id: format!("{}-item", content_type),
+        content: format!("{} content", content_type),
+        timestamp: get_current_timestamp().to_string(),
+        device: "local".to_string(),
+        content_type: content_type.to_string(),
+        file_path: None,
+        file_size: None,
+        file_name: None,
+        use_count: 0,
+        content_size: 0,
+        mime_type: None,
+        metadata: HashMap::new(),
+        expires_at: None,
+        no_sync: false,
+    }
+}
+
+#[test]
+fn each_content_type_is_in_exactly_one_view() {
+    let db_path = scratch_database("views");
+    let types = ["text", "image", "html", "file"];
+    for content_type in types {
+        save_clipboard_item_to_db(&db_path, &item(content_type)).unwrap();
+    }
+
+    let history = load_clipboard_history_paginated(&db_path, 0, 100).unwrap();
+    let files = get_clipboard_files_paginated_from_db(&db_path, 0, 100).unwrap();
+    assert_eq!(get_clipboard_history_count_from_db(&db_path).unwrap() as usize, history.len());
+    assert_eq!(get_clipboard_files_count_from_db(&db_path).unwrap() as usize, files.len());
+
+    for content_type in types {
+        let in_history = history.iter().any(|i| i.content_type == content_type);
+        let in_files = files.iter().any(|i| i.content_type == content_type);
+        assert!(in_history != in_files, "{} is in {} views", content_type, if in_history { "both" } else { "neither" });
+        assert_eq!(in_history, is_history_visible(content_type));
+    }
+    assert!(files.iter().all(|i| i.content_type == "file"));
+    let _ = std::fs::remove_file(&db_path);
+}
@@ -0,0 +1,54 @@
+// Checks that received file names and data directory moves stay where they belong
+use super::*;
+use std::path::Path;
+
+fn downloads_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join("cliped-file-tests")
+}
+
+#[test]
+fn traversal_names_stay_in_downloads_directory() {
+    let dir = downloads_dir();
+    for name in ["../../etc/passwd", "..\\..\\Windows\\win.ini", "a/../../b.txt", "nested/dir/file.txt", "..", "./.hidden"] {
+        if let Ok(path) = received_file_path(&dir, name) {
+            assert_eq!(path.parent(), Some(dir.as_path()), "{} escaped to {}", name, path.display());
+        }
+    }
+    assert_eq!(received_file_path(&dir, "../../etc/passwd").unwrap(), dir.join("_.._etc_passwd"));
+}
+
+#[test]
+fn absolute_names_are_rejected() {
+    let dir = downloads_dir();
+    assert!(received_file_path(&dir, "/etc/passwd").is_err());
+    assert!(received_file_path(&dir, "\\\\server\\share\\file").is_err());
+    assert!(received_file_path(&dir, "C:\\Windows\\system32\\evil.dll").is_err());
+}
+
+#[test]
+fn names_with_nothing_left_after_sanitizing_are_rejected() {
+    let dir = downloads_dir();
+    assert!(received_file_path(&dir, "..").is_err());
+    assert!(received_file_path(&dir, " . ").is_err());
+    assert!(received_file_path(&dir, "").is_err());
+}
+
+#[test]
+fn ordinary_names_are_kept() {
+    let dir = downloads_dir();
+    assert_eq!(received_file_path(&dir, "report final.pdf").unwrap(), dir.join("report final.pdf"));
+    assert_eq!(sanitize_file_name("résumé.txt").unwrap(), "résumé.txt");
+    assert!(Path::new(&sanitize_file_name("a\u{0}b").unwrap()).file_name().is_some());
+}
+
+#[test]
+fn data_directory_cannot_move_inside_itself() {
+    let old_dir = std::env::temp_dir().join("cliped-move-tests");
+    let nested = old_dir.join("nested");
+    std::fs::create_dir_all(&nested).unwrap();
+    assert!(is_within_directory(&nested, &old_dir));
+    assert!(is_within_directory(&old_dir.join("nested/.."), &old_dir));
+    assert!(!is_within_directory(&old_dir, &nested));
+    assert!(move_data_directory(&old_dir.join("clipboard.db"), &old_dir, &nested).is_err());
+    let _ = std::fs::remove_dir_all(&old_dir);
+}
@@ -3,18 +3,23 @@
 #[cfg(feature = "clipboard")]
 use arboard::Clipboard;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{SystemTime, UNIX_EPOCH};
-use tauri::{AppHandle, Emitter, Manager, State};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
 use tokio::net::UdpSocket;
 use tokio::time::{sleep, Duration};
-use local_ip_address::local_ip;
+use local_ip_address::{list_afinet_netifas, local_ip};
 use rusqlite::Connection;
 use directories::ProjectDirs;
 use rfd::FileDialog;
 use base64::{Engine as _, engine::general_purpose};
 
+mod capture;
+use capture::{apply_transform_rules, compile_patterns, compile_transform_rules, is_clipboard_noise, is_trivial_content, matches_any_pattern, CompiledTransformRule, ConflictDetector, TransformRule};
+mod sync;
+use sync::{dedup_history, is_sync_allowed, is_sync_target, sync_file_to_connected_devices, sync_to_connected_devices};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Device {
     id: u32,
@@ -24,6 +29,16 @@ struct Device {
     status: DeviceStatus,
     sync_mode: SyncMode,
     last_seen: u64,
+    #[serde(default)]
+    trusted: bool, // Allowed to sync when explicit trust is required
+    #[serde(default)]
+    last_synced: Option<u64>, // When a clipboard item was last sent to or accepted from this device
+    #[serde(default)]
+    display_name: Option<String>, // Friendly name set by the device's user; `name` stays the stable identifier
+    #[serde(default)]
+    disabled: bool, // Muted by the user: stays paired and connected but nothing syncs either way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    relay_key: Option<String>, // Secret agreed at pairing that signs messages relayed to and from this device
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Copy)]
@@ -47,11 +62,15 @@ struct NetworkMessage {
     device_id: u32,
     device_name: String,
     data: Option<String>,
+    // User-chosen name shown instead of device_name; sent with discovery and pairing messages
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    display_name: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 enum MessageType {
     Discovery,        // Device announcing presence
+    DiscoveryResponse, // Reply to a Discovery probe, echoing its scan id
     ConnectionRequest, // Request to connect
     ConnectionAccept,  // Accept connection
     ConnectionDeny,    // Deny connection
@@ -61,6 +80,36 @@ enum MessageType {
     FileTransferChunk, // File data chunk
     FileTransferComplete, // File transfer completion
     Heartbeat,        // Keep connection alive
+    HistoryRequest,   // Ask a peer to send its entire history
+    HistoryResponse,  // One item of a peer's history, sent in reply to a HistoryRequest
+    FileTransferCancel, // Abort a file transfer in either direction
+    Echo,             // Diagnostic probe, answered with the same nonce
+    IdentityCheck,    // Challenge a paired device to prove it holds the pairing's relay key
+    KeyRotation,      // Replace the pairing's relay key, see rotate_device_key
+}
+
+// Payload of an Echo message. Requests leave `paired` unset; the reply reports
+// whether the responder has the requester paired and allowed to sync.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct EchoPayload {
+    nonce: String,
+    paired: Option<bool>,
+}
+
+// Payload of an IdentityCheck message. Challenges leave `mac` unset; the reply
+// signs the nonce with the relay key shared at pairing, see identity_mac.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IdentityPayload {
+    nonce: String,
+    mac: Option<String>,
+}
+
+// Payload of a KeyRotation message. Requests carry the new key signed with the
+// current one; the reply leaves `key` unset and signs with the new key to confirm it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct KeyRotationPayload {
+    key: Option<String>,
+    mac: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,9 +122,263 @@ struct ClipboardItem {
     file_path: Option<String>,
     file_size: Option<u64>,
     file_name: Option<String>,
+    #[serde(default)]
+    use_count: u32,
+    #[serde(default)]
+    content_size: u64, // Bytes of text, or the file size for file items
+    #[serde(default)]
+    mime_type: Option<String>, // Sniffed from file content, falling back to the extension
+    #[serde(default)]
+    metadata: HashMap<String, String>, // Free-form annotations from features like source app or OCR
+    #[serde(default)]
+    expires_at: Option<u64>, // Unix time after which the expiry sweep deletes the item
+    #[serde(default)]
+    no_sync: bool, // Kept on this device: never sent to peers by live sync, total sync or history requests
+}
+
+// A folder items can be filed in. Collections nest through parent_id, and an
+// item belongs to at most one collection at a time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Collection {
+    id: i64,
+    name: String,
+    parent_id: Option<i64>, // None for top-level collections
+    timestamp: String,
+    item_count: u32, // Items filed directly in this collection, not in its children
+}
+
+// Something that happened to a device's connection, as kept in the audit log
+#[derive(Debug, Clone, Copy)]
+enum ConnectionEvent {
+    RequestReceived,
+    Accepted,       // We accepted the device's request
+    AcceptedByPeer, // The device accepted our request
+    Denied,         // We refused the device, by hand or because of the device limit
+    DeniedByPeer,
+    Removed,        // We unpaired the device
+    RemovedByPeer,
+    Expired,        // The request went unanswered, or was dropped for newer ones
+}
+
+impl ConnectionEvent {
+    fn as_str(self) -> &'static str {
+        match self {
+            ConnectionEvent::RequestReceived => "request_received",
+            ConnectionEvent::Accepted => "accepted",
+            ConnectionEvent::AcceptedByPeer => "accepted_by_peer",
+            ConnectionEvent::Denied => "denied",
+            ConnectionEvent::DeniedByPeer => "denied_by_peer",
+            ConnectionEvent::Removed => "removed",
+            ConnectionEvent::RemovedByPeer => "removed_by_peer",
+            ConnectionEvent::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ConnectionAuditEntry {
+    id: i64,
+    timestamp: u64,
+    event: String, // One of the ConnectionEvent names, e.g. "accepted_by_peer"
+    device_id: u32,
+    device_name: String,
+    ip: String,
+    detail: Option<String>, // Why, when there is a reason, e.g. the device limit
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Snippet {
+    name: String,
+    template: String,
+    timestamp: String,
+    placeholders: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum TransferDirection {
+    Send,
+    Receive,
+}
+
+// Progress of one file transfer to or from one device, reported to the frontend
+#[derive(Debug, Clone, Serialize)]
+struct FileTransferProgress {
+    file_id: String,
+    file_name: String,
+    device_id: u32,
+    direction: TransferDirection,
+    bytes_transferred: u64,
+    total_bytes: u64,
+}
+
+// Payload of a FileTransfer message announcing a chunked transfer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileTransferHeader {
+    item: ClipboardItem,
+    total_size: u64,
+    chunk_count: usize,
+}
+
+// Payload of a FileTransferChunk message
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileChunk {
+    file_id: String,
+    index: usize,
+    data: String, // Base64-encoded chunk bytes
+}
+
+// Payload of FileTransferComplete and FileTransferCancel messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileTransferEnd {
+    file_id: String,
+}
+
+// Items merged from an incoming history stream whose event hasn't been emitted yet
+struct HistoryBatch {
+    merged: usize,
+    last_item_at: tokio::time::Instant,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct TrafficCounters {
+    messages_sent: u64,
+    bytes_sent: u64,
+    send_failures: u64, // Sends that errored or only wrote part of the datagram
+    messages_received: u64,
+    bytes_received: u64,
+}
+
+// Per-peer counters stop growing past this many addresses until the next prune,
+// so datagrams from arbitrary sources can't grow the map without bound
+const MAX_TRACKED_PEERS: usize = 256;
+
+// Datagram counters since launch, broken down by message type and by peer address
+struct NetworkStats {
+    started_at: std::time::Instant,
+    counters: Mutex<NetworkCounters>,
+}
+
+#[derive(Default)]
+struct NetworkCounters {
+    total: TrafficCounters,
+    by_message_type: HashMap<String, TrafficCounters>,
+    by_peer: HashMap<String, TrafficCounters>,
+    last_discovery_scan: Option<DiscoveryScanStats>,
+}
+
+// Replies seen by the most recent discover_devices scan
+#[derive(Debug, Clone, Default, Serialize)]
+struct DiscoveryScanStats {
+    unique_responses: u32,
+    duplicate_responses: u32, // Further replies from a device already seen this scan
+}
+
+impl Default for NetworkStats {
+    fn default() -> Self {
+        NetworkStats {
+            started_at: std::time::Instant::now(),
+            counters: Mutex::new(NetworkCounters::default()),
+        }
+    }
+}
+
+impl NetworkCounters {
+    fn peer(&mut self, peer: &str) -> Option<&mut TrafficCounters> {
+        if self.by_peer.len() >= MAX_TRACKED_PEERS && !self.by_peer.contains_key(peer) {
+            return None;
+        }
+        Some(self.by_peer.entry(peer.to_string()).or_default())
+    }
+}
+
+impl NetworkStats {
+    // `peer` is None for scan probes, which would otherwise add an entry per swept address
+    fn record_sent(&self, msg_type: &MessageType, peer: Option<&str>, bytes: usize) {
+        let mut counters = self.counters.lock_or_recover();
+        let count = |c: &mut TrafficCounters| {
+            c.messages_sent += 1;
+            c.bytes_sent += bytes as u64;
+        };
+        count(&mut counters.total);
+        count(counters.by_message_type.entry(format!("{:?}", msg_type)).or_default());
+        if let Some(peer) = peer.and_then(|peer| counters.peer(peer)) {
+            count(peer);
+        }
+    }
+
+    fn record_send_failure(&self, msg_type: &MessageType, peer: Option<&str>) {
+        let mut counters = self.counters.lock_or_recover();
+        counters.total.send_failures += 1;
+        counters.by_message_type.entry(format!("{:?}", msg_type)).or_default().send_failures += 1;
+        if let Some(peer) = peer.and_then(|peer| counters.peer(peer)) {
+            peer.send_failures += 1;
+        }
+    }
+
+    fn record_received(&self, msg_type: &MessageType, peer: &str, bytes: usize) {
+        let mut counters = self.counters.lock_or_recover();
+        let count = |c: &mut TrafficCounters| {
+            c.messages_received += 1;
+            c.bytes_received += bytes as u64;
+        };
+        count(&mut counters.total);
+        count(counters.by_message_type.entry(format!("{:?}", msg_type)).or_default());
+        if let Some(peer) = counters.peer(peer) {
+            count(peer);
+        }
+    }
+
+    // Drops per-peer counters for addresses that no longer belong to a known device
+    fn prune_peers(&self, known_ips: &HashSet<String>) {
+        self.counters.lock_or_recover().by_peer.retain(|ip, _| known_ips.contains(ip));
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct PeerTraffic {
+    ip: String,
+    device_id: Option<u32>,
+    device_name: Option<String>,
+    traffic: TrafficCounters,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct NetworkStatsSnapshot {
+    uptime_secs: u64,
+    total: TrafficCounters,
+    by_message_type: HashMap<String, TrafficCounters>,
+    by_device: Vec<PeerTraffic>,
+    last_discovery_scan: Option<DiscoveryScanStats>,
+}
+
+// Chunks received so far for an incoming file, keyed by file id in AppState
+struct IncomingFileTransfer {
+    item: ClipboardItem,
+    device_id: u32,
+    total_size: u64,
+    chunks: Vec<Option<Vec<u8>>>,
+    last_chunk_at: std::time::Instant, // When the header or the latest chunk arrived
+}
+
+// Poisoned locks are recovered instead of propagated, so a panic in one task
+// can't cascade into every command that touches the same state
+trait LockExt<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> LockExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|poisoned| {
+            eprintln!("Recovering from a poisoned lock");
+            poisoned.into_inner()
+        })
+    }
 }
 
 type ClipboardState = Arc<Mutex<Vec<ClipboardItem>>>;
+type TransferKey = (String, u32); // (file id, device id)
+type CancelledTransfer = (String, Option<u32>); // (file id, device id), None cancels every device
+type RelayOutbox = tokio::sync::mpsc::UnboundedSender<RelayFrame>;
 
 #[derive(Default)]
 struct AppState {
@@ -83,63 +386,461 @@ struct AppState {
     clipboard_history: ClipboardState,
     last_clipboard_content: Arc<Mutex<String>>,
     enabled: Arc<Mutex<bool>>,
+    monitor_alive: Arc<Mutex<bool>>, // Set while the monitor_clipboard loop is actually running
     local_device: Arc<Mutex<Option<Device>>>,
     db_path: Arc<Mutex<Option<String>>>,
     pending_connections: Arc<Mutex<Vec<Device>>>,
     discovered_devices: Arc<Mutex<Vec<Device>>>,
     ignore_next_clipboard_change: Arc<Mutex<bool>>, // Flag to ignore clipboard changes from sync
+    settings: Arc<Mutex<Settings>>,
+    active_transfers: Arc<Mutex<HashMap<TransferKey, FileTransferProgress>>>,
+    incoming_transfers: Arc<Mutex<HashMap<String, IncomingFileTransfer>>>,
+    cancelled_transfers: Arc<Mutex<HashSet<CancelledTransfer>>>, // Outgoing transfers the send loop should stop
+    history_batch: Arc<Mutex<Option<HistoryBatch>>>, // Set while a peer's history is streaming in
+    ready: Arc<Mutex<bool>>, // Set once setup has loaded the database and local device
+    ready_notify: Arc<tokio::sync::Notify>,
+    network_stats: Arc<NetworkStats>,
+    relay_outbox: Arc<Mutex<Option<RelayOutbox>>>, // Set while the relay connection is up
+    relay_task: Arc<Mutex<Option<tauri::async_runtime::JoinHandle<()>>>>,
+    discovery_generation: Arc<Mutex<u64>>, // Bumped to stop the running discovery scan
+    discovery_in_progress: Arc<Mutex<bool>>, // Only one discover_devices scan runs at a time
+    discovery_done: Arc<tokio::sync::Notify>,
+    sync_paused_untrusted: Arc<Mutex<bool>>, // Set while on a network outside trusted_networks
+    local_ips: Arc<Mutex<HashSet<String>>>, // Every address on this machine's interfaces, collected at startup
+    server_addr: Arc<Mutex<Option<std::net::SocketAddr>>>, // Where the UDP server is listening; None until it has bound
+    total_sync_sent_at: Arc<Mutex<HashMap<u32, std::time::Instant>>>, // When each device was last sent our whole history
+    transform_rules: Arc<Mutex<Vec<CompiledTransformRule>>>, // settings.transform_rules, compiled whenever they change
+    no_sync_patterns: Arc<Mutex<Vec<regex::Regex>>>, // settings.no_sync_patterns, compiled whenever they change
+    ip_change_checks: Arc<Mutex<HashSet<u32>>>, // Devices whose new address is being verified right now
+    unconfirmed_ip_changes: Arc<Mutex<HashMap<u32, String>>>, // New addresses of keyless pairings, waiting for the user
+    retired_relay_keys: Arc<Mutex<HashMap<u32, RetiredRelayKey>>>, // Each device's last rotated-out relay key
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+struct Settings {
+    capture_min_length: usize, // Shorter clipboard text is not added to history
+    capture_max_length: usize, // Longer clipboard text is ignored entirely
+    capture_debounce_ms: u64,  // How long clipboard content must stay unchanged before capture
+    inline_text_files: bool,   // Also add small text files' contents to the text history
+    trim_captured_whitespace: bool, // Strip leading/trailing whitespace before storing
+    clear_devices_on_startup: bool, // Forget paired devices on launch instead of restoring them
+    require_explicit_trust: bool, // Only devices marked trusted may sync, even when connected
+    pending_request_expiry_secs: u64, // Unanswered connection requests are dropped after this long
+    item_attribution: ItemAttribution, // Which name new items record as their source device
+    max_item_size: u64, // Text items larger than this many bytes are refused
+    auto_paste: bool, // Paste into the focused app after activating an item
+    dedup_window: usize, // How many recent items a new copy is deduplicated against; 0 keeps every copy
+    capture_copied_files: bool, // Store files copied in a file manager as file items instead of their URIs as text
+    discovery_timeout_ms: u64, // How long each discovery pass listens for replies
+    discovery_retries: u32, // Number of sweep-and-listen passes per discovery scan
+    network_interface: Option<String>, // Local IPv4 address outgoing sockets bind to; None lets the OS choose
+    relay_url: Option<String>, // WebSocket relay used to reach paired devices off the LAN
+    sync_latest_on_connect: bool, // Send the newest history item to a device when it (re)connects
+    close_to_tray: bool, // Closing the window hides it and keeps syncing in the background
+    launch_at_startup: bool, // Start the app when the user logs in
+    notify_content_types: Vec<String>, // Content types of synced items that raise a sync notification
+    quiet_hours: Option<QuietHours>, // Local time window during which no sync notifications fire
+    trusted_networks: Vec<String>, // Wi-Fi SSIDs or gateway MACs sync is allowed on; empty allows every network
+    max_devices: u32, // Most peers connected at once; further connections are denied
+    thumbnail_max_dimension: u32, // Longest side, in pixels, of image previews
+    thumbnail_quality: u8, // JPEG quality (1-100) of image previews
+    theme: Theme, // Light, dark, or follow the system appearance
+    read_only: bool, // History and devices can't be changed from the UI; capture and sync still add items
+    trivial_content_min_length: Option<usize>, // Single tokens shorter than this (except URLs/emails) stay out of history; None disables
+    retention_limits: HashMap<String, u32>, // Most items kept per content type; types not listed are unlimited
+    monitored_formats: Vec<ClipboardFormat>, // Clipboard formats the monitor reads; others are never captured
+    no_sync_patterns: Vec<String>, // Regexes; captured text matching any of them is flagged no_sync
+    transform_rules: Vec<TransformRule>, // Applied in order to captured text before it is deduplicated and stored
+    keep_original_content: bool, // Keep the untransformed text in the item's metadata when a rule changed it
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ClipboardFormat {
+    Text,
+    Html,  // Rich text; captured as its plain text with the HTML kept in metadata
+    Image,
+    Files, // A file manager's copied files, stored as file items
+}
+
+// Start and end as "HH:MM" local time; a window may wrap past midnight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct QuietHours {
+    start: String,
+    end: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+enum ItemAttribution {
+    Hostname,   // The machine's hostname, e.g. "Johns-MacBook-Pro.local"
+    DeviceName, // The device name configured in the app
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Theme {
+    Light,
+    Dark,
+    System, // Follow the OS appearance
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            capture_min_length: 1,
+            capture_max_length: usize::MAX,
+            capture_debounce_ms: 300,
+            inline_text_files: false,
+            trim_captured_whitespace: false,
+            clear_devices_on_startup: true,
+            require_explicit_trust: false,
+            pending_request_expiry_secs: 120,
+            item_attribution: ItemAttribution::Hostname,
+            max_item_size: u64::MAX,
+            auto_paste: false,
+            dedup_window: usize::MAX,
+            capture_copied_files: false,
+            discovery_timeout_ms: 3000,
+            discovery_retries: 1,
+            network_interface: None,
+            relay_url: None,
+            sync_latest_on_connect: false,
+            close_to_tray: false,
+            launch_at_startup: false,
+            notify_content_types: vec!["text".to_string(), "image".to_string(), "file".to_string()],
+            quiet_hours: None,
+            trusted_networks: Vec::new(),
+            max_devices: u32::MAX,
+            thumbnail_max_dimension: 256,
+            thumbnail_quality: 80,
+            theme: Theme::System,
+            read_only: false,
+            trivial_content_min_length: None,
+            retention_limits: HashMap::new(),
+            monitored_formats: vec![ClipboardFormat::Text, ClipboardFormat::Image],
+            no_sync_patterns: Vec::new(),
+            transform_rules: Vec::new(),
+            keep_original_content: false,
+        }
+    }
+}
+
+// How long wait_for_ready waits for setup before giving up
+const READY_TIMEOUT_SECS: u64 = 10;
+
+// Delay before reconnecting to the relay, doubled after each failure up to the max
+const RELAY_RECONNECT_SECS: u64 = 2;
+const RELAY_RECONNECT_MAX_SECS: u64 = 60;
+
+// Heartbeat payload asking the receiver to reply with its own heartbeat
+const HEARTBEAT_PING: &str = "ping";
+// A heartbeat younger than this is trusted as proof that a device is online
+const HEARTBEAT_FRESHNESS_SECS: u64 = 30;
+
+// Most connection requests kept waiting at once; the oldest is dropped to make room
+const MAX_PENDING_CONNECTIONS: usize = 32;
+// How often stale connection requests are swept
+const PENDING_SWEEP_INTERVAL_SECS: u64 = 10;
+// How often items past their expires_at or their type's retention limit are deleted
+const ITEM_EXPIRY_SWEEP_INTERVAL_SECS: u64 = 15;
+// How often the current network is checked against trusted_networks
+const TRUSTED_NETWORK_CHECK_SECS: u64 = 20;
+
+// A history stream counts as finished once no item has arrived for this long
+const HISTORY_BATCH_QUIET_MS: u64 = 500;
+
+// Argument factory_reset must be called with, so it can't be triggered by accident
+const FACTORY_RESET_CONFIRMATION: &str = "ERASE ALL DATA";
+
+// Raw bytes per FileTransferChunk; base64 plus the JSON envelope must stay under the UDP datagram limit
+const FILE_CHUNK_SIZE: usize = 32 * 1024;
+// Largest datagram the UDP server will read
+const MAX_DATAGRAM_SIZE: usize = 65536;
+
+// Current schema version, stored in the database via `PRAGMA user_version`
+const SCHEMA_VERSION: i32 = 7;
+
 // Utility functions
 fn init_database() -> Result<String, String> {
-    if let Some(proj_dirs) = ProjectDirs::from("com", "cliped", "cliped") {
-        let data_dir = proj_dirs.data_dir();
-        std::fs::create_dir_all(data_dir).map_err(|e| e.to_string())?;
+    init_database_at(&database_path()?)
+}
+
+// Creates or migrates the database at `db_path` and returns the path as a string
+fn init_database_at(db_path: &std::path::Path) -> Result<String, String> {
+    if let Some(parent) = db_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
 
-        let db_path = data_dir.join("clipboard.db");
-        let conn = Connection::open(&db_path).map_err(|e| e.to_string())?;
+    let db_existed = db_path.exists();
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
 
-        // Enable WAL mode for better concurrency (use query since PRAGMA returns results)
-        let _ = conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()));
+    // Enable WAL mode for better concurrency (use query since PRAGMA returns results)
+    let _ = conn.query_row("PRAGMA journal_mode=WAL", [], |_| Ok(()));
 
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS clipboard_items (
-                id TEXT PRIMARY KEY,
-                content TEXT NOT NULL,
-                timestamp TEXT NOT NULL,
-                device TEXT NOT NULL,
-                content_type TEXT NOT NULL,
-                file_path TEXT,
-                file_size INTEGER,
-                file_name TEXT
-            )",
-            [],
-        ).map_err(|e| e.to_string())?;
-        
-        // Add new columns if they don't exist (for existing databases)
-        let _ = conn.execute(
-            "ALTER TABLE clipboard_items ADD COLUMN file_path TEXT",
-            [],
-        );
-        let _ = conn.execute(
-            "ALTER TABLE clipboard_items ADD COLUMN file_size INTEGER",
-            [],
-        );
-        let _ = conn.execute(
-            "ALTER TABLE clipboard_items ADD COLUMN file_name TEXT",
-            [],
-        );
-        
-        Ok(db_path.to_string_lossy().to_string())
-    } else {
-        Err("Failed to get project directories".to_string())
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS clipboard_items (
+            id TEXT PRIMARY KEY,
+            content TEXT NOT NULL,
+            timestamp TEXT NOT NULL,
+            device TEXT NOT NULL,
+            content_type TEXT NOT NULL,
+            file_path TEXT,
+            file_size INTEGER,
+            file_name TEXT,
+            use_count INTEGER NOT NULL DEFAULT 0,
+            content_size INTEGER NOT NULL DEFAULT 0,
+            mime_type TEXT,
+            metadata TEXT,
+            expires_at INTEGER,
+            no_sync INTEGER NOT NULL DEFAULT 0
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS devices (
+            id INTEGER PRIMARY KEY,
+            data TEXT NOT NULL
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS snippets (
+            name TEXT PRIMARY KEY,
+            template TEXT NOT NULL,
+            timestamp TEXT NOT NULL
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS collections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL,
+            parent_id INTEGER,
+            timestamp TEXT NOT NULL
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    // Keyed by item so an item can only be in one collection
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS collection_items (
+            item_id TEXT PRIMARY KEY,
+            collection_id INTEGER NOT NULL
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS connection_audit (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL,
+            event TEXT NOT NULL,
+            device_id INTEGER NOT NULL,
+            device_name TEXT NOT NULL,
+            ip TEXT NOT NULL,
+            detail TEXT
+        )",
+        [],
+    ).map_err(|e| e.to_string())?;
+
+    let current_version: i32 = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+
+    if current_version < SCHEMA_VERSION {
+        // Back up existing databases before touching their data
+        let backup_path = if db_existed {
+            Some(backup_database(&conn, db_path, current_version)?)
+        } else {
+            None
+        };
+
+        if let Err(e) = run_migrations(&conn, current_version) {
+            drop(conn);
+            if let Some(ref backup_path) = backup_path {
+                restore_database_file(backup_path, db_path)?;
+            }
+            return Err(format!(
+                "Database migration from version {} to {} failed: {}",
+                current_version, SCHEMA_VERSION, e
+            ));
+        }
+
+        println!("Database migrated from version {} to {}", current_version, SCHEMA_VERSION);
+
+        // Only prune older backups once the migration has succeeded
+        if let Some(ref backup_path) = backup_path {
+            remove_old_database_backups(db_path, backup_path);
+        }
+    }
+
+    Ok(db_path.to_string_lossy().to_string())
+}
+
+fn run_migrations(conn: &Connection, from_version: i32) -> Result<(), String> {
+    conn.execute_batch("BEGIN").map_err(|e| e.to_string())?;
+
+    let result = (|| {
+        if from_version < 1 {
+            // Add file columns for databases created before file support
+            add_column_if_missing(conn, "clipboard_items", "file_path", "TEXT")?;
+            add_column_if_missing(conn, "clipboard_items", "file_size", "INTEGER")?;
+            add_column_if_missing(conn, "clipboard_items", "file_name", "TEXT")?;
+        }
+
+        if from_version < 2 {
+            add_column_if_missing(conn, "clipboard_items", "use_count", "INTEGER NOT NULL DEFAULT 0")?;
+        }
+
+        if from_version < 3 {
+            add_column_if_missing(conn, "clipboard_items", "content_size", "INTEGER NOT NULL DEFAULT 0")?;
+            conn.execute(
+                "UPDATE clipboard_items SET content_size = CASE
+                    WHEN content_type = 'file' THEN COALESCE(file_size, 0)
+                    ELSE length(CAST(content AS BLOB))
+                 END",
+                [],
+            ).map_err(|e| e.to_string())?;
+        }
+
+        if from_version < 4 {
+            // Existing items are left without a type; previews fall back to the extension
+            add_column_if_missing(conn, "clipboard_items", "mime_type", "TEXT")?;
+        }
+
+        if from_version < 5 {
+            add_column_if_missing(conn, "clipboard_items", "metadata", "TEXT")?;
+        }
+
+        if from_version < 6 {
+            add_column_if_missing(conn, "clipboard_items", "expires_at", "INTEGER")?;
+        }
+
+        if from_version < 7 {
+            add_column_if_missing(conn, "clipboard_items", "no_sync", "INTEGER NOT NULL DEFAULT 0")?;
+        }
+
+        conn.execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION))
+            .map_err(|e| e.to_string())
+    })();
+
+    match result {
+        Ok(()) => conn.execute_batch("COMMIT").map_err(|e| e.to_string()),
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK");
+            Err(e)
+        }
+    }
+}
+
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), String> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|e| e.to_string())?
+        .filter_map(|name| name.ok())
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn backup_database(conn: &Connection, db_path: &std::path::Path, version: i32) -> Result<std::path::PathBuf, String> {
+    // Flush the WAL so the main database file is complete before copying it
+    let _ = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()));
+
+    let backup_path = db_path.with_file_name(format!("clipboard.db.bak-{}", version));
+    std::fs::copy(db_path, &backup_path)
+        .map_err(|e| format!("Failed to back up database before migration: {}", e))?;
+
+    println!("Database backed up to: {}", backup_path.display());
+    Ok(backup_path)
+}
+
+fn restore_database_file(backup_path: &std::path::Path, db_path: &std::path::Path) -> Result<(), String> {
+    std::fs::copy(backup_path, db_path)
+        .map_err(|e| format!("Failed to restore database from backup: {}", e))?;
+
+    // Stale WAL/SHM files would be replayed on top of the restored database
+    for suffix in ["-wal", "-shm"] {
+        let mut sidecar = db_path.as_os_str().to_owned();
+        sidecar.push(suffix);
+        let _ = std::fs::remove_file(std::path::PathBuf::from(sidecar));
+    }
+
+    println!("Database restored from: {}", backup_path.display());
+    Ok(())
+}
+
+fn list_database_backups(db_path: &std::path::Path) -> Vec<(i32, std::path::PathBuf)> {
+    let mut backups = Vec::new();
+
+    if let Some(dir) = db_path.parent() {
+        if let Ok(entries) = std::fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let Some(version) = name.strip_prefix("clipboard.db.bak-") {
+                    if let Ok(version) = version.parse::<i32>() {
+                        backups.push((version, entry.path()));
+                    }
+                }
+            }
+        }
+    }
+
+    // Newest backup first
+    backups.sort_by_key(|b| std::cmp::Reverse(b.0));
+    backups
+}
+
+fn remove_old_database_backups(db_path: &std::path::Path, keep: &std::path::Path) {
+    for (_, path) in list_database_backups(db_path) {
+        if path != keep {
+            if let Err(e) = std::fs::remove_file(&path) {
+                eprintln!("Failed to remove old database backup {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn mark_ready(state: &AppState) {
+    *state.ready.lock_or_recover() = true;
+    state.ready_notify.notify_waiters();
+}
+
+// Resolves once setup has initialized the database and local device
+async fn wait_until_ready(state: &AppState) {
+    loop {
+        // Created before the check so a notification in between isn't missed
+        let notified = state.ready_notify.notified();
+        if *state.ready.lock_or_recover() {
+            return;
+        }
+        notified.await;
     }
 }
 
-fn generate_device_info() -> Device {
+fn generate_device_info(interface: Option<&str>) -> Device {
     let id = generate_id();
     let device_name = format!("Device-{}", generate_random_suffix());
-    let ip = get_local_ip();
+    let ip = get_local_ip(interface);
     
     Device {
         id,
@@ -149,6 +850,11 @@ fn generate_device_info() -> Device {
         status: DeviceStatus::Connected,
         sync_mode: SyncMode::Disabled,
         last_seen: get_current_timestamp(),
+        trusted: false,
+        last_synced: None,
+        display_name: None,
+        disabled: false,
+        relay_key: None,
     }
 }
 
@@ -177,62 +883,313 @@ fn generate_random_suffix() -> String {
     format!("{:04}", rand::random::<u16>() % 10000)
 }
 
-fn get_local_ip() -> String {
+// The chosen interface address if one is set, otherwise the default route's
+fn get_local_ip(interface: Option<&str>) -> String {
+    if let Some(interface) = interface {
+        return interface.to_string();
+    }
     local_ip().map(|ip| ip.to_string()).unwrap_or_else(|_| "127.0.0.1".to_string())
 }
 
-fn load_clipboard_history_from_db(db_path: &str) -> Result<Vec<ClipboardItem>, String> {
-    load_clipboard_history_paginated(db_path, 0, 50)
+// IPv4 addresses of every usable interface, so discovery reaches each attached
+// network (Ethernet, Wi-Fi, VPN) rather than only the default route's
+// All addresses assigned to local interfaces, including loopback and link-local,
+// for recognizing datagrams this machine sent to itself
+fn all_local_ips() -> HashSet<String> {
+    list_afinet_netifas()
+        .map(|interfaces| interfaces.into_iter().map(|(_, ip)| ip.to_string()).collect())
+        .unwrap_or_default()
 }
 
-fn load_clipboard_history_paginated(db_path: &str, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
-    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
-    
-    let mut stmt = conn.prepare(
-        "SELECT id, content, timestamp, device, content_type, file_path, file_size, file_name FROM clipboard_items WHERE content_type != 'file' ORDER BY timestamp DESC LIMIT ?1 OFFSET ?2"
-    ).map_err(|e| e.to_string())?;
-    
-    let clipboard_iter = stmt.query_map([limit, offset], |row| {
-        Ok(ClipboardItem {
-            id: row.get(0)?,
-            content: row.get(1)?,
-            timestamp: row.get(2)?,
-            device: row.get(3)?,
-            content_type: row.get(4)?,
-            file_path: row.get(5).ok(),
-            file_size: row.get(6).ok(),
-            file_name: row.get(7).ok(),
+fn get_local_ipv4_addresses() -> Vec<std::net::Ipv4Addr> {
+    let mut addresses: Vec<std::net::Ipv4Addr> = list_afinet_netifas()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .filter_map(|(_, ip)| match ip {
+                    std::net::IpAddr::V4(ip) if !ip.is_loopback() && !ip.is_link_local() => Some(ip),
+                    _ => None,
+                })
+                .collect()
         })
-    }).map_err(|e| e.to_string())?;
-    
-    let mut items = Vec::new();
-    for item in clipboard_iter {
-        items.push(item.map_err(|e| e.to_string())?);
+        .unwrap_or_default();
+
+    if addresses.is_empty() {
+        if let Ok(std::net::IpAddr::V4(ip)) = local_ip() {
+            addresses.push(ip);
+        }
     }
-    
-    Ok(items)
+
+    addresses.sort();
+    addresses.dedup();
+    addresses
 }
 
-fn get_clipboard_history_count_from_db(db_path: &str) -> Result<u32, String> {
-    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
-    
-    let count: u32 = conn.query_row(
-        "SELECT COUNT(*) FROM clipboard_items WHERE content_type != 'file'",
-        [],
-        |row| row.get(0)
-    ).map_err(|e| e.to_string())?;
-    
-    Ok(count)
+// The /24 network prefix ("192.168.1") of each local interface, deduplicated.
+// With an interface chosen, only that interface's network is used.
+fn get_local_network_bases(interface: Option<&str>) -> Vec<String> {
+    let addresses = match interface.and_then(|ip| ip.parse::<std::net::Ipv4Addr>().ok()) {
+        Some(ip) => vec![ip],
+        None => get_local_ipv4_addresses(),
+    };
+    let mut bases: Vec<String> = addresses
+        .iter()
+        .map(|ip| {
+            let [a, b, c, _] = ip.octets();
+            format!("{}.{}.{}", a, b, c)
+        })
+        .collect();
+    bases.dedup();
+    bases
 }
 
-fn get_clipboard_files_count_from_db(db_path: &str) -> Result<u32, String> {
-    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+fn network_interface(state: &AppState) -> Option<String> {
+    state.settings.lock_or_recover().network_interface.clone()
+}
 
-    let count: u32 = conn.query_row(
-        "SELECT COUNT(*) FROM clipboard_items WHERE content_type = 'file'",
-        [],
-        |row| row.get(0)
-    ).map_err(|e| e.to_string())?;
+// Binds an outgoing socket to the chosen interface so traffic leaves through it.
+// If that address has gone away (e.g. a VPN disconnected) the OS picks instead.
+async fn bind_udp_socket(interface: Option<&str>) -> std::io::Result<UdpSocket> {
+    if let Some(interface) = interface {
+        match UdpSocket::bind(format!("{}:0", interface)).await {
+            Ok(socket) => return Ok(socket),
+            Err(e) => eprintln!("Failed to bind to {}, falling back to any interface: {}", interface, e),
+        }
+    }
+    UdpSocket::bind("0.0.0.0:0").await
+}
+
+// Content types listed in the files view. Every other type (text, image, html, ...)
+// belongs to the history view, so the two views always partition the table.
+const FILES_VIEW_CONTENT_TYPES: &[&str] = &["file"];
+
+#[derive(Debug, Clone, Copy)]
+enum ContentView {
+    History,
+    Files,
+}
+
+impl ContentView {
+    // SQL condition on `content_type` selecting the rows shown in this view
+    fn sql_filter(self) -> String {
+        let types = FILES_VIEW_CONTENT_TYPES
+            .iter()
+            .map(|t| format!("'{}'", t))
+            .collect::<Vec<_>>()
+            .join(", ");
+        match self {
+            ContentView::History => format!("content_type NOT IN ({})", types),
+            ContentView::Files => format!("content_type IN ({})", types),
+        }
+    }
+}
+
+fn is_history_visible(content_type: &str) -> bool {
+    !FILES_VIEW_CONTENT_TYPES.contains(&content_type)
+}
+
+fn parse_clock_time(time: &str) -> Result<chrono::NaiveTime, String> {
+    chrono::NaiveTime::parse_from_str(time.trim(), "%H:%M").map_err(|_| format!("Invalid time \"{}\", expected HH:MM", time))
+}
+
+fn in_quiet_hours(quiet_hours: &QuietHours, now: chrono::NaiveTime) -> bool {
+    let (Ok(start), Ok(end)) = (parse_clock_time(&quiet_hours.start), parse_clock_time(&quiet_hours.end)) else {
+        return false;
+    };
+    if start <= end {
+        start <= now && now < end
+    } else {
+        now >= start || now < end
+    }
+}
+
+// Raises `sync-notification` for an item that arrived from a peer, unless its
+// content type is muted or it's quiet hours
+fn notify_synced_item<R: Runtime>(app_handle: &AppHandle<R>, item: &ClipboardItem, device_name: &str) {
+    let (type_enabled, quiet_hours) = {
+        let state = app_handle.state::<AppState>();
+        let settings = state.settings.lock_or_recover();
+        (settings.notify_content_types.contains(&item.content_type), settings.quiet_hours.clone())
+    };
+    let is_quiet = quiet_hours.is_some_and(|quiet| in_quiet_hours(&quiet, chrono::Local::now().time()));
+    if type_enabled && !is_quiet {
+        let _ = app_handle.emit("sync-notification", serde_json::json!({
+            "item": item,
+            "summary": item_summary(item),
+            "device_name": device_name,
+        }));
+    }
+}
+
+// Tells the frontend about a newly stored item, with the event matching the
+// view it belongs to:
+// - `clipboard-updated`: history-visible items (text), as returned by get_clipboard_history
+// - `file-received`: file items, as returned by get_clipboard_files_paginated
+// Items arriving in a peer's history stream are announced together by
+// `history-batch-updated` instead (see record_history_batch_item).
+fn emit_item_added<R: Runtime>(app_handle: &AppHandle<R>, item: &ClipboardItem) {
+    let event = if is_history_visible(&item.content_type) {
+        "clipboard-updated"
+    } else {
+        "file-received"
+    };
+    let _ = app_handle.emit(event, item);
+}
+
+const CLIPBOARD_ITEM_COLUMNS: &str =
+    "id, content, timestamp, device, content_type, file_path, file_size, file_name, use_count, content_size, mime_type, metadata, expires_at, no_sync";
+
+fn row_to_clipboard_item(row: &rusqlite::Row) -> rusqlite::Result<ClipboardItem> {
+    Ok(ClipboardItem {
+        id: row.get(0)?,
+        content: row.get(1)?,
+        timestamp: row.get(2)?,
+        device: row.get(3)?,
+        content_type: row.get(4)?,
+        file_path: row.get(5).ok(),
+        file_size: row.get(6).ok(),
+        file_name: row.get(7).ok(),
+        use_count: row.get(8).unwrap_or(0),
+        content_size: row.get(9).unwrap_or(0),
+        mime_type: row.get::<_, Option<String>>(10).ok().flatten().filter(|m| !m.is_empty()),
+        // Missing or malformed metadata reads as empty rather than failing the whole row
+        metadata: row.get::<_, Option<String>>(11).ok().flatten()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default(),
+        expires_at: row.get::<_, Option<i64>>(12).ok().flatten().map(|t| t as u64),
+        no_sync: row.get::<_, i64>(13).unwrap_or(0) != 0,
+    })
+}
+
+// Size recorded in the content_size column, derived from the item itself so
+// a peer can't misreport it
+fn item_content_size(item: &ClipboardItem) -> u64 {
+    if is_history_visible(&item.content_type) {
+        item.content.len() as u64
+    } else {
+        item.file_size.unwrap_or(0)
+    }
+}
+
+// Refuses text items larger than the configured maximum item size
+fn check_item_size(state: &AppState, item: &ClipboardItem) -> Result<(), String> {
+    let max_item_size = state.settings.lock_or_recover().max_item_size;
+    let size = item_content_size(item);
+    if is_history_visible(&item.content_type) && size > max_item_size {
+        return Err(format!("Item is {} bytes, larger than the {} byte limit", size, max_item_size));
+    }
+    Ok(())
+}
+
+// Synced items go onto the clipboard as text whatever type the peer claims,
+// so their content is held to the limit too
+fn check_synced_item_size(state: &AppState, item: &ClipboardItem) -> Result<(), String> {
+    let max_item_size = state.settings.lock_or_recover().max_item_size;
+    if item.content.len() as u64 > max_item_size {
+        return Err(format!("Item is {} bytes, larger than the {} byte limit", item.content.len(), max_item_size));
+    }
+    check_item_size(state, item)
+}
+
+fn load_clipboard_history_from_db(db_path: &str) -> Result<Vec<ClipboardItem>, String> {
+    load_clipboard_history_paginated(db_path, 0, 50)
+}
+
+fn load_clipboard_history_paginated(db_path: &str, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    
+    let mut stmt = conn.prepare(
+        &format!("SELECT {} FROM clipboard_items WHERE {} ORDER BY timestamp DESC LIMIT ?1 OFFSET ?2", CLIPBOARD_ITEM_COLUMNS, ContentView::History.sql_filter())
+    ).map_err(|e| e.to_string())?;
+    
+    let clipboard_iter = stmt.query_map([limit, offset], row_to_clipboard_item).map_err(|e| e.to_string())?;
+    
+    let mut items = Vec::new();
+    for item in clipboard_iter {
+        items.push(item.map_err(|e| e.to_string())?);
+    }
+    
+    Ok(items)
+}
+
+// Item metadata key holding the application the content was copied from
+const SOURCE_APP_METADATA_KEY: &str = "source_app";
+// Metadata key holding the HTML version of text copied as rich text
+const HTML_METADATA_KEY: &str = "html";
+// Metadata key holding captured text as it was before transform rules changed it
+const ORIGINAL_CONTENT_METADATA_KEY: &str = "original_content";
+
+// Reads metadata.source_app, treating malformed metadata as having none
+const SOURCE_APP_SQL: &str =
+    "CASE WHEN json_valid(metadata) THEN json_extract(metadata, '$.source_app') END";
+
+fn load_history_by_source_app_from_db(db_path: &str, source_app: &str, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM clipboard_items WHERE {} AND {} = ?1 ORDER BY timestamp DESC LIMIT ?2 OFFSET ?3",
+        CLIPBOARD_ITEM_COLUMNS, ContentView::History.sql_filter(), SOURCE_APP_SQL
+    )).map_err(|e| e.to_string())?;
+
+    let items = stmt.query_map(rusqlite::params![source_app, limit, offset], row_to_clipboard_item)
+        .map_err(|e| e.to_string())?;
+    items.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+fn load_source_apps_from_db(db_path: &str) -> Result<Vec<String>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT DISTINCT {0} AS app FROM clipboard_items WHERE app IS NOT NULL ORDER BY app",
+        SOURCE_APP_SQL
+    )).map_err(|e| e.to_string())?;
+
+    let apps = stmt.query_map([], |row| row.get::<_, String>(0)).map_err(|e| e.to_string())?;
+    apps.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+// Contents of the most recent items that hold text, newest first
+fn load_recent_text_contents_from_db(db_path: &str, limit: u32) -> Result<Vec<String>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare(
+        "SELECT content FROM clipboard_items WHERE content_type NOT IN ('file', 'image') ORDER BY timestamp DESC LIMIT ?1"
+    ).map_err(|e| e.to_string())?;
+
+    let contents = stmt.query_map([limit], |row| row.get::<_, String>(0)).map_err(|e| e.to_string())?;
+    contents.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+fn get_content_type_counts_from_db(db_path: &str) -> Result<HashMap<String, u32>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare("SELECT content_type, COUNT(*) FROM clipboard_items GROUP BY content_type")
+        .map_err(|e| e.to_string())?;
+    let counts = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, u32>(1)?)))
+        .map_err(|e| e.to_string())?;
+    counts.collect::<Result<HashMap<_, _>, _>>().map_err(|e| e.to_string())
+}
+
+fn get_clipboard_history_count_from_db(db_path: &str) -> Result<u32, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    
+    let count: u32 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM clipboard_items WHERE {}", ContentView::History.sql_filter()),
+        [],
+        |row| row.get(0)
+    ).map_err(|e| e.to_string())?;
+    
+    Ok(count)
+}
+
+fn get_clipboard_files_count_from_db(db_path: &str) -> Result<u32, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let count: u32 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM clipboard_items WHERE {}", ContentView::Files.sql_filter()),
+        [],
+        |row| row.get(0)
+    ).map_err(|e| e.to_string())?;
 
     Ok(count)
 }
@@ -243,27 +1200,18 @@ fn search_clipboard_items(db_path: &str, query: &str, offset: u32, limit: u32) -
     // Use LIKE for substring matching with case-insensitive search
     let search_pattern = format!("%{}%", query);
 
-    let mut stmt = conn.prepare(
-        "SELECT id, content, timestamp, device, content_type, file_path, file_size, file_name
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
          FROM clipboard_items
          WHERE (content LIKE ?1 COLLATE NOCASE OR file_name LIKE ?1 COLLATE NOCASE)
-         AND content_type != 'file'
+         AND {}
          ORDER BY timestamp DESC
-         LIMIT ?2 OFFSET ?3"
-    ).map_err(|e| e.to_string())?;
+         LIMIT ?2 OFFSET ?3",
+        CLIPBOARD_ITEM_COLUMNS,
+        ContentView::History.sql_filter()
+    )).map_err(|e| e.to_string())?;
 
-    let clipboard_iter = stmt.query_map([&search_pattern, &limit.to_string(), &offset.to_string()], |row| {
-        Ok(ClipboardItem {
-            id: row.get(0)?,
-            content: row.get(1)?,
-            timestamp: row.get(2)?,
-            device: row.get(3)?,
-            content_type: row.get(4)?,
-            file_path: row.get(5).ok(),
-            file_size: row.get(6).ok(),
-            file_name: row.get(7).ok(),
-        })
-    }).map_err(|e| e.to_string())?;
+    let clipboard_iter = stmt.query_map([&search_pattern, &limit.to_string(), &offset.to_string()], row_to_clipboard_item).map_err(|e| e.to_string())?;
 
     let mut items = Vec::new();
     for item in clipboard_iter {
@@ -279,9 +1227,12 @@ fn get_search_results_count(db_path: &str, query: &str) -> Result<u32, String> {
     let search_pattern = format!("%{}%", query);
 
     let count: u32 = conn.query_row(
-        "SELECT COUNT(*) FROM clipboard_items
-         WHERE (content LIKE ?1 COLLATE NOCASE OR file_name LIKE ?1 COLLATE NOCASE)
-         AND content_type != 'file'",
+        &format!(
+            "SELECT COUNT(*) FROM clipboard_items
+             WHERE (content LIKE ?1 COLLATE NOCASE OR file_name LIKE ?1 COLLATE NOCASE)
+             AND {}",
+            ContentView::History.sql_filter()
+        ),
         [&search_pattern],
         |row| row.get(0)
     ).map_err(|e| e.to_string())?;
@@ -289,29 +1240,142 @@ fn get_search_results_count(db_path: &str, query: &str) -> Result<u32, String> {
     Ok(count)
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum HistorySort {
+    #[default]
+    Newest,
+    Oldest,
+    MostUsed,
+    Largest,
+}
+
+impl HistorySort {
+    fn sql_order(self) -> &'static str {
+        match self {
+            HistorySort::Newest => "timestamp DESC",
+            HistorySort::Oldest => "timestamp ASC",
+            HistorySort::MostUsed => "use_count DESC, timestamp DESC",
+            HistorySort::Largest => "content_size DESC, timestamp DESC",
+        }
+    }
+}
+
+// Inclusive bounds on an item's timestamp, in seconds since the epoch
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+struct DateRange {
+    start: Option<u64>,
+    end: Option<u64>,
+}
+
+// Every filter is optional; an empty query returns all items newest first
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+struct HistoryQuery {
+    search: Option<String>,       // Case-insensitive substring of the content or file name
+    content_types: Vec<String>,   // Empty matches every content type
+    device: Option<String>,       // The device an item was captured on
+    collection_id: Option<i64>,   // Items filed directly in this collection
+    sort_by: HistorySort,
+    date_range: Option<DateRange>,
+    offset: u32,
+    limit: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct HistoryPage {
+    items: Vec<ClipboardItem>,
+    total: u32, // Matching items across all pages
+}
+
+// Largest page query_history returns
+const MAX_HISTORY_QUERY_LIMIT: u32 = 500;
+
+// Builds one WHERE clause from the query's filters. User input only ever
+// reaches SQLite as bound parameters.
+fn query_history_from_db(db_path: &str, query: &HistoryQuery) -> Result<HistoryPage, String> {
+    use rusqlite::types::Value;
+
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    let mut conditions: Vec<String> = Vec::new();
+    let mut params: Vec<Value> = Vec::new();
+
+    if let Some(search) = query.search.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        params.push(Value::Text(format!("%{}%", search)));
+        conditions.push(format!("(content LIKE ?{0} COLLATE NOCASE OR file_name LIKE ?{0} COLLATE NOCASE)", params.len()));
+    }
+    if !query.content_types.is_empty() {
+        let placeholders: Vec<String> = query.content_types.iter().map(|content_type| {
+            params.push(Value::Text(content_type.clone()));
+            format!("?{}", params.len())
+        }).collect();
+        conditions.push(format!("content_type IN ({})", placeholders.join(", ")));
+    }
+    if let Some(device) = &query.device {
+        params.push(Value::Text(device.clone()));
+        conditions.push(format!("device = ?{}", params.len()));
+    }
+    if let Some(collection_id) = query.collection_id {
+        params.push(Value::Integer(collection_id));
+        conditions.push(format!("id IN (SELECT item_id FROM collection_items WHERE collection_id = ?{})", params.len()));
+    }
+    if let Some(range) = query.date_range {
+        if let Some(start) = range.start {
+            params.push(Value::Integer(start as i64));
+            conditions.push(format!("CAST(timestamp AS INTEGER) >= ?{}", params.len()));
+        }
+        if let Some(end) = range.end {
+            params.push(Value::Integer(end as i64));
+            conditions.push(format!("CAST(timestamp AS INTEGER) <= ?{}", params.len()));
+        }
+    }
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+
+    let total: u32 = conn.query_row(
+        &format!("SELECT COUNT(*) FROM clipboard_items {}", where_clause),
+        rusqlite::params_from_iter(params.iter()),
+        |row| row.get(0),
+    ).map_err(|e| e.to_string())?;
+
+    let limit = query.limit.unwrap_or(50).min(MAX_HISTORY_QUERY_LIMIT);
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM clipboard_items {} ORDER BY {} LIMIT ?{} OFFSET ?{}",
+        CLIPBOARD_ITEM_COLUMNS,
+        where_clause,
+        query.sort_by.sql_order(),
+        params.len() + 1,
+        params.len() + 2
+    )).map_err(|e| e.to_string())?;
+    params.push(Value::Integer(limit as i64));
+    params.push(Value::Integer(query.offset as i64));
+
+    let items = stmt.query_map(rusqlite::params_from_iter(params.iter()), row_to_clipboard_item)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(HistoryPage { items, total })
+}
+
 fn get_clipboard_files_paginated_from_db(db_path: &str, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
     let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
     
-    let mut stmt = conn.prepare(
-        "SELECT id, content, timestamp, device, content_type, file_path, file_size, file_name 
-         FROM clipboard_items 
-         WHERE content_type = 'file'
-         ORDER BY timestamp DESC 
-         LIMIT ? OFFSET ?"
-    ).map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         FROM clipboard_items
+         WHERE {}
+         ORDER BY timestamp DESC
+         LIMIT ? OFFSET ?",
+        CLIPBOARD_ITEM_COLUMNS,
+        ContentView::Files.sql_filter()
+    )).map_err(|e| e.to_string())?;
     
-    let rows = stmt.query_map([limit, offset], |row| {
-        Ok(ClipboardItem {
-            id: row.get(0)?,
-            content: row.get(1)?,
-            timestamp: row.get(2)?,
-            device: row.get(3)?,
-            content_type: row.get(4)?,
-            file_path: row.get(5)?,
-            file_size: row.get(6)?,
-            file_name: row.get(7)?,
-        })
-    }).map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([limit, offset], row_to_clipboard_item).map_err(|e| e.to_string())?;
     
     let mut items = Vec::new();
     for row in rows {
@@ -337,7 +1401,7 @@ fn save_clipboard_item_to_db(db_path: &str, item: &ClipboardItem) -> Result<(),
 
     for attempt in 0..max_retries {
         match conn.execute(
-            "INSERT OR REPLACE INTO clipboard_items (id, content, timestamp, device, content_type, file_path, file_size, file_name) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            "INSERT OR REPLACE INTO clipboard_items (id, content, timestamp, device, content_type, file_path, file_size, file_name, use_count, content_size, mime_type, metadata, expires_at, no_sync) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
             [
                 &item.id,
                 &item.content,
@@ -347,6 +1411,12 @@ fn save_clipboard_item_to_db(db_path: &str, item: &ClipboardItem) -> Result<(),
                 &item.file_path.as_ref().unwrap_or(&String::new()),
                 &item.file_size.map(|s| s.to_string()).unwrap_or_default(),
                 &item.file_name.as_ref().unwrap_or(&String::new()),
+                &item.use_count.to_string(),
+                &item_content_size(item).to_string(),
+                &item.mime_type.clone().unwrap_or_default(),
+                &serde_json::to_string(&item.metadata).unwrap_or_default(),
+                &item.expires_at.map(|t| t.to_string()).unwrap_or_default(),
+                &(item.no_sync as i32).to_string(),
             ],
         ) {
             Ok(_) => return Ok(()),
@@ -365,940 +1435,5114 @@ fn save_clipboard_item_to_db(db_path: &str, item: &ClipboardItem) -> Result<(),
     Err(last_error)
 }
 
-fn clear_clipboard_history_from_db(db_path: &str) -> Result<(), String> {
+// Removes earlier rows holding the same content as `item` and returns the use count the
+// new row should inherit: the merged rows' counts plus one use per reappearance.
+fn merge_duplicate_items_in_db(db_path: &str, item: &ClipboardItem, window: usize) -> Result<u32, String> {
     let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
-    
-    conn.execute("DELETE FROM clipboard_items", [])
-        .map_err(|e| e.to_string())?;
-    
-    Ok(())
+
+    // Only the `window` most recent history rows are candidates; SQLite reads a negative LIMIT as no limit
+    let window = i64::try_from(window).unwrap_or(-1);
+    let duplicate_filter = format!(
+        "content = ?1 AND content_type = ?2 AND id != ?3 AND id IN (
+            SELECT id FROM clipboard_items WHERE {} ORDER BY timestamp DESC LIMIT ?4
+        )",
+        ContentView::History.sql_filter()
+    );
+    let params = rusqlite::params![item.content, item.content_type, item.id, window];
+
+    let (duplicates, merged_count): (u32, u32) = conn.query_row(
+        &format!("SELECT COUNT(*), COALESCE(SUM(use_count), 0) FROM clipboard_items WHERE {}", duplicate_filter),
+        params,
+        |row| Ok((row.get(0)?, row.get(1)?))
+    ).map_err(|e| e.to_string())?;
+
+    if duplicates == 0 {
+        return Ok(item.use_count);
+    }
+
+    // Copying an item again keeps it in the collection it was filed in
+    conn.execute(
+        &format!("UPDATE OR REPLACE collection_items SET item_id = ?3 WHERE item_id IN (SELECT id FROM clipboard_items WHERE {})", duplicate_filter),
+        params,
+    ).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        &format!("DELETE FROM clipboard_items WHERE {}", duplicate_filter),
+        params,
+    ).map_err(|e| e.to_string())?;
+
+    Ok(item.use_count + merged_count + duplicates)
 }
 
-fn delete_clipboard_item_from_db(db_path: &str, item_id: &str) -> Result<(), String> {
+fn get_most_used_items_from_db(db_path: &str, limit: u32) -> Result<Vec<ClipboardItem>, String> {
     let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
-    
-    conn.execute("DELETE FROM clipboard_items WHERE id = ?1", [item_id])
-        .map_err(|e| e.to_string())?;
-    
-    Ok(())
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}
+         FROM clipboard_items
+         WHERE {} AND use_count > 0
+         ORDER BY use_count DESC, timestamp DESC
+         LIMIT ?1",
+        CLIPBOARD_ITEM_COLUMNS,
+        ContentView::History.sql_filter()
+    )).map_err(|e| e.to_string())?;
+
+    let rows = stmt.query_map([limit], row_to_clipboard_item).map_err(|e| e.to_string())?;
+
+    let mut items = Vec::new();
+    for row in rows {
+        items.push(row.map_err(|e| e.to_string())?);
+    }
+
+    Ok(items)
 }
 
-fn store_file_content(file_content: &[u8], file_name: &str, file_id: &str) -> Result<String, String> {
-    use std::fs;
-    use std::path::Path;
-    
-    // Get app data directory for storing files
-    if let Some(proj_dirs) = ProjectDirs::from("com", "cliped", "cliped") {
-        let data_dir = proj_dirs.data_dir();
-        let files_dir = data_dir.join("files");
-        
-        // Create files directory if it doesn't exist
-        fs::create_dir_all(&files_dir).map_err(|e| format!("Failed to create files directory: {}", e))?;
-        
-        // Extract file extension to preserve it
-        let extension = Path::new(file_name)
-            .extension()
-            .and_then(|ext| ext.to_str())
-            .unwrap_or("");
-        
-        // Create stored filename: file_id + original extension
-        let stored_filename = if extension.is_empty() {
-            file_id.to_string()
+// Returns the distinct `{{placeholder}}` names in a template, in order of first use
+fn extract_placeholders(template: &str) -> Vec<String> {
+    let mut placeholders: Vec<String> = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let after_open = &rest[start + 2..];
+        if let Some(end) = after_open.find("}}") {
+            let name = after_open[..end].trim().to_string();
+            if !name.is_empty() && !placeholders.contains(&name) {
+                placeholders.push(name);
+            }
+            rest = &after_open[end + 2..];
         } else {
-            format!("{}.{}", file_id, extension)
-        };
-        
-        let stored_path = files_dir.join(&stored_filename);
-        
-        // Write file content to storage
-        fs::write(&stored_path, file_content)
-            .map_err(|e| format!("Failed to write file to storage: {}", e))?;
-        
-        println!("File stored successfully: {} -> {}", file_name, stored_path.display());
-        Ok(stored_path.to_string_lossy().to_string())
-    } else {
-        Err("Failed to get project directories for file storage".to_string())
+            break;
+        }
     }
+
+    placeholders
 }
 
-fn get_files_storage_directory() -> Result<String, String> {
-    if let Some(proj_dirs) = ProjectDirs::from("com", "cliped", "cliped") {
-        let data_dir = proj_dirs.data_dir();
-        let files_dir = data_dir.join("files");
-        Ok(files_dir.to_string_lossy().to_string())
-    } else {
-        Err("Failed to get project directories".to_string())
+fn expand_template(template: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    let missing: Vec<String> = extract_placeholders(template)
+        .into_iter()
+        .filter(|name| !values.contains_key(name))
+        .collect();
+
+    if !missing.is_empty() {
+        return Err(format!("Missing values for placeholders: {}", missing.join(", ")));
     }
-}
 
-async fn handle_network_discovery(_app_handle: AppHandle, _state: Arc<AppState>) {
-    // Placeholder for network discovery logic
-    println!("Network discovery service started");
-    
-    loop {
-        tokio::time::sleep(Duration::from_secs(30)).await;
-        // Periodic discovery logic would go here
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        let after_open = &rest[start + 2..];
+        if let Some(end) = after_open.find("}}") {
+            let name = after_open[..end].trim();
+            result.push_str(&rest[..start]);
+            match values.get(name) {
+                Some(value) => result.push_str(value),
+                // Empty braces are not a placeholder, keep them verbatim
+                None => result.push_str(&rest[start..start + 2 + end + 2]),
+            }
+            rest = &after_open[end + 2..];
+        } else {
+            break;
+        }
     }
+    result.push_str(rest);
+
+    Ok(result)
 }
 
-// Store functionality disabled - using in-memory storage only for now
+fn save_snippet_to_db(db_path: &str, name: &str, template: &str) -> Result<Snippet, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    let timestamp = get_current_timestamp().to_string();
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .manage(AppState::default())
-        .setup(|app| {
-            let app_handle = app.handle().clone();
+    conn.execute(
+        "INSERT OR REPLACE INTO snippets (name, template, timestamp) VALUES (?1, ?2, ?3)",
+        [name, template, &timestamp],
+    ).map_err(|e| e.to_string())?;
 
-            // Start UDP server for device discovery in an async task
-            let app_handle_for_udp = app_handle.clone();
-            tauri::async_runtime::spawn(async move {
-                if let Ok(udp_socket) = UdpSocket::bind("0.0.0.0:51847").await {
-                    println!("UDP server listening on port 51847 for device discovery");
-                    let mut buf = [0; 1024];
-                    
-                    loop {
-                        if let Ok((len, addr)) = udp_socket.recv_from(&mut buf).await {
-                            let message_str = String::from_utf8_lossy(&buf[..len]);
-                            println!("Received UDP message from {}: {}", addr, message_str);
-                            
-                            // Try to parse as NetworkMessage
-                            if let Ok(network_msg) = serde_json::from_str::<NetworkMessage>(&message_str) {
-                                match network_msg.msg_type {
-                                    MessageType::Discovery => {
-                                        println!("Discovery request from device: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        
-                                        // Get state to both respond and potentially add discovered device
-                                        let app_state = app_handle_for_udp.state::<AppState>();
-                                        
-                                        // Extract data before any async operations
-                                        let (should_add_device, response_msg) = {
-                                            if let Ok(local_device_lock) = app_state.local_device.lock() {
-                                                if let Some(ref local_device) = *local_device_lock {
-                                                    let should_add = network_msg.device_id != local_device.id;
-                                                    let response = NetworkMessage {
-                                                        msg_type: MessageType::Discovery,
-                                                        device_id: local_device.id,
-                                                        device_name: local_device.name.clone(),
-                                                        data: None,
-                                                    };
-                                                    (should_add, Some(response))
-                                                } else {
-                                                    (false, None)
-                                                }
-                                            } else {
-                                                (false, None)
-                                            }
-                                        };
-                                        
-                                        // Add discovered device if needed
-                                        if should_add_device {
-                                            let sender_ip = addr.ip().to_string();
-                                            let discovered_device = Device {
-                                                id: network_msg.device_id,
-                                                name: network_msg.device_name.clone(),
-                                                icon: "laptop".to_string(),
-                                                ip: sender_ip,
-                                                status: DeviceStatus::Offline,
-                                                sync_mode: SyncMode::Disabled,
-                                                last_seen: get_current_timestamp(),
-                                            };
-                                            
-                                            if let Ok(mut discovered) = app_state.discovered_devices.lock() {
-                                                if !discovered.iter().any(|d| d.id == network_msg.device_id) {
-                                                    discovered.push(discovered_device);
-                                                    println!("Added discovered device: {} at {}", network_msg.device_name, addr.ip());
-                                                }
-                                            }
-                                        }
-                                        
-                                        // Send response
-                                        if let Some(response) = response_msg {
-                                            if let Ok(response_json) = serde_json::to_string(&response) {
-                                                // Send response back to the sender's port (not port 51847)
-                                                let _ = udp_socket.send_to(response_json.as_bytes(), addr).await;
-                                                println!("Sent discovery response to {}", addr);
-                                            }
-                                        }
-                                    },
-                                    MessageType::ConnectionRequest => {
-                                        println!("Connection request from: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        
-                                        // Add to pending connections
-                                        let app_state = app_handle_for_udp.state::<AppState>();
-                                        let sender_ip = addr.ip().to_string();
-                                        let requesting_device = Device {
-                                            id: network_msg.device_id,
-                                            name: network_msg.device_name.clone(),
-                                            icon: "laptop".to_string(),
-                                            ip: sender_ip,
-                                            status: DeviceStatus::Pending,
-                                            sync_mode: SyncMode::Disabled,
-                                            last_seen: get_current_timestamp(),
-                                        };
-                                        
-                                        // Add to pending connections with proper scope
-                                        {
-                                            if let Ok(mut pending) = app_state.pending_connections.lock() {
-                                                if !pending.iter().any(|d| d.id == network_msg.device_id) {
-                                                    pending.push(requesting_device.clone());
-                                                    println!("Added connection request from: {}", network_msg.device_name);
-                                                    
-                                                    // Emit event to frontend to notify of new connection request
-                                                    let _ = app_handle_for_udp.emit("connection-request-received", &requesting_device);
-                                                }
-                                            }
-                                        }
-                                        
-                                        // Emit event to frontend
-                                        let _ = app_handle_for_udp.emit("connection-request", &network_msg);
-                                    },
-                                    MessageType::ConnectionAccept => {
-                                        println!("Connection accepted by: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        
-                                        // When we receive an acceptance, add the accepting device to our connected devices
-                                        let app_state = app_handle_for_udp.state::<AppState>();
-                                        let sender_ip = addr.ip().to_string();
-                                        let accepting_device = Device {
-                                            id: network_msg.device_id,
-                                            name: network_msg.device_name.clone(),
-                                            icon: "laptop".to_string(),
-                                            ip: sender_ip,
-                                            status: DeviceStatus::Connected,
-                                            sync_mode: SyncMode::PartialSync, // Default to partial sync
-                                            last_seen: get_current_timestamp(),
-                                        };
-                                        
-                                        {
-                                            let mut devices = app_state.devices.lock().unwrap();
-                                            devices.insert(network_msg.device_id, accepting_device);
-                                            println!("Added accepted connection: {} at {}", network_msg.device_name, addr.ip());
-                                        }
-                                        
-                                        // Emit event to frontend to refresh device list
-                                        let _ = app_handle_for_udp.emit("connection-accepted", &network_msg.device_id);
-                                    },
-                                    MessageType::ConnectionDeny => {
-                                        println!("Connection denied by: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        // Handle connection denial
-                                    },
-                                    MessageType::ClipboardSync => {
-                                        println!("Clipboard sync from: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        
-                                        // Check if we have any connected devices first
-                                        let app_state = app_handle_for_udp.state::<AppState>();
-                                        let devices = app_state.devices.lock().unwrap();
-                                        
-                                        // If no connected devices, ignore all clipboard sync messages
-                                        if devices.is_empty() {
-                                            println!("No connected devices - ignoring clipboard sync from: {} ({})", 
-                                                    network_msg.device_name, network_msg.device_id);
-                                            continue;
-                                        }
-                                        
-                                        // Check if device is actually connected and verify IP matches
-                                        let sender_ip = addr.ip().to_string();
-                                        let is_valid_device = devices.get(&network_msg.device_id)
-                                            .map(|device| device.ip == sender_ip)
-                                            .unwrap_or(false);
-                                        
-                                        if !is_valid_device {
-                                            println!("Ignoring clipboard sync from unknown/unconnected device or wrong IP: {} ({}) from {}", 
-                                                    network_msg.device_name, network_msg.device_id, sender_ip);
-                                            continue;
-                                        }
-                                        
-                                        drop(devices);
-                                        
-                                        // Handle incoming clipboard sync
-                                        #[cfg(feature = "clipboard")]
-                                        if let Some(item_data) = network_msg.data {
-                                            if let Ok(synced_item) = serde_json::from_str::<ClipboardItem>(&item_data) {
-                                                
-                                                // Check if this content is different from what's currently in clipboard
-                                                let should_update = {
-                                                    if let Ok(mut clipboard) = Clipboard::new() {
-                                                        if let Ok(current_text) = clipboard.get_text() {
-                                                            current_text != synced_item.content
-                                                        } else {
-                                                            true // If we can't read clipboard, assume we should update
-                                                        }
-                                                    } else {
-                                                        true // If we can't access clipboard, assume we should update
-                                                    }
-                                                };
-                                                
-                                                if should_update {
-                                                    // Set ignore flag to prevent sync loop - the monitor will handle adding to history
-                                                    {
-                                                        let mut ignore = app_state.ignore_next_clipboard_change.lock().unwrap();
-                                                        *ignore = true;
-                                                        println!("Setting ignore flag for synced content from {}", network_msg.device_name);
-                                                    }
-                                                    
-                                                    // Set the clipboard content - the monitor will detect this and add to history
-                                                    if let Ok(mut clipboard) = Clipboard::new() {
-                                                        if let Err(e) = clipboard.set_text(&synced_item.content) {
-                                                            eprintln!("Failed to set clipboard content: {}", e);
-                                                        } else {
-                                                            println!("Set clipboard content from connected device {}: {}", 
-                                                                    network_msg.device_name, 
-                                                                    synced_item.content.chars().take(50).collect::<String>());
-                                                        }
-                                                    }
-                                                } else {
-                                                    println!("Synced content is same as current clipboard, skipping update");
-                                                }
-                                            }
-                                        }
-                                        
-                                        #[cfg(not(feature = "clipboard"))]
-                                        if let Some(_item_data) = network_msg.data {
-                                            println!("Received clipboard sync but clipboard functionality not available on this platform");
-                                        }
-                                    },
-                                    MessageType::ConnectionRemove => {
-                                        println!("Connection removed by: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        
-                                        // Remove the device from our connected devices list
-                                        let app_state = app_handle_for_udp.state::<AppState>();
-                                        {
-                                            let mut devices = app_state.devices.lock().unwrap();
-                                            devices.remove(&network_msg.device_id);
-                                            println!("Removed disconnected device: {}", network_msg.device_name);
-                                        }
-                                        
-                                        // Emit event to frontend to refresh device list
-                                        let _ = app_handle_for_udp.emit("device-disconnected", &network_msg.device_id);
-                                    },
-                                    MessageType::Heartbeat => {
-                                        println!("Heartbeat from: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        // Handle heartbeat
-                                    },
-                                    MessageType::FileTransfer => {
-                                        println!("File transfer from: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        
-                                        // Check if device is connected
-                                        let app_state = app_handle_for_udp.state::<AppState>();
-                                        let devices = app_state.devices.lock().unwrap();
-                                        let sender_ip = addr.ip().to_string();
-                                        let is_valid_device = devices.get(&network_msg.device_id)
-                                            .map(|device| device.ip == sender_ip)
-                                            .unwrap_or(false);
-                                        
-                                        if !is_valid_device {
-                                            println!("Ignoring file transfer from unknown/unconnected device: {} ({})", 
-                                                    network_msg.device_name, network_msg.device_id);
-                                            continue;
-                                        }
-                                        
-                                        drop(devices);
-                                        
-                                        // Handle incoming file transfer
-                                        if let Some(file_data) = network_msg.data {
-                                            if let Ok(parsed_data) = serde_json::from_str::<serde_json::Value>(&file_data) {
-                                                if let (Some(item_data), Some(file_content_b64)) = (
-                                                    parsed_data.get("item"),
-                                                    parsed_data.get("file_content").and_then(|v| v.as_str())
-                                                ) {
-                                                    // Decode the file content
-                                                    if let Ok(file_content) = general_purpose::STANDARD.decode(file_content_b64) {
-                                                        if let Ok(received_item) = serde_json::from_value::<ClipboardItem>(item_data.clone()) {
-                                                            
-                                                            // Store the received file
-                                                            let file_name = received_item.file_name.as_ref()
-                                                                .unwrap_or(&"received_file".to_string()).clone();
-                                                            
-                                                            match store_file_content(&file_content, &file_name, &received_item.id) {
-                                                                Ok(stored_path) => {
-                                                                    // Create new item with our local storage path
-                                                                    let local_item = ClipboardItem {
-                                                                        id: received_item.id,
-                                                                        content: received_item.content,
-                                                                        timestamp: received_item.timestamp,
-                                                                        device: received_item.device,
-                                                                        content_type: received_item.content_type,
-                                                                        file_path: Some(stored_path),
-                                                                        file_size: received_item.file_size,
-                                                                        file_name: received_item.file_name,
-                                                                    };
-                                                                    
-                                                                    // Files are not added to in-memory history - only stored in database
-                                                                    
-                                                                    // Save to database
-                                                                    let db_path = app_state.db_path.lock().unwrap().clone();
-                                                                    if let Some(db_path) = db_path {
-                                                                        let _ = save_clipboard_item_to_db(&db_path, &local_item);
-                                                                    }
-                                                                    
-                                                                    // Emit to frontend
-                                                                    let _ = app_handle_for_udp.emit("clipboard-updated", &local_item);
-                                                                    
-                                                                    println!("Received and stored file: {} ({} bytes) from {}", 
-                                                                            file_name, file_content.len(), network_msg.device_name);
-                                                                },
-                                                                Err(e) => {
-                                                                    eprintln!("Failed to store received file: {}", e);
-                                                                }
-                                                            }
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    },
-                                    MessageType::FileTransferChunk => {
-                                        println!("File transfer chunk from: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        // TODO: Handle file transfer chunk
-                                    },
-                                    MessageType::FileTransferComplete => {
-                                        println!("File transfer complete from: {} ({})", network_msg.device_name, network_msg.device_id);
-                                        // TODO: Handle file transfer completion
-                                    }
-                                }
-                            } else {
-                                println!("Failed to parse network message: {}", message_str);
-                            }
-                        }
-                    }
-                } else {
-                    eprintln!("Failed to bind UDP socket on port 51847");
-                }
-            });
+    Ok(Snippet {
+        name: name.to_string(),
+        template: template.to_string(),
+        timestamp,
+        placeholders: extract_placeholders(template),
+    })
+}
 
-            // Initialize state
-            let state: State<AppState> = app.state();
-            let _clipboard_history = Arc::clone(&state.clipboard_history);
-            let enabled = Arc::clone(&state.enabled);
-            
-            // Clear all cached/stale connected devices on startup
-            {
-                let mut devices = state.devices.lock().unwrap();
-                devices.clear();
-                println!("Cleared all cached connected devices on startup");
-            }
-            
-            // Clear any pending connections
-            {
-                let mut pending = state.pending_connections.lock().unwrap();
-                pending.clear();
-                println!("Cleared all pending connections on startup");
-            }
-            
-            // Clear discovered devices
-            {
-                let mut discovered = state.discovered_devices.lock().unwrap();
-                discovered.clear();
-                println!("Cleared all discovered devices on startup");
+fn load_snippets_from_db(db_path: &str) -> Result<Vec<Snippet>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare(
+        "SELECT name, template, timestamp FROM snippets ORDER BY name COLLATE NOCASE"
+    ).map_err(|e| e.to_string())?;
+
+    let rows = stmt.query_map([], |row| {
+        let template: String = row.get(1)?;
+        Ok(Snippet {
+            name: row.get(0)?,
+            placeholders: extract_placeholders(&template),
+            template,
+            timestamp: row.get(2)?,
+        })
+    }).map_err(|e| e.to_string())?;
+
+    let mut snippets = Vec::new();
+    for row in rows {
+        snippets.push(row.map_err(|e| e.to_string())?);
+    }
+
+    Ok(snippets)
+}
+
+fn get_snippet_template_from_db(db_path: &str, name: &str) -> Result<String, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    conn.query_row(
+        "SELECT template FROM snippets WHERE name = ?1",
+        [name],
+        |row| row.get(0)
+    ).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => format!("Snippet '{}' not found", name),
+        other => other.to_string(),
+    })
+}
+
+fn load_collections_from_db(db_path: &str) -> Result<Vec<Collection>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    // Memberships of items that have since been deleted aren't counted
+    let mut stmt = conn.prepare(
+        "SELECT c.id, c.name, c.parent_id, c.timestamp,
+                (SELECT COUNT(*) FROM collection_items ci JOIN clipboard_items i ON i.id = ci.item_id
+                 WHERE ci.collection_id = c.id)
+         FROM collections c ORDER BY c.name COLLATE NOCASE"
+    ).map_err(|e| e.to_string())?;
+
+    let rows = stmt.query_map([], |row| {
+        Ok(Collection {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            parent_id: row.get(2)?,
+            timestamp: row.get(3)?,
+            item_count: row.get(4)?,
+        })
+    }).map_err(|e| e.to_string())?;
+
+    let mut collections = Vec::new();
+    for row in rows {
+        collections.push(row.map_err(|e| e.to_string())?);
+    }
+
+    Ok(collections)
+}
+
+fn collection_exists(conn: &Connection, collection_id: i64) -> Result<bool, String> {
+    conn.query_row("SELECT COUNT(*) FROM collections WHERE id = ?1", [collection_id], |row| row.get::<_, u32>(0))
+        .map(|count| count > 0)
+        .map_err(|e| e.to_string())
+}
+
+fn create_collection_in_db(db_path: &str, name: &str, parent_id: Option<i64>) -> Result<Collection, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    if let Some(parent_id) = parent_id {
+        if !collection_exists(&conn, parent_id)? {
+            return Err("Parent collection not found".to_string());
+        }
+    }
+
+    // Siblings need distinct names to be told apart; `IS` also matches two top-level NULLs
+    let siblings_named: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM collections WHERE name = ?1 COLLATE NOCASE AND parent_id IS ?2",
+        rusqlite::params![name, parent_id],
+        |row| row.get(0),
+    ).map_err(|e| e.to_string())?;
+    if siblings_named > 0 {
+        return Err(format!("A collection named '{}' already exists here", name));
+    }
+
+    let timestamp = get_current_timestamp().to_string();
+    conn.execute(
+        "INSERT INTO collections (name, parent_id, timestamp) VALUES (?1, ?2, ?3)",
+        rusqlite::params![name, parent_id, timestamp],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(Collection {
+        id: conn.last_insert_rowid(),
+        name: name.to_string(),
+        parent_id,
+        timestamp,
+        item_count: 0,
+    })
+}
+
+// Files an item in a collection, replacing any earlier one; None unfiles it
+fn move_item_to_collection_in_db(db_path: &str, item_id: &str, collection_id: Option<i64>) -> Result<(), String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let item_count: u32 = conn.query_row("SELECT COUNT(*) FROM clipboard_items WHERE id = ?1", [item_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if item_count == 0 {
+        return Err("Item not found".to_string());
+    }
+
+    match collection_id {
+        Some(collection_id) => {
+            if !collection_exists(&conn, collection_id)? {
+                return Err("Collection not found".to_string());
             }
-            
-            
-            
-            // Set enabled to true by default
-            *enabled.lock().unwrap() = true;
-            
-            println!("🚀 Cliped app starting...");
-            println!("✨ Beautiful UI clipboard manager ready!");
+            conn.execute(
+                "INSERT OR REPLACE INTO collection_items (item_id, collection_id) VALUES (?1, ?2)",
+                rusqlite::params![item_id, collection_id],
+            ).map_err(|e| e.to_string())?;
+        },
+        None => {
+            conn.execute("DELETE FROM collection_items WHERE item_id = ?1", [item_id])
+                .map_err(|e| e.to_string())?;
+        },
+    }
 
-            // Start clipboard monitoring after a short delay to ensure runtime is ready
-            let state: State<AppState> = app.state();
-            
-            let app_handle_for_monitor = app_handle.clone();
-            let clipboard_history_clone = Arc::clone(&state.clipboard_history);
-            let last_content_clone = Arc::clone(&state.last_clipboard_content);
-            let enabled_clone = Arc::clone(&state.enabled);
-            let devices_clone = Arc::clone(&state.devices);
-            let local_device_clone = Arc::clone(&state.local_device);
-            tauri::async_runtime::spawn(async move {
-                // Small delay to ensure everything is initialized
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                monitor_clipboard(app_handle_for_monitor, clipboard_history_clone, last_content_clone, enabled_clone, devices_clone, local_device_clone).await;
-            });
+    Ok(())
+}
 
-            // Initialize database and load existing history
-            match init_database() {
-                Ok(path) => {
-                    println!("Database initialized at: {}", path);
-                    
-                    // Load existing clipboard history from database
-                    match load_clipboard_history_from_db(&path) {
-                        Ok(history) => {
-                            let mut clipboard_state = state.clipboard_history.lock().unwrap();
-                            *clipboard_state = history;
-                            println!("Loaded {} items from database", clipboard_state.len());
-                        },
-                        Err(e) => {
-                            eprintln!("Failed to load clipboard history: {}", e);
-                        }
+// Deletes a collection along with every collection nested inside it. Their items
+// are unfiled, or deleted too when `delete_items` is set; returns the deleted item ids.
+fn delete_collection_from_db(db_path: &str, collection_id: i64, delete_items: bool) -> Result<Vec<String>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare(
+        "WITH RECURSIVE subtree(id) AS (
+            SELECT id FROM collections WHERE id = ?1
+            UNION SELECT c.id FROM collections c JOIN subtree s ON c.parent_id = s.id
+         )
+         SELECT id FROM subtree"
+    ).map_err(|e| e.to_string())?;
+    let collection_ids = stmt.query_map([collection_id], |row| row.get::<_, i64>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    if collection_ids.is_empty() {
+        return Err("Collection not found".to_string());
+    }
+
+    let placeholders = vec!["?"; collection_ids.len()].join(", ");
+    let mut stmt = conn.prepare(&format!("SELECT item_id FROM collection_items WHERE collection_id IN ({})", placeholders))
+        .map_err(|e| e.to_string())?;
+    let item_ids = stmt.query_map(rusqlite::params_from_iter(collection_ids.iter()), |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    conn.execute_batch("BEGIN").map_err(|e| e.to_string())?;
+    let result = (|| {
+        if delete_items {
+            conn.execute(
+                &format!("DELETE FROM clipboard_items WHERE id IN (SELECT item_id FROM collection_items WHERE collection_id IN ({}))", placeholders),
+                rusqlite::params_from_iter(collection_ids.iter()),
+            ).map_err(|e| e.to_string())?;
+        }
+        conn.execute(
+            &format!("DELETE FROM collection_items WHERE collection_id IN ({})", placeholders),
+            rusqlite::params_from_iter(collection_ids.iter()),
+        ).map_err(|e| e.to_string())?;
+        conn.execute(
+            &format!("DELETE FROM collections WHERE id IN ({})", placeholders),
+            rusqlite::params_from_iter(collection_ids.iter()),
+        ).map_err(|e| e.to_string())?;
+        Ok::<(), String>(())
+    })();
+
+    match result {
+        Ok(()) => conn.execute_batch("COMMIT").map_err(|e| e.to_string())?,
+        Err(e) => {
+            let _ = conn.execute_batch("ROLLBACK");
+            return Err(e);
+        }
+    }
+
+    Ok(if delete_items { item_ids } else { Vec::new() })
+}
+
+// Most connection audit entries kept; older ones are dropped as new ones arrive
+const CONNECTION_AUDIT_LIMIT: u32 = 1000;
+
+fn append_connection_audit_to_db(db_path: &str, entry: &ConnectionAuditEntry) -> Result<(), String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    conn.execute(
+        "INSERT INTO connection_audit (timestamp, event, device_id, device_name, ip, detail) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        rusqlite::params![entry.timestamp as i64, entry.event, entry.device_id, entry.device_name, entry.ip, entry.detail],
+    ).map_err(|e| e.to_string())?;
+    conn.execute(
+        "DELETE FROM connection_audit WHERE id NOT IN (SELECT id FROM connection_audit ORDER BY id DESC LIMIT ?1)",
+        [CONNECTION_AUDIT_LIMIT],
+    ).map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+// Newest first
+fn load_connection_audit_from_db(db_path: &str, limit: u32, offset: u32) -> Result<Vec<ConnectionAuditEntry>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare(
+        "SELECT id, timestamp, event, device_id, device_name, ip, detail FROM connection_audit ORDER BY id DESC LIMIT ?1 OFFSET ?2"
+    ).map_err(|e| e.to_string())?;
+    let entries = stmt.query_map([limit, offset], |row| {
+        Ok(ConnectionAuditEntry {
+            id: row.get(0)?,
+            timestamp: row.get::<_, i64>(1)? as u64,
+            event: row.get(2)?,
+            device_id: row.get(3)?,
+            device_name: row.get(4)?,
+            ip: row.get(5)?,
+            detail: row.get(6)?,
+        })
+    }).map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+fn load_clipboard_item_from_db(db_path: &str, item_id: &str) -> Result<ClipboardItem, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    conn.query_row(
+        &format!("SELECT {} FROM clipboard_items WHERE id = ?1", CLIPBOARD_ITEM_COLUMNS),
+        [item_id],
+        row_to_clipboard_item
+    ).map_err(|e| match e {
+        rusqlite::Error::QueryReturnedNoRows => "Item not found".to_string(),
+        other => other.to_string(),
+    })
+}
+
+fn clear_clipboard_history_from_db(db_path: &str) -> Result<(), String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    
+    conn.execute("DELETE FROM clipboard_items", [])
+        .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM collection_items", [])
+        .map_err(|e| e.to_string())?;
+    
+    Ok(())
+}
+
+// Empties every table, leaving the schema (and its version) in place
+fn wipe_database(db_path: &str) -> Result<(), String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    conn.execute_batch(
+        "BEGIN;
+         DELETE FROM clipboard_items;
+         DELETE FROM settings;
+         DELETE FROM devices;
+         DELETE FROM snippets;
+         DELETE FROM collection_items;
+         DELETE FROM collections;
+         DELETE FROM connection_audit;
+         COMMIT;",
+    ).map_err(|e| e.to_string())?;
+
+    // Reclaim the space so deleted content doesn't linger in free pages
+    let _ = conn.execute_batch("VACUUM");
+    Ok(())
+}
+
+// Rows reported by `PRAGMA integrity_check`; a healthy database reports just "ok"
+fn check_database_integrity_in_db(db_path: &str) -> Result<Vec<String>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare("PRAGMA integrity_check").map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0)).map_err(|e| e.to_string())?;
+    rows.collect::<Result<Vec<_>, _>>().map_err(|e| e.to_string())
+}
+
+// Copies whatever can still be read from a damaged database into a fresh one.
+// Items are read in rowid order from both ends, so a corrupt page in the middle
+// only loses the rows it holds. Returns the number of items recovered.
+fn recover_database(damaged_path: &std::path::Path, db_path: &str) -> Result<usize, String> {
+    let damaged = Connection::open(damaged_path).map_err(|e| e.to_string())?;
+    let mut recovered = HashSet::new();
+
+    for order in ["ASC", "DESC"] {
+        let query = format!("SELECT {} FROM clipboard_items ORDER BY rowid {}", CLIPBOARD_ITEM_COLUMNS, order);
+        let Ok(mut stmt) = damaged.prepare(&query) else {
+            continue;
+        };
+        let Ok(mut rows) = stmt.query([]) else {
+            continue;
+        };
+        loop {
+            match rows.next() {
+                Ok(Some(row)) => {
+                    let Ok(item) = row_to_clipboard_item(row) else {
+                        continue;
+                    };
+                    if recovered.contains(&item.id) {
+                        break; // Met the rows already read from the other end
+                    }
+                    if save_clipboard_item_to_db(db_path, &item).is_ok() {
+                        recovered.insert(item.id);
                     }
-                    
-                    // Store the database path
-                    *state.db_path.lock().unwrap() = Some(path.clone());
                 },
+                Ok(None) => break,
                 Err(e) => {
-                    eprintln!("Failed to initialize database: {}", e);
+                    eprintln!("Stopped reading damaged clipboard items ({}): {}", order, e);
+                    break;
                 }
-            };
-
-            // Generate and set local device info
-            let local_device = generate_device_info();
-            {
-                let mut devices = state.devices.lock().unwrap();
-                devices.insert(local_device.id, local_device.clone());
             }
-            *state.local_device.lock().unwrap() = Some(local_device);
+        }
+    }
 
-            // Start network discovery service
-            let state_arc = Arc::new(AppState::default()); // We'll initialize properly later
-            let state_for_discovery = Arc::clone(&state_arc);
-            tauri::async_runtime::spawn(async move {
-                handle_network_discovery(app_handle, state_for_discovery).await;
-            });
+    // The remaining tables are small; copy each one whole if it is readable.
+    // Collections come before the entries that point at them.
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    conn.execute("ATTACH DATABASE ?1 AS damaged", [damaged_path.to_string_lossy().as_ref()])
+        .map_err(|e| e.to_string())?;
+    for table in ["settings", "devices", "snippets", "collections", "collection_items", "connection_audit"] {
+        let copy = format!("INSERT OR IGNORE INTO main.{0} SELECT * FROM damaged.{0}", table);
+        if let Err(e) = conn.execute(&copy, []) {
+            eprintln!("Could not recover table {}: {}", table, e);
+        }
+    }
+    let _ = conn.execute("DETACH DATABASE damaged", []);
 
-            Ok(())
-        })
-        .invoke_handler(tauri::generate_handler![
-            get_clipboard_history,
-            get_clipboard_history_paginated,
-            get_clipboard_history_count,
-            get_clipboard_files_count,
-            get_clipboard_files_paginated,
-            search_clipboard,
-            get_search_count,
-            clear_clipboard_history,
-            delete_clipboard_item,
-            set_clipboard_content,
-            toggle_monitoring,
-            is_monitoring_enabled,
-            add_clipboard_item,
-            add_device,
-            remove_device,
-            sync_clipboard,
-            get_local_device,
-            get_connected_devices,
-            send_connection_request,
-            accept_connection,
-            deny_connection,
-            get_pending_connections,
-            set_sync_mode,
-            discover_devices,
-            update_device_name,
-            send_connection_request_to_device,
-            add_file_to_clipboard,
-            get_file_content,
-            save_received_file,
-            save_file_to_path,
-            show_open_dialog,
-            show_save_dialog,
-            get_file_preview,
-            get_files_storage_directory_path,
-            move_clipboard_item_to_top
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+    Ok(recovered.len())
+}
+
+// Deletes items whose expiry has passed and returns their ids
+fn delete_expired_items_from_db(db_path: &str, now: u64) -> Result<Vec<String>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    // Items without an expiry store an empty string, so only compare integers
+    let condition = "typeof(expires_at) = 'integer' AND expires_at <= ?1";
+
+    let mut stmt = conn.prepare(&format!("SELECT id FROM clipboard_items WHERE {}", condition))
+        .map_err(|e| e.to_string())?;
+    let ids: Vec<String> = stmt.query_map([now as i64], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .filter_map(|id| id.ok())
+        .collect();
+
+    if !ids.is_empty() {
+        // Collection entries go first, while the subquery can still find the items
+        conn.execute(&format!("DELETE FROM collection_items WHERE item_id IN (SELECT id FROM clipboard_items WHERE {})", condition), [now as i64])
+            .map_err(|e| e.to_string())?;
+        conn.execute(&format!("DELETE FROM clipboard_items WHERE {}", condition), [now as i64])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(ids)
+}
+
+// Deletes all but the `max` newest items of one content type and returns them
+fn delete_items_over_limit_from_db(db_path: &str, content_type: &str, max: u32) -> Result<Vec<ClipboardItem>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM clipboard_items WHERE content_type = ?1 ORDER BY timestamp DESC LIMIT -1 OFFSET ?2",
+        CLIPBOARD_ITEM_COLUMNS
+    )).map_err(|e| e.to_string())?;
+    let items = stmt.query_map(rusqlite::params![content_type, max], row_to_clipboard_item)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    for item in &items {
+        conn.execute("DELETE FROM clipboard_items WHERE id = ?1", [&item.id])
+            .map_err(|e| e.to_string())?;
+        conn.execute("DELETE FROM collection_items WHERE item_id = ?1", [&item.id])
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(items)
+}
+
+// Removes a file the app stored for an item. Paths outside managed storage
+// are left alone, so a bad path can never delete the user's own files.
+// Returns the bytes freed.
+fn remove_stored_file(path: &str) -> u64 {
+    let Ok(files_dir) = files_directory() else {
+        return 0;
+    };
+    let path = std::path::Path::new(path);
+    if path.parent() != Some(files_dir.as_path()) {
+        return 0;
+    }
+    remove_file_counting_bytes(path)
+}
+
+fn remove_file_counting_bytes(path: &std::path::Path) -> u64 {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    match std::fs::remove_file(path) {
+        Ok(()) => size,
+        Err(e) => {
+            eprintln!("Failed to remove {}: {}", path.display(), e);
+            0
+        }
+    }
+}
+
+// Stored files younger than this are never treated as orphans; a received file
+// is written to storage a moment before its item is saved
+const ORPHAN_GRACE_SECS: u64 = 60;
+
+// Deletes files in managed storage that no item refers to any more, e.g. those
+// of expired items. Returns how many were deleted and their total size.
+fn remove_orphaned_files(db_path: &str) -> Result<(usize, u64), String> {
+    // A directory picked through the environment may hold files that aren't ours
+    if env_path_override(FILES_DIR_ENV).is_some() {
+        return Ok((0, 0));
+    }
+    let files_dir = files_directory()?;
+    let Ok(entries) = std::fs::read_dir(&files_dir) else {
+        return Ok((0, 0));
+    };
+
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare("SELECT file_path FROM clipboard_items WHERE file_path IS NOT NULL AND file_path != ''")
+        .map_err(|e| e.to_string())?;
+    // Compared by name so differently spelled paths to the same file still match
+    let referenced: HashSet<std::ffi::OsString> = stmt.query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .filter_map(|path| path.ok())
+        .filter_map(|path| std::path::Path::new(&path).file_name().map(|name| name.to_os_string()))
+        .collect();
+
+    let grace = Duration::from_secs(ORPHAN_GRACE_SECS);
+    let (mut count, mut bytes) = (0, 0);
+    // Only files directly in storage; thumbnails and received files live in subfolders
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let is_recent = metadata.modified().ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_none_or(|age| age < grace);
+        if !metadata.is_file() || is_recent || referenced.contains(&entry.file_name()) {
+            continue;
+        }
+        let freed = remove_file_counting_bytes(&path);
+        if freed > 0 || !path.exists() {
+            count += 1;
+            bytes += freed;
+        }
+    }
+    Ok((count, bytes))
+}
+
+fn delete_clipboard_item_from_db(db_path: &str, item_id: &str) -> Result<(), String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    
+    conn.execute("DELETE FROM clipboard_items WHERE id = ?1", [item_id])
+        .map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM collection_items WHERE item_id = ?1", [item_id])
+        .map_err(|e| e.to_string())?;
+    
+    Ok(())
+}
+
+// Reduces a peer-supplied file name to a single path component so it can't
+// escape the directory it's joined to
+fn sanitize_file_name(file_name: &str) -> Result<String, String> {
+    let is_absolute = std::path::Path::new(file_name).is_absolute()
+        || file_name.starts_with(['/', '\\'])
+        || file_name.chars().nth(1) == Some(':');
+    if is_absolute {
+        return Err(format!("Refusing absolute file name: {}", file_name));
+    }
+
+    let sanitized: String = file_name
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':') || c.is_control() { '_' } else { c })
+        .collect();
+    let sanitized = sanitized.trim_matches(|c: char| c == '.' || c.is_whitespace());
+
+    if sanitized.is_empty() {
+        return Err(format!("Invalid file name: {}", file_name));
+    }
+    Ok(sanitized.to_string())
+}
+
+fn store_file_content(file_content: &[u8], file_name: &str, file_id: &str) -> Result<String, String> {
+    use std::fs;
+    use std::path::Path;
+    
+    // Get app data directory for storing files
+    let files_dir = files_directory()?;
+    
+    // Create files directory if it doesn't exist
+    fs::create_dir_all(&files_dir).map_err(|e| format!("Failed to create files directory: {}", e))?;
+    
+    // Extract file extension to preserve it
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+    
+    // Create stored filename: file_id + original extension. Both come from
+    // the sending peer, so the result is sanitized like any other name.
+    let stored_filename = if extension.is_empty() {
+        file_id.to_string()
+    } else {
+        format!("{}.{}", file_id, extension)
+    };
+    let stored_filename = sanitize_file_name(&stored_filename)?;
+    
+    let stored_path = files_dir.join(&stored_filename);
+    
+    // Write file content to storage
+    fs::write(&stored_path, file_content)
+        .map_err(|e| format!("Failed to write file to storage: {}", e))?;
+    
+    println!("File stored successfully: {} -> {}", file_name, stored_path.display());
+    Ok(stored_path.to_string_lossy().to_string())
+}
+
+// List of text-based file extensions
+const TEXT_FILE_EXTENSIONS: &[&str] = &[
+    "txt", "md", "json", "xml", "html", "htm", "css", "js", "ts", "jsx", "tsx",
+    "py", "rs", "go", "java", "c", "cpp", "h", "hpp", "cs", "php", "rb", "pl",
+    "sh", "bash", "zsh", "fish", "ps1", "bat", "cmd", "sql", "log", "cfg", "conf",
+    "ini", "toml", "yaml", "yml", "csv", "tsv", "rtf", "tex", "dockerfile", "gitignore",
+    "readme", "license", "changelog", "makefile", "cmake", "vcxproj", "csproj",
+    "swift", "kt", "scala", "clj", "hs", "elm", "dart", "lua", "r", "jl", "m", "mm"
+];
+
+const IMAGE_FILE_EXTENSIONS: &[(&str, &str)] = &[
+    ("png", "image/png"), ("jpg", "image/jpeg"), ("jpeg", "image/jpeg"), ("gif", "image/gif"),
+    ("webp", "image/webp"), ("bmp", "image/bmp"), ("svg", "image/svg+xml"), ("ico", "image/x-icon"),
+];
+
+// Identifies a file from its leading bytes, since extensions can be wrong
+// (a ".txt" that is really a PNG). Falls back to the extension when the
+// content has no recognizable signature, which is the case for most text.
+fn detect_mime_type(file_name: &str, content: &[u8]) -> Option<String> {
+    if let Some(kind) = infer::get(content) {
+        return Some(kind.mime_type().to_string());
+    }
+
+    let extension = std::path::Path::new(file_name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    if let Some((_, mime)) = IMAGE_FILE_EXTENSIONS.iter().find(|(ext, _)| *ext == extension) {
+        return Some(mime.to_string());
+    }
+    if TEXT_FILE_EXTENSIONS.contains(&extension.as_str()) {
+        return Some("text/plain".to_string());
+    }
+    None
+}
+
+fn is_text_mime_type(mime_type: &str) -> bool {
+    mime_type.starts_with("text/")
+        || matches!(mime_type, "application/json" | "application/xml" | "application/x-sh")
+}
+
+// Largest text file that gets an inline text copy in history
+const MAX_INLINE_TEXT_FILE_SIZE: u64 = 64 * 1024;
+
+// Largest file item, whether added from disk, a URL or a zipped directory
+const MAX_FILE_ITEM_SIZE: u64 = 10 * 1024 * 1024;
+// Limits for add_url_to_clipboard, matching the local file size cap
+const MAX_URL_DOWNLOAD_SIZE: u64 = MAX_FILE_ITEM_SIZE;
+// Most uncompressed data zipped by add_directory_to_clipboard or unpacked by
+// extract_file_item, so neither can run away on a huge tree or a zip bomb
+const MAX_DIRECTORY_CONTENT_SIZE: u64 = 100 * 1024 * 1024;
+const URL_DOWNLOAD_TIMEOUT_SECS: u64 = 30;
+
+fn get_files_storage_directory() -> Result<String, String> {
+    let files_dir = files_directory()?;
+    Ok(files_dir.to_string_lossy().to_string())
+}
+
+fn default_data_directory() -> Result<std::path::PathBuf, String> {
+    ProjectDirs::from("com", "cliped", "cliped")
+        .map(|proj_dirs| proj_dirs.data_dir().to_path_buf())
+        .ok_or("Failed to get project directories".to_string())
+}
+
+// The custom data directory can't be recorded in the database it relocates,
+// so its path is kept in a small file in the default config directory
+fn data_directory_pointer_file() -> Result<std::path::PathBuf, String> {
+    ProjectDirs::from("com", "cliped", "cliped")
+        .map(|proj_dirs| proj_dirs.config_dir().join("data_directory"))
+        .ok_or("Failed to get project directories".to_string())
+}
+
+// Where the database and stored files live: the directory chosen with
+// set_data_directory, or the default one if none was chosen or it's gone
+fn data_directory() -> Result<std::path::PathBuf, String> {
+    if let Ok(custom) = std::fs::read_to_string(data_directory_pointer_file()?) {
+        let custom = std::path::PathBuf::from(custom.trim());
+        if custom.is_dir() {
+            return Ok(custom);
+        }
+        eprintln!("Data directory {} is unavailable, falling back to the default", custom.display());
+    }
+    default_data_directory()
+}
+
+// Environment overrides for the database file and the stored files directory,
+// so tests can point at a temp location and power users can relocate either.
+// Precedence: environment variable > set_data_directory > default directory.
+const DB_PATH_ENV: &str = "CLIPED_DB_PATH";
+const FILES_DIR_ENV: &str = "CLIPED_FILES_DIR";
+
+fn env_path_override(var: &str) -> Option<std::path::PathBuf> {
+    std::env::var_os(var)
+        .filter(|value| !value.is_empty())
+        .map(std::path::PathBuf::from)
+}
+
+fn database_path() -> Result<std::path::PathBuf, String> {
+    match env_path_override(DB_PATH_ENV) {
+        Some(path) => Ok(path),
+        None => Ok(data_directory()?.join("clipboard.db")),
+    }
+}
+
+fn files_directory() -> Result<std::path::PathBuf, String> {
+    match env_path_override(FILES_DIR_ENV) {
+        Some(path) => Ok(path),
+        None => Ok(data_directory()?.join("files")),
+    }
+}
+
+fn check_directory_writable(dir: &std::path::Path) -> Result<(), String> {
+    let probe = dir.join(".cliped-write-test");
+    std::fs::write(&probe, b"")
+        .map_err(|e| format!("Directory {} is not writable: {}", dir.display(), e))?;
+    let _ = std::fs::remove_file(&probe);
+    Ok(())
+}
+
+fn copy_dir_recursive(from: &std::path::Path, to: &std::path::Path) -> Result<(), String> {
+    std::fs::create_dir_all(to).map_err(|e| e.to_string())?;
+    for entry in std::fs::read_dir(from).map_err(|e| e.to_string())? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        let target = to.join(entry.file_name());
+        if entry.path().is_dir() {
+            copy_dir_recursive(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(())
+}
+
+// Whether `inner` is `outer` or somewhere beneath it, once symlinks and ".." are resolved
+fn is_within_directory(inner: &std::path::Path, outer: &std::path::Path) -> bool {
+    match (inner.canonicalize(), outer.canonicalize()) {
+        (Ok(inner), Ok(outer)) => inner.starts_with(outer),
+        _ => false,
+    }
+}
+
+// Copies the database, its backups and stored files into `new_dir` and
+// repoints stored file paths there. Everything is staged in a scratch
+// directory first, so a failed copy leaves `new_dir` untouched.
+fn move_data_directory(old_db_path: &std::path::Path, old_dir: &std::path::Path, new_dir: &std::path::Path) -> Result<std::path::PathBuf, String> {
+    // The copy would recurse into itself, and removing the old files afterwards would delete the new ones
+    if is_within_directory(new_dir, old_dir) {
+        return Err(format!("{} is inside the current data directory {}", new_dir.display(), old_dir.display()));
+    }
+    let staging = new_dir.join(".cliped-moving");
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir_all(&staging).map_err(|e| e.to_string())?;
+
+    let staged = (|| {
+        // Flush the WAL so the main database file is complete before copying it
+        if let Ok(conn) = Connection::open(old_db_path) {
+            let _ = conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()));
+        }
+        std::fs::copy(old_db_path, staging.join("clipboard.db")).map_err(|e| e.to_string())?;
+
+        for (_, backup_path) in list_database_backups(old_db_path) {
+            if let Some(name) = backup_path.file_name() {
+                std::fs::copy(&backup_path, staging.join(name)).map_err(|e| e.to_string())?;
+            }
+        }
+
+        let old_files_dir = old_dir.join("files");
+        if old_files_dir.is_dir() {
+            copy_dir_recursive(&old_files_dir, &staging.join("files"))?;
+        }
+
+        let conn = Connection::open(staging.join("clipboard.db")).map_err(|e| e.to_string())?;
+        let old_prefix = old_files_dir.to_string_lossy().to_string();
+        let new_prefix = new_dir.join("files").to_string_lossy().to_string();
+        conn.execute(
+            "UPDATE clipboard_items SET file_path = ?2 || substr(file_path, length(?1) + 1)
+             WHERE substr(file_path, 1, length(?1)) = ?1",
+            [&old_prefix, &new_prefix],
+        ).map_err(|e| e.to_string())?;
+        Ok::<(), String>(())
+    })();
+
+    if let Err(e) = staged {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(format!("Failed to copy data to {}: {}", new_dir.display(), e));
+    }
+
+    // Renames within one directory are atomic, unlike the copies above
+    for entry in std::fs::read_dir(&staging).map_err(|e| e.to_string())?.flatten() {
+        std::fs::rename(entry.path(), new_dir.join(entry.file_name())).map_err(|e| e.to_string())?;
+    }
+    let _ = std::fs::remove_dir(&staging);
+
+    Ok(new_dir.join("clipboard.db"))
+}
+
+fn load_setting_from_db(db_path: &str, key: &str) -> Result<Option<String>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    match conn.query_row("SELECT value FROM settings WHERE key = ?1", [key], |row| row.get(0)) {
+        Ok(value) => Ok(Some(value)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn save_setting_to_db(db_path: &str, key: &str, value: &str) -> Result<(), String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    conn.execute(
+        "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+        [key, value],
+    ).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+fn load_settings_from_db(db_path: &str) -> Settings {
+    match load_setting_from_db(db_path, "settings") {
+        Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+            eprintln!("Failed to parse saved settings, using defaults: {}", e);
+            Settings::default()
+        }),
+        Ok(None) => Settings::default(),
+        Err(e) => {
+            eprintln!("Failed to load settings, using defaults: {}", e);
+            Settings::default()
+        }
+    }
+}
+
+// Replaces the in-memory history with the latest items from the database
+fn reload_history_from_db(state: &AppState) -> Result<Vec<ClipboardItem>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    let history = load_clipboard_history_from_db(&db_path)?;
+    *state.clipboard_history.lock_or_recover() = history.clone();
+    Ok(history)
+}
+
+// Saves the current settings; failures are logged since the in-memory value still applies
+fn persist_settings(state: &AppState) {
+    let db_path = state.db_path.lock_or_recover().clone();
+    let settings = state.settings.lock_or_recover().clone();
+
+    if let Some(db_path) = db_path {
+        let result = serde_json::to_string(&settings)
+            .map_err(|e| e.to_string())
+            .and_then(|json| save_setting_to_db(&db_path, "settings", &json));
+        if let Err(e) = result {
+            eprintln!("Failed to save settings: {}", e);
+        }
+    }
+}
+
+fn load_devices_from_db(db_path: &str) -> Result<Vec<Device>, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let mut stmt = conn.prepare("SELECT data FROM devices").map_err(|e| e.to_string())?;
+    let rows = stmt.query_map([], |row| row.get::<_, String>(0)).map_err(|e| e.to_string())?;
+
+    let mut devices = Vec::new();
+    for row in rows {
+        let data = row.map_err(|e| e.to_string())?;
+        match serde_json::from_str::<Device>(&data) {
+            Ok(device) => devices.push(device),
+            Err(e) => eprintln!("Skipping unreadable saved device: {}", e),
+        }
+    }
+
+    Ok(devices)
+}
+
+// Saves a paired device so it can be restored on the next launch
+fn persist_device(state: &AppState, device: &Device) {
+    let db_path = state.db_path.lock_or_recover().clone();
+
+    if let Some(db_path) = db_path {
+        let result = Connection::open(&db_path)
+            .map_err(|e| e.to_string())
+            .and_then(|conn| {
+                let data = serde_json::to_string(device).map_err(|e| e.to_string())?;
+                conn.execute(
+                    "INSERT OR REPLACE INTO devices (id, data) VALUES (?1, ?2)",
+                    rusqlite::params![device.id, data],
+                ).map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            eprintln!("Failed to save device {}: {}", device.name, e);
+        }
+    }
+}
+
+// A paired device belongs only in `devices`; drop any copy lingering in the
+// pending or discovered lists
+fn remove_from_transient_lists(state: &AppState, device_id: u32) {
+    state.pending_connections.lock_or_recover().retain(|d| d.id != device_id);
+    state.discovered_devices.lock_or_recover().retain(|d| d.id != device_id);
+}
+
+// Returns how many items were deleted
+fn purge_expired_items(app_handle: &AppHandle) -> usize {
+    let state = app_handle.state::<AppState>();
+    let Some(db_path) = state.db_path.lock_or_recover().clone() else {
+        return 0;
+    };
+
+    let expired = match delete_expired_items_from_db(&db_path, get_current_timestamp()) {
+        Ok(expired) => expired,
+        Err(e) => {
+            eprintln!("Failed to delete expired items: {}", e);
+            return 0;
+        }
+    };
+    if expired.is_empty() {
+        return 0;
+    }
+
+    let remaining = {
+        let mut history = state.clipboard_history.lock_or_recover();
+        history.retain(|item| !expired.contains(&item.id));
+        history.len()
+    };
+    for id in &expired {
+        remove_item_thumbnails(id);
+    }
+    println!("Deleted {} expired items", expired.len());
+    let _ = app_handle.emit("history-updated", remaining);
+    expired.len()
+}
+
+// Deletes the oldest items of each content type beyond its retention limit,
+// along with the files stored for them. Returns the number of items deleted
+// and the bytes their files took up.
+fn enforce_retention_limits(app_handle: &AppHandle) -> (usize, u64) {
+    let state = app_handle.state::<AppState>();
+    let Some(db_path) = state.db_path.lock_or_recover().clone() else {
+        return (0, 0);
+    };
+    let limits = state.settings.lock_or_recover().retention_limits.clone();
+
+    let mut pruned = Vec::new();
+    for (content_type, max) in limits {
+        match delete_items_over_limit_from_db(&db_path, &content_type, max) {
+            Ok(items) => pruned.extend(items),
+            Err(e) => eprintln!("Failed to apply the {} retention limit: {}", content_type, e),
+        }
+    }
+    if pruned.is_empty() {
+        return (0, 0);
+    }
+
+    let remaining = {
+        let mut history = state.clipboard_history.lock_or_recover();
+        history.retain(|item| !pruned.iter().any(|p| p.id == item.id));
+        history.len()
+    };
+    let mut bytes = 0;
+    for item in &pruned {
+        if let Some(path) = &item.file_path {
+            bytes += remove_stored_file(path);
+        }
+        remove_item_thumbnails(&item.id);
+    }
+    println!("Deleted {} items over their retention limit", pruned.len());
+    let _ = app_handle.emit("history-updated", remaining);
+    (pruned.len(), bytes)
+}
+
+// Drops connection requests older than the configured expiry; a request's
+// `last_seen` is when it arrived
+fn expire_pending_connections(app_handle: &AppHandle) {
+    let state = app_handle.state::<AppState>();
+    let expiry_secs = state.settings.lock_or_recover().pending_request_expiry_secs;
+    let now = get_current_timestamp();
+
+    let expired: Vec<Device> = {
+        let mut pending = state.pending_connections.lock_or_recover();
+        let (expired, kept) = pending.drain(..)
+            .partition(|d| now.saturating_sub(d.last_seen) > expiry_secs);
+        *pending = kept;
+        expired
+    };
+
+    for device in expired {
+        println!("Connection request from {} expired", device.name);
+        record_connection_event(&state, ConnectionEvent::Expired, device.id, &device.name, &device.ip, None);
+        let _ = app_handle.emit("connection-request-expired", &device.id);
+    }
+}
+
+// Records the display name a paired device announced, if it changed and came
+// from the address the device is known at
+fn update_peer_display_name(state: &AppState, device_id: u32, sender_ip: &str, display_name: &Option<String>) {
+    let updated = {
+        let mut devices = state.devices.lock_or_recover();
+        devices.get_mut(&device_id)
+            .filter(|device| device.ip == sender_ip && device.display_name != *display_name)
+            .map(|device| {
+                device.display_name = display_name.clone();
+                device.clone()
+            })
+    };
+    if let Some(device) = updated {
+        persist_device(state, &device);
+        println!("Device {} is now shown as {:?}", device.name, device.display_name);
+    }
+}
+
+// Offers an unpaired device for pairing again, as if a scan had just found it
+fn rediscover_device(state: &AppState, device: &Device) {
+    let mut discovered = state.discovered_devices.lock_or_recover();
+    if !discovered.iter().any(|d| d.id == device.id) {
+        discovered.push(Device {
+            status: DeviceStatus::Offline,
+            sync_mode: SyncMode::Disabled,
+            relay_key: None,
+            ..device.clone()
+        });
+    }
+}
+
+// Appends to the connection audit log; failures are logged rather than
+// interrupting the connection change being recorded
+fn record_connection_event(state: &AppState, event: ConnectionEvent, device_id: u32, device_name: &str, ip: &str, detail: Option<&str>) {
+    let db_path = state.db_path.lock_or_recover().clone();
+
+    if let Some(db_path) = db_path {
+        let entry = ConnectionAuditEntry {
+            id: 0,
+            timestamp: get_current_timestamp(),
+            event: event.as_str().to_string(),
+            device_id,
+            device_name: device_name.to_string(),
+            ip: ip.to_string(),
+            detail: detail.map(str::to_string),
+        };
+        if let Err(e) = append_connection_audit_to_db(&db_path, &entry) {
+            eprintln!("Failed to record connection event for {}: {}", device_name, e);
+        }
+    }
+}
+
+fn forget_device(state: &AppState, device_id: u32) {
+    let db_path = state.db_path.lock_or_recover().clone();
+
+    if let Some(db_path) = db_path {
+        let result = Connection::open(&db_path)
+            .map_err(|e| e.to_string())
+            .and_then(|conn| {
+                conn.execute("DELETE FROM devices WHERE id = ?1", [device_id])
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            eprintln!("Failed to delete saved device {}: {}", device_id, e);
+        }
+    }
+}
+
+// Loads the local device identity saved by a previous launch, or creates a new one
+fn load_or_create_local_device(db_path: Option<&str>, interface: Option<&str>) -> Device {
+    let saved = db_path.and_then(|db_path| {
+        load_setting_from_db(db_path, "local_device")
+            .ok()
+            .flatten()
+            .and_then(|json| serde_json::from_str::<Device>(&json).ok())
+    });
+
+    let device = match saved {
+        Some(mut device) => {
+            // The address may have changed since the last launch
+            device.ip = get_local_ip(interface);
+            device.status = DeviceStatus::Connected;
+            device.last_seen = get_current_timestamp();
+            device
+        },
+        None => generate_device_info(interface),
+    };
+
+    if let Some(db_path) = db_path {
+        save_local_device_to_db(db_path, &device);
+    }
+
+    device
+}
+
+fn save_local_device_to_db(db_path: &str, device: &Device) {
+    let result = serde_json::to_string(device)
+        .map_err(|e| e.to_string())
+        .and_then(|json| save_setting_to_db(db_path, "local_device", &json));
+    if let Err(e) = result {
+        eprintln!("Failed to save local device: {}", e);
+    }
+}
+
+// Sends a heartbeat ping and waits briefly for the device to answer
+async fn probe_device(stats: &NetworkStats, interface: Option<&str>, local: &Device, device: &Device) -> bool {
+    let message = NetworkMessage {
+        msg_type: MessageType::Heartbeat,
+        device_id: local.id,
+        device_name: local.name.clone(),
+        data: Some(HEARTBEAT_PING.to_string()),
+        display_name: None,
+    };
+
+    let Ok(socket) = bind_udp_socket(interface).await else {
+        return false;
+    };
+    let Ok(message_json) = serde_json::to_string(&message) else {
+        return false;
+    };
+    let target_addr = format!("{}:51847", device.ip);
+    if let Err(e) = send_datagram(stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&device.ip)).await {
+        eprintln!("{}", e);
+        return false;
+    }
+
+    let mut buf = [0; 1024];
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(1000);
+    while let Ok(Ok((len, addr))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        if let Ok(reply) = serde_json::from_slice::<NetworkMessage>(&buf[..len]) {
+            stats.record_received(&reply.msg_type, &addr.ip().to_string(), len);
+            if matches!(reply.msg_type, MessageType::Heartbeat)
+                && reply.device_id == device.id
+                && addr.ip().to_string() == device.ip
+            {
+                return true;
+            }
+        }
+    }
+
+    false
+}
+
+// Starts verification of a paired device's new address if `sender_ip` differs from
+// the one on record. Only one check per device runs at a time.
+fn check_device_ip_change<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, sender_ip: &str) {
+    let app_state = app_handle.state::<AppState>();
+    let device = app_state.devices.lock_or_recover()
+        .get(&device_id)
+        .filter(|device| device.ip != sender_ip)
+        .cloned();
+    let is_local = app_state.local_device.lock_or_recover()
+        .as_ref()
+        .map(|local| local.id == device_id)
+        .unwrap_or(false);
+    let Some(device) = device.filter(|_| !is_local) else {
+        return;
+    };
+
+    // Pairings from before relay keys can't prove who they are; let the user decide
+    if device.relay_key.is_none() {
+        let is_new = app_state.unconfirmed_ip_changes.lock_or_recover()
+            .insert(device_id, sender_ip.to_string())
+            .is_none_or(|previous| previous != sender_ip);
+        if is_new {
+            println!("Device {} may have moved from {} to {}; waiting for the user to confirm", device.name, device.ip, sender_ip);
+            let _ = app_handle.emit("device-ip-change-detected", serde_json::json!({
+                "device_id": device_id,
+                "old_ip": device.ip,
+                "new_ip": sender_ip,
+            }));
+        }
+        return;
+    }
+
+    if !app_state.ip_change_checks.lock_or_recover().insert(device_id) {
+        return;
+    }
+    let app_handle = app_handle.clone();
+    let new_ip = sender_ip.to_string();
+    tauri::async_runtime::spawn(async move {
+        confirm_device_ip_change(&app_handle, device_id, new_ip).await;
+        app_handle.state::<AppState>().ip_change_checks.lock_or_recover().remove(&device_id);
+    });
+}
+
+// Moves a paired device to a new IP once whoever answers there proves it holds
+// the pairing's relay key. Claiming the device id alone isn't enough, since any
+// host on the LAN can do that from its own address.
+async fn confirm_device_ip_change<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, new_ip: String) {
+    let app_state = app_handle.state::<AppState>();
+    let local = app_state.local_device.lock_or_recover().clone();
+    let device = app_state.devices.lock_or_recover().get(&device_id).cloned();
+    let interface = network_interface(&app_state);
+
+    let (Some(local), Some(device)) = (local, device) else {
+        return;
+    };
+    let keys = accepted_relay_keys(&app_state, &device);
+    if keys.is_empty() || device.ip == new_ip {
+        return;
+    }
+
+    let mut candidate = device.clone();
+    candidate.ip = new_ip.clone();
+    if !prove_device_identity(&app_state.network_stats, interface.as_deref(), &local, &candidate, &keys).await {
+        println!("Ignoring unconfirmed address change for {}: {} -> {}", device.name, device.ip, new_ip);
+        return;
+    }
+    move_device_ip(app_handle, device_id, &new_ip);
+}
+
+// Points a paired device at its new address, saves it and tells the frontend
+fn move_device_ip<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, new_ip: &str) {
+    let app_state = app_handle.state::<AppState>();
+    app_state.unconfirmed_ip_changes.lock_or_recover().remove(&device_id);
+    let updated = {
+        let mut devices = app_state.devices.lock_or_recover();
+        devices.get_mut(&device_id).map(|device| {
+            let old_ip = std::mem::replace(&mut device.ip, new_ip.to_string());
+            device.last_seen = get_current_timestamp();
+            (old_ip, device.clone())
+        })
+    };
+
+    if let Some((old_ip, updated)) = updated {
+        persist_device(&app_state, &updated);
+        let _ = app_handle.emit("device-ip-changed", serde_json::json!({
+            "device_id": device_id,
+            "old_ip": old_ip,
+            "new_ip": new_ip,
+        }));
+        println!("Device {} moved from {} to {}", updated.name, old_ip, new_ip);
+    }
+}
+
+// Applies an address change reported by `device-ip-change-detected`, for
+// pairings that have no relay key to prove the change with
+#[tauri::command]
+fn confirm_device_ip(app_handle: AppHandle, state: State<AppState>, device_id: u32, ip: String) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let detected = state.unconfirmed_ip_changes.lock_or_recover().get(&device_id).cloned();
+    if detected.as_deref() != Some(ip.as_str()) {
+        return Err(format!("No address change to {} was detected for this device", ip));
+    }
+    move_device_ip(&app_handle, device_id, &ip);
+    Ok(())
+}
+
+// Signs `nonce` for the device that sent the challenge; the message type keeps
+// the signature from passing as a relayed frame
+fn identity_mac(key: &str, challenger_id: u32, responder_id: u32, nonce: &str) -> Result<String, String> {
+    relay_mac(key, challenger_id, &identity_message(responder_id, nonce))
+}
+
+fn identity_message(responder_id: u32, nonce: &str) -> NetworkMessage {
+    NetworkMessage {
+        msg_type: MessageType::IdentityCheck,
+        device_id: responder_id,
+        device_name: String::new(),
+        data: Some(nonce.to_string()),
+        display_name: None,
+    }
+}
+
+// Challenges `device` at its (possibly new) address and waits for a reply from
+// that address whose signature checks out against one of the pairing's relay keys
+async fn prove_device_identity(stats: &NetworkStats, interface: Option<&str>, local: &Device, device: &Device, keys: &[String]) -> bool {
+    let nonce = generate_relay_key();
+    let message = NetworkMessage {
+        msg_type: MessageType::IdentityCheck,
+        device_id: local.id,
+        device_name: local.name.clone(),
+        data: serde_json::to_string(&IdentityPayload { nonce: nonce.clone(), mac: None }).ok(),
+        display_name: None,
+    };
+
+    await_device_reply(stats, interface, device, &message, |reply| {
+        let proof = reply.data.as_deref().and_then(|data| serde_json::from_str::<IdentityPayload>(data).ok());
+        match (&reply.msg_type, proof) {
+            (MessageType::IdentityCheck, Some(IdentityPayload { nonce: reply_nonce, mac: Some(mac) })) => {
+                reply_nonce == nonce
+                    && keys.iter().any(|key| verify_relay_mac(key, local.id, &identity_message(device.id, &nonce), &mac))
+            },
+            _ => false,
+        }
+    }).await
+}
+
+// Sends `message` to `device` from a fresh socket and waits for a reply from
+// the device's id and address that `is_reply` accepts
+async fn await_device_reply(stats: &NetworkStats, interface: Option<&str>, device: &Device, message: &NetworkMessage, is_reply: impl Fn(&NetworkMessage) -> bool) -> bool {
+    let Ok(socket) = bind_udp_socket(interface).await else {
+        return false;
+    };
+    let Ok(message_json) = serde_json::to_string(message) else {
+        return false;
+    };
+    let target_addr = format!("{}:51847", device.ip);
+    if let Err(e) = send_datagram(stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&device.ip)).await {
+        eprintln!("{}", e);
+        return false;
+    }
+
+    let mut buf = [0; 1024];
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(1000);
+    while let Ok(Ok((len, addr))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let Ok(reply) = serde_json::from_slice::<NetworkMessage>(&buf[..len]) else {
+            continue;
+        };
+        stats.record_received(&reply.msg_type, &addr.ip().to_string(), len);
+        if reply.device_id == device.id && addr.ip().to_string() == device.ip && is_reply(&reply) {
+            return true;
+        }
+    }
+
+    false
+}
+
+// Tray menu: reopen the window, or quit for real even when close_to_tray is on
+fn build_tray(app: &tauri::App) -> tauri::Result<()> {
+    use tauri::menu::{Menu, MenuItem};
+    use tauri::tray::TrayIconBuilder;
+
+    let show = MenuItem::with_id(app, "show", "Show Cliped", true, None::<&str>)?;
+    let quit = MenuItem::with_id(app, "quit", "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[&show, &quit])?;
+
+    let mut tray = TrayIconBuilder::with_id("main")
+        .tooltip("Cliped")
+        .menu(&menu)
+        .on_menu_event(|app_handle, event| match event.id().as_ref() {
+            "show" => {
+                if let Some(window) = app_handle.get_webview_window("cliped") {
+                    let _ = window.show();
+                    let _ = window.set_focus();
+                }
+            },
+            "quit" => {
+                let app_handle = app_handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    quit_app(&app_handle).await;
+                });
+            },
+            _ => {},
+        });
+    if let Some(icon) = app.default_window_icon() {
+        tray = tray.icon(icon.clone());
+    }
+    tray.build(app)?;
+    Ok(())
+}
+
+// Tells every paired device we're leaving, then exits
+async fn quit_app(app_handle: &AppHandle) {
+    let state = app_handle.state::<AppState>();
+    notify_paired_devices_removed(&state).await;
+    app_handle.exit(0);
+}
+
+// Sends ConnectionRemove to every paired device
+async fn notify_paired_devices_removed(state: &AppState) {
+    let local = state.local_device.lock_or_recover().clone();
+    let Some(local) = local else {
+        return;
+    };
+    let paired: Vec<Device> = state.devices.lock_or_recover()
+        .values()
+        .filter(|d| d.id != local.id)
+        .cloned()
+        .collect();
+    for device in &paired {
+        let message = NetworkMessage {
+            msg_type: MessageType::ConnectionRemove,
+            device_id: local.id,
+            device_name: local.name.clone(),
+            data: None,
+            display_name: None,
+        };
+        let _ = send_message_to_device(&state.network_stats, network_interface(state).as_deref(), &device.ip, &message).await;
+    }
+}
+
+async fn handle_network_discovery(_app_handle: AppHandle, _state: Arc<AppState>) {
+    // Placeholder for network discovery logic
+    println!("Network discovery service started");
+    
+    loop {
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        // Periodic discovery logic would go here
+    }
+}
+
+// Handles one datagram received by the UDP server. `addr` is where it came
+// from; replies that aren't sent to the port 51847 go back through `udp_socket`.
+async fn handle_message<R: Runtime>(app_handle: &AppHandle<R>, udp_socket: &UdpSocket, network_msg: NetworkMessage, addr: std::net::SocketAddr) {
+    // Our own broadcasts loop back to us; nothing from our own id may make this
+    // device its own peer
+    let local_id = app_handle.state::<AppState>().local_device.lock_or_recover().as_ref().map(|d| d.id);
+    if Some(network_msg.device_id) == local_id {
+        return;
+    }
+
+    match network_msg.msg_type {
+        MessageType::Discovery => {
+            println!("Discovery request from device: {} ({})", network_msg.device_name, network_msg.device_id);
+            
+            // Get state to both respond and potentially add discovered device
+            let app_state = app_handle.state::<AppState>();
+            
+            // Extract data before any async operations
+            let (should_add_device, response_msg) = {
+                if let Ok(local_device_lock) = app_state.local_device.lock() {
+                    if let Some(ref local_device) = *local_device_lock {
+                        let should_add = network_msg.device_id != local_device.id;
+                        let response = NetworkMessage {
+                            msg_type: MessageType::DiscoveryResponse,
+                            device_id: local_device.id,
+                            device_name: local_device.name.clone(),
+                            data: network_msg.data.clone(), // Echo the scan id
+                            display_name: local_device.display_name.clone(),
+                        };
+                        (should_add, Some(response))
+                    } else {
+                        (false, None)
+                    }
+                } else {
+                    (false, None)
+                }
+            };
+            
+            // A paired device announcing itself from a new address may have changed IP
+            if should_add_device {
+                check_device_ip_change(app_handle, network_msg.device_id, &addr.ip().to_string());
+                update_peer_display_name(&app_state, network_msg.device_id, &addr.ip().to_string(), &network_msg.display_name);
+            }
+            
+            // Add discovered device if needed; paired devices are already listed
+            let is_paired = app_state.devices.lock_or_recover().contains_key(&network_msg.device_id);
+            if should_add_device && !is_paired {
+                let sender_ip = addr.ip().to_string();
+                let discovered_device = Device {
+                    id: network_msg.device_id,
+                    name: network_msg.device_name.clone(),
+                    icon: "laptop".to_string(),
+                    ip: sender_ip,
+                    status: DeviceStatus::Offline,
+                    sync_mode: SyncMode::Disabled,
+                    last_seen: get_current_timestamp(),
+                    trusted: false,
+                    last_synced: None,
+                    display_name: network_msg.display_name.clone(),
+                    disabled: false,
+                    relay_key: None,
+                };
+                
+                if let Ok(mut discovered) = app_state.discovered_devices.lock() {
+                    if !discovered.iter().any(|d| d.id == network_msg.device_id) {
+                        discovered.push(discovered_device);
+                        println!("Added discovered device: {} at {}", network_msg.device_name, addr.ip());
+                    }
+                }
+            }
+            
+            // Send response
+            if let Some(response) = response_msg {
+                if let Ok(response_json) = serde_json::to_string(&response) {
+                    // Send response back to the sender's port (not port 51847)
+                    match send_datagram(&app_state.network_stats, udp_socket, &response.msg_type, &response_json, addr, Some(&addr.ip().to_string())).await {
+                        Ok(()) => println!("Sent discovery response to {}", addr),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+            }
+        },
+        MessageType::DiscoveryResponse => {
+            // Replies go to the scanning socket, not the server port
+            println!("Ignoring unsolicited discovery response from: {} ({})", network_msg.device_name, network_msg.device_id);
+        },
+        MessageType::ConnectionRequest => {
+            println!("Connection request from: {} ({})", network_msg.device_name, network_msg.device_id);
+            
+            // Add to pending connections
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+            let requesting_device = Device {
+                id: network_msg.device_id,
+                name: network_msg.device_name.clone(),
+                icon: "laptop".to_string(),
+                ip: sender_ip,
+                status: DeviceStatus::Pending,
+                sync_mode: SyncMode::Disabled,
+                last_seen: get_current_timestamp(),
+                trusted: false,
+                last_synced: None,
+                display_name: network_msg.display_name.clone(),
+                disabled: false,
+                relay_key: None,
+            };
+            
+            // Add to pending connections with proper scope
+            let (added, dropped) = {
+                let mut added = false;
+                let mut dropped = None;
+                if let Ok(mut pending) = app_state.pending_connections.lock() {
+                    if !pending.iter().any(|d| d.id == network_msg.device_id) {
+                        if pending.len() >= MAX_PENDING_CONNECTIONS {
+                            if let Some(oldest) = pending.iter().enumerate().min_by_key(|(_, d)| d.last_seen).map(|(i, _)| i) {
+                                let oldest = pending.remove(oldest);
+                                let _ = app_handle.emit("connection-request-expired", &oldest.id);
+                                dropped = Some(oldest);
+                            }
+                        }
+                        pending.push(requesting_device.clone());
+                        added = true;
+                        println!("Added connection request from: {}", network_msg.device_name);
+                        
+                        // Emit event to frontend to notify of new connection request
+                        let _ = app_handle.emit("connection-request-received", &requesting_device);
+                    }
+                }
+                (added, dropped)
+            };
+            if let Some(dropped) = dropped {
+                record_connection_event(&app_state, ConnectionEvent::Expired, dropped.id, &dropped.name, &dropped.ip, Some("Too many pending requests"));
+            }
+            if added {
+                record_connection_event(&app_state, ConnectionEvent::RequestReceived, requesting_device.id, &requesting_device.name, &requesting_device.ip, None);
+            }
+            
+            // Emit event to frontend
+            let _ = app_handle.emit("connection-request", &network_msg);
+        },
+        MessageType::ConnectionAccept => {
+            println!("Connection accepted by: {} ({})", network_msg.device_name, network_msg.device_id);
+            
+            // When we receive an acceptance, add the accepting device to our connected devices
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+
+            // Over the limit: refuse instead, so the peer drops us too
+            if let Some(reason) = device_limit_reason(&app_state, network_msg.device_id) {
+                println!("Refusing connection from {}: {}", network_msg.device_name, reason);
+                record_connection_event(&app_state, ConnectionEvent::Denied, network_msg.device_id, &network_msg.device_name, &sender_ip, Some(&reason));
+                remove_from_transient_lists(&app_state, network_msg.device_id);
+                if let Err(e) = send_connection_deny(&app_state, &sender_ip, Some(reason)).await {
+                    eprintln!("Failed to send connection denial: {}", e);
+                }
+                return;
+            }
+
+            let accepting_device = Device {
+                id: network_msg.device_id,
+                name: network_msg.device_name.clone(),
+                icon: "laptop".to_string(),
+                ip: sender_ip,
+                status: DeviceStatus::Connected,
+                sync_mode: SyncMode::PartialSync, // Default to partial sync
+                last_seen: get_current_timestamp(),
+                trusted: false,
+                last_synced: None,
+                display_name: network_msg.display_name.clone(),
+                disabled: false,
+                relay_key: network_msg.data.clone(),
+            };
+            
+            {
+                let mut devices = app_state.devices.lock_or_recover();
+                devices.insert(network_msg.device_id, accepting_device.clone());
+                println!("Added accepted connection: {} at {}", network_msg.device_name, addr.ip());
+            }
+            remove_from_transient_lists(&app_state, network_msg.device_id);
+            persist_device(&app_state, &accepting_device);
+            record_connection_event(&app_state, ConnectionEvent::AcceptedByPeer, accepting_device.id, &accepting_device.name, &accepting_device.ip, None);
+            
+            // Emit event to frontend to refresh device list
+            let _ = app_handle.emit("connection-accepted", &network_msg.device_id);
+
+            let app_handle = app_handle.clone();
+            let device_id = network_msg.device_id;
+            tauri::async_runtime::spawn(async move {
+                sync_latest_item(&app_handle, device_id).await;
+            });
+        },
+        MessageType::ConnectionDeny => {
+            let reason = network_msg.data.clone().unwrap_or_else(|| "Connection denied".to_string());
+            println!("Connection denied by: {} ({}): {}", network_msg.device_name, network_msg.device_id, reason);
+
+            // A denial can follow an accept when the peer is at its device limit
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+            record_connection_event(&app_state, ConnectionEvent::DeniedByPeer, network_msg.device_id, &network_msg.device_name, &sender_ip, network_msg.data.as_deref());
+            let removed = {
+                let mut devices = app_state.devices.lock_or_recover();
+                match devices.get(&network_msg.device_id) {
+                    Some(device) if device.ip == sender_ip => devices.remove(&network_msg.device_id).is_some(),
+                    _ => false,
+                }
+            };
+            if removed {
+                forget_device(&app_state, network_msg.device_id);
+                let _ = app_handle.emit("device-disconnected", &network_msg.device_id);
+            }
+
+            let _ = app_handle.emit("connection-denied", serde_json::json!({
+                "device_id": network_msg.device_id,
+                "reason": reason,
+            }));
+        },
+        MessageType::ClipboardSync => {
+            println!("Clipboard sync from: {} ({})", network_msg.device_name, network_msg.device_id);
+            
+            // Check if we have any connected devices first
+            let app_state = app_handle.state::<AppState>();
+            let devices = app_state.devices.lock_or_recover();
+            
+            // If no connected devices, ignore all clipboard sync messages
+            if devices.is_empty() {
+                println!("No connected devices - ignoring clipboard sync from: {} ({})", 
+                        network_msg.device_name, network_msg.device_id);
+                return;
+            }
+            
+            // Check if device is actually connected and verify IP matches
+            let sender_ip = addr.ip().to_string();
+            let require_trust = app_state.settings.lock_or_recover().require_explicit_trust;
+            let is_valid_device = devices.get(&network_msg.device_id)
+                .map(|device| device.ip == sender_ip && is_sync_allowed(device, require_trust))
+                .unwrap_or(false);
+            
+            if !is_valid_device {
+                println!("Ignoring clipboard sync from unknown/unconnected device or wrong IP: {} ({}) from {}", 
+                        network_msg.device_name, network_msg.device_id, sender_ip);
+                return;
+            }
+            
+            drop(devices);
+            
+            if is_sync_paused(&app_state) {
+                println!("Sync paused on an untrusted network - ignoring clipboard sync from {}", network_msg.device_name);
+                return;
+            }
+            
+            // Handle incoming clipboard sync
+            #[cfg(feature = "clipboard")]
+            if let Some(item_data) = network_msg.data {
+                if let Ok(synced_item) = serde_json::from_str::<ClipboardItem>(&item_data) {
+                    if let Err(e) = check_synced_item_size(&app_state, &synced_item) {
+                        eprintln!("Refusing clipboard sync from {}: {}", network_msg.device_name, e);
+                        let _ = app_handle.emit("item-rejected", &e);
+                        return;
+                    }
+                    mark_device_synced(&app_state, network_msg.device_id);
+                    
+                    apply_synced_clipboard_item(&app_state, &synced_item, &network_msg.device_name);
+                    notify_synced_item(app_handle, &synced_item, &network_msg.device_name);
+                }
+            }
+            
+            #[cfg(not(feature = "clipboard"))]
+            if let Some(_item_data) = network_msg.data {
+                println!("Received clipboard sync but clipboard functionality not available on this platform");
+            }
+        },
+        MessageType::ConnectionRemove => {
+            println!("Connection removed by: {} ({})", network_msg.device_name, network_msg.device_id);
+            
+            // Remove the device from our connected devices list
+            // Only the paired device itself may end the connection
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+            let removed = {
+                let mut devices = app_state.devices.lock_or_recover();
+                match devices.get(&network_msg.device_id) {
+                    Some(device) if device.ip == sender_ip => devices.remove(&network_msg.device_id),
+                    _ => None,
+                }
+            };
+
+            // The device just reached us, so it can be offered for pairing again
+            if let Some(device) = removed {
+                println!("Removed disconnected device: {}", network_msg.device_name);
+                forget_device(&app_state, network_msg.device_id);
+                record_connection_event(&app_state, ConnectionEvent::RemovedByPeer, device.id, &device.name, &sender_ip, None);
+                rediscover_device(&app_state, &Device {
+                    ip: sender_ip,
+                    last_seen: get_current_timestamp(),
+                    ..device
+                });
+
+                // Emit event to frontend to refresh device list
+                let _ = app_handle.emit("device-disconnected", &network_msg.device_id);
+            }
+        },
+        MessageType::Heartbeat => {
+            println!("Heartbeat from: {} ({})", network_msg.device_name, network_msg.device_id);
+
+            // Record that a known device is alive
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+            check_device_ip_change(app_handle, network_msg.device_id, &sender_ip);
+            let revived = {
+                let mut devices = app_state.devices.lock_or_recover();
+                let mut revived = false;
+                if let Some(device) = devices.get_mut(&network_msg.device_id) {
+                    if device.ip == sender_ip {
+                        device.last_seen = get_current_timestamp();
+                        // A restored pairing is back online
+                        if matches!(device.status, DeviceStatus::Offline) {
+                            device.status = DeviceStatus::Connected;
+                            println!("Device back online: {}", device.name);
+                            revived = true;
+                        }
+                    }
+                }
+                revived
+            };
+            if revived {
+                remove_from_transient_lists(&app_state, network_msg.device_id);
+            }
+
+            // Answer reachability probes so the sender can tell we're online
+            if network_msg.data.as_deref() == Some(HEARTBEAT_PING) {
+                let local_device = app_state.local_device.lock_or_recover().clone();
+                if let Some(local) = local_device {
+                    let response = NetworkMessage {
+                        msg_type: MessageType::Heartbeat,
+                        device_id: local.id,
+                        device_name: local.name,
+                        data: None,
+                        display_name: None,
+                    };
+                    if let Ok(response_json) = serde_json::to_string(&response) {
+                        if let Err(e) = send_datagram(&app_state.network_stats, udp_socket, &response.msg_type, &response_json, addr, Some(&sender_ip)).await {
+                            eprintln!("{}", e);
+                        }
+                    }
+                }
+            }
+        },
+        MessageType::FileTransfer => {
+            println!("File transfer from: {} ({})", network_msg.device_name, network_msg.device_id);
+            
+            // Check if device is connected
+            let app_state = app_handle.state::<AppState>();
+            let require_trust = app_state.settings.lock_or_recover().require_explicit_trust;
+            let devices = app_state.devices.lock_or_recover();
+            let sender_ip = addr.ip().to_string();
+            let is_valid_device = devices.get(&network_msg.device_id)
+                .map(|device| device.ip == sender_ip && is_sync_allowed(device, require_trust))
+                .unwrap_or(false);
+            
+            if !is_valid_device {
+                println!("Ignoring file transfer from unknown/unconnected device: {} ({})", 
+                        network_msg.device_name, network_msg.device_id);
+                return;
+            }
+            
+            drop(devices);
+            
+            if is_sync_paused(&app_state) {
+                println!("Sync paused on an untrusted network - ignoring file transfer from {}", network_msg.device_name);
+                return;
+            }
+            
+            // Handle incoming file transfer
+            if let Some(file_data) = network_msg.data {
+                if let Ok(header) = serde_json::from_str::<FileTransferHeader>(&file_data) {
+                    // Chunked transfer: content follows in FileTransferChunk messages
+                    begin_incoming_transfer(app_handle, network_msg.device_id, header);
+                } else if let Ok(parsed_data) = serde_json::from_str::<serde_json::Value>(&file_data) {
+                    // Older peers send the whole file inline in a single message
+                    if let (Some(item_data), Some(file_content_b64)) = (
+                        parsed_data.get("item"),
+                        parsed_data.get("file_content").and_then(|v| v.as_str())
+                    ) {
+                        if let (Ok(file_content), Ok(received_item)) = (
+                            general_purpose::STANDARD.decode(file_content_b64),
+                            serde_json::from_value::<ClipboardItem>(item_data.clone())
+                        ) {
+                            if let Err(e) = store_received_file(app_handle, received_item, &file_content, &network_msg.device_name) {
+                                eprintln!("Failed to store received file: {}", e);
+                            }
+                        }
+                    }
+                }
+            }
+        },
+        MessageType::FileTransferChunk => {
+            let app_state = app_handle.state::<AppState>();
+            if !is_connected_sender(&app_state, network_msg.device_id, &addr.ip().to_string()) {
+                return;
+            }
+
+            if let Some(chunk) = network_msg.data.and_then(|data| serde_json::from_str::<FileChunk>(&data).ok()) {
+                receive_file_chunk(app_handle, network_msg.device_id, chunk);
+            }
+        },
+        MessageType::FileTransferComplete => {
+            println!("File transfer complete from: {} ({})", network_msg.device_name, network_msg.device_id);
+
+            let app_state = app_handle.state::<AppState>();
+            if !is_connected_sender(&app_state, network_msg.device_id, &addr.ip().to_string()) {
+                return;
+            }
+
+            if let Some(end) = network_msg.data.and_then(|data| serde_json::from_str::<FileTransferEnd>(&data).ok()) {
+                complete_incoming_transfer(app_handle, network_msg.device_id, &network_msg.device_name, &end.file_id);
+            }
+        },
+        MessageType::FileTransferCancel => {
+            println!("File transfer cancelled by: {} ({})", network_msg.device_name, network_msg.device_id);
+
+            let app_state = app_handle.state::<AppState>();
+            if !is_connected_sender(&app_state, network_msg.device_id, &addr.ip().to_string()) {
+                return;
+            }
+
+            if let Some(cancel) = network_msg.data.and_then(|data| serde_json::from_str::<FileTransferEnd>(&data).ok()) {
+                handle_transfer_cancel(app_handle, network_msg.device_id, cancel);
+            }
+        },
+        MessageType::Echo => {
+            // Answer diagnostic probes from anyone; the reply only says
+            // whether the sender is paired with us
+            let Some(request) = network_msg.data.and_then(|data| serde_json::from_str::<EchoPayload>(&data).ok()) else {
+                return;
+            };
+            if request.paired.is_some() {
+                return;
+            }
+
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+            let require_trust = app_state.settings.lock_or_recover().require_explicit_trust;
+            let paired = app_state.devices.lock_or_recover()
+                .get(&network_msg.device_id)
+                .map(|device| device.ip == sender_ip && is_sync_allowed(device, require_trust))
+                .unwrap_or(false);
+            let local_device = app_state.local_device.lock_or_recover().clone();
+            if let Some(local) = local_device {
+                let reply = NetworkMessage {
+                    msg_type: MessageType::Echo,
+                    device_id: local.id,
+                    device_name: local.name,
+                    data: serde_json::to_string(&EchoPayload { nonce: request.nonce, paired: Some(paired) }).ok(),
+                    display_name: None,
+                };
+                if let Ok(reply_json) = serde_json::to_string(&reply) {
+                    if let Err(e) = send_datagram(&app_state.network_stats, udp_socket, &reply.msg_type, &reply_json, addr, Some(&sender_ip)).await {
+                        eprintln!("{}", e);
+                    }
+                }
+            }
+        },
+        MessageType::IdentityCheck => {
+            // Prove our identity to a paired device that sees us at a new address.
+            // Only challenges from where we know that device are answered, so a
+            // third host can't relay a challenge through us and pass as us.
+            let Some(challenge) = network_msg.data.and_then(|data| serde_json::from_str::<IdentityPayload>(&data).ok()) else {
+                return;
+            };
+            if challenge.mac.is_some() {
+                return;
+            }
+
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+            let key = app_state.devices.lock_or_recover()
+                .get(&network_msg.device_id)
+                .filter(|device| device.ip == sender_ip)
+                .and_then(|device| device.relay_key.clone());
+            let local_device = app_state.local_device.lock_or_recover().clone();
+            let (Some(key), Some(local)) = (key, local_device) else {
+                return;
+            };
+            let Ok(mac) = identity_mac(&key, network_msg.device_id, local.id, &challenge.nonce) else {
+                return;
+            };
+            let reply = NetworkMessage {
+                msg_type: MessageType::IdentityCheck,
+                device_id: local.id,
+                device_name: local.name,
+                data: serde_json::to_string(&IdentityPayload { nonce: challenge.nonce, mac: Some(mac) }).ok(),
+                display_name: None,
+            };
+            if let Ok(reply_json) = serde_json::to_string(&reply) {
+                if let Err(e) = send_datagram(&app_state.network_stats, udp_socket, &reply.msg_type, &reply_json, addr, Some(&sender_ip)).await {
+                    eprintln!("{}", e);
+                }
+            }
+        },
+        MessageType::KeyRotation => {
+            // A paired device replacing our relay key. The request has to come from
+            // its known address, signed with a key we still accept from it.
+            // Confirmations are read by the requester's own socket, not here.
+            let Some(KeyRotationPayload { key: Some(new_key), mac }) = network_msg.data.and_then(|data| serde_json::from_str(&data).ok()) else {
+                return;
+            };
+
+            let app_state = app_handle.state::<AppState>();
+            let sender_ip = addr.ip().to_string();
+            let device = app_state.devices.lock_or_recover()
+                .get(&network_msg.device_id)
+                .filter(|device| device.ip == sender_ip)
+                .cloned();
+            let local_device = app_state.local_device.lock_or_recover().clone();
+            let (Some(device), Some(local)) = (device, local_device) else {
+                return;
+            };
+            let request = key_rotation_message(device.id, &new_key);
+            if !accepted_relay_keys(&app_state, &device).iter().any(|key| verify_relay_mac(key, local.id, &request, &mac)) {
+                println!("Ignoring key rotation with a bad signature claiming to be {} ({})", device.name, device.id);
+                return;
+            }
+            // Signing with the new key also checks that it is a usable key
+            let Ok(confirmation) = relay_mac(&new_key, device.id, &key_rotation_message(local.id, &new_key)) else {
+                return;
+            };
+
+            // A repeated request for the key we already switched to is only confirmed again
+            if device.relay_key.as_deref() != Some(new_key.as_str()) {
+                install_relay_key(app_handle, device.id, new_key);
+            }
+            let reply = NetworkMessage {
+                msg_type: MessageType::KeyRotation,
+                device_id: local.id,
+                device_name: local.name,
+                data: serde_json::to_string(&KeyRotationPayload { key: None, mac: confirmation }).ok(),
+                display_name: None,
+            };
+            if let Ok(reply_json) = serde_json::to_string(&reply) {
+                if let Err(e) = send_datagram(&app_state.network_stats, udp_socket, &reply.msg_type, &reply_json, addr, Some(&sender_ip)).await {
+                    eprintln!("{}", e);
+                }
+            }
+        },
+        MessageType::HistoryRequest => {
+            println!("History request from: {} ({})", network_msg.device_name, network_msg.device_id);
+
+            // Only serve history to connected devices at their known IP
+            let app_state = app_handle.state::<AppState>();
+            if is_sync_paused(&app_state) {
+                println!("Sync paused on an untrusted network - ignoring history request from {}", network_msg.device_name);
+                return;
+            }
+            let sender_ip = addr.ip().to_string();
+            let require_trust = app_state.settings.lock_or_recover().require_explicit_trust;
+            let requester = {
+                let devices = app_state.devices.lock_or_recover();
+                devices.get(&network_msg.device_id)
+                    .filter(|device| matches!(device.status, DeviceStatus::Connected) && device.ip == sender_ip)
+                    .filter(|device| is_sync_allowed(device, require_trust))
+                    .cloned()
+            };
+
+            let Some(requester) = requester else {
+                println!("Ignoring history request from unknown/unconnected device: {} ({})",
+                        network_msg.device_name, network_msg.device_id);
+                return;
+            };
+
+            let db_path = app_state.db_path.lock_or_recover().clone();
+            let local_device = app_state.local_device.lock_or_recover().clone();
+
+            let network_stats = Arc::clone(&app_state.network_stats);
+            let interface = network_interface(&app_state);
+
+            if let (Some(db_path), Some(local)) = (db_path, local_device) {
+                // Page through the database without blocking the UDP server loop
+                tauri::async_runtime::spawn(async move {
+                    send_history_to_device(&network_stats, interface.as_deref(), &db_path, &local, &requester).await;
+                });
+            }
+        },
+        MessageType::HistoryResponse => {
+            let app_state = app_handle.state::<AppState>();
+            if is_sync_paused(&app_state) {
+                println!("Sync paused on an untrusted network - ignoring history from {}", network_msg.device_name);
+                return;
+            }
+            let sender_ip = addr.ip().to_string();
+            let require_trust = app_state.settings.lock_or_recover().require_explicit_trust;
+            let is_valid_device = {
+                let devices = app_state.devices.lock_or_recover();
+                devices.get(&network_msg.device_id)
+                    .map(|device| {
+                        matches!(device.status, DeviceStatus::Connected) && device.ip == sender_ip
+                            && is_sync_allowed(device, require_trust)
+                    })
+                    .unwrap_or(false)
+            };
+
+            if !is_valid_device {
+                println!("Ignoring history response from unknown/unconnected device: {} ({})",
+                        network_msg.device_name, network_msg.device_id);
+                return;
+            }
+
+            if let Some(item_data) = network_msg.data {
+                if let Ok(received_item) = serde_json::from_str::<ClipboardItem>(&item_data) {
+                    if let Err(e) = check_item_size(&app_state, &received_item) {
+                        println!("Skipping history item from {}: {}", network_msg.device_name, e);
+                        return;
+                    }
+                    let db_path = app_state.db_path.lock_or_recover().clone();
+                    if let Some(db_path) = db_path {
+                        let merged = match merge_history_item(&db_path, &app_state.clipboard_history, received_item) {
+                            Ok(Some(_)) => 1,
+                            Ok(None) => 0,
+                            Err(e) => {
+                                eprintln!("Failed to merge history item from {}: {}", network_msg.device_name, e);
+                                0
+                            }
+                        };
+                        record_history_batch_item(app_handle, merged);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Store functionality disabled - using in-memory storage only for now
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .manage(AppState::default())
+        .setup(|app| {
+            let app_handle = app.handle().clone();
+
+            // Start UDP server for device discovery in an async task
+            let app_handle_for_udp = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                // Handlers need the local device, so don't accept messages before setup has set it
+                wait_until_ready(&app_handle_for_udp.state::<AppState>()).await;
+
+                if let Ok(udp_socket) = UdpSocket::bind("0.0.0.0:51847").await {
+                    println!("UDP server listening on port 51847 for device discovery");
+                    *app_handle_for_udp.state::<AppState>().server_addr.lock_or_recover() = udp_socket.local_addr().ok();
+                    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+                    
+                    loop {
+                        if let Ok((len, addr)) = udp_socket.recv_from(&mut buf).await {
+                            let message_str = String::from_utf8_lossy(&buf[..len]);
+                            println!("Received UDP message from {}: {}", addr, message_str);
+                            
+                            // Try to parse as NetworkMessage
+                            if let Ok(network_msg) = serde_json::from_str::<NetworkMessage>(&message_str) {
+                                app_handle_for_udp.state::<AppState>().network_stats
+                                    .record_received(&network_msg.msg_type, &addr.ip().to_string(), len);
+                                handle_message(&app_handle_for_udp, &udp_socket, network_msg, addr).await;
+                            } else {
+                                println!("Failed to parse network message: {}", message_str);
+                            }
+                        }
+                    }
+                } else {
+                    eprintln!("Failed to bind UDP socket on port 51847");
+                }
+            });
+
+            // Initialize state
+            let state: State<AppState> = app.state();
+            let _clipboard_history = Arc::clone(&state.clipboard_history);
+            let enabled = Arc::clone(&state.enabled);
+            
+            // Initialize database and load existing history
+            match init_database() {
+                Ok(path) => {
+                    println!("Database initialized at: {}", path);
+                    
+                    // Load existing clipboard history from database
+                    match load_clipboard_history_from_db(&path) {
+                        Ok(history) => {
+                            let mut clipboard_state = state.clipboard_history.lock_or_recover();
+                            *clipboard_state = history;
+                            println!("Loaded {} items from database", clipboard_state.len());
+                        },
+                        Err(e) => {
+                            eprintln!("Failed to load clipboard history: {}", e);
+                        }
+                    }
+                    
+                    // Load saved settings
+                    let settings = load_settings_from_db(&path);
+                    *state.transform_rules.lock_or_recover() = compile_transform_rules(&settings.transform_rules);
+                    *state.no_sync_patterns.lock_or_recover() = compile_patterns(&settings.no_sync_patterns);
+                    *state.settings.lock_or_recover() = settings;
+                    
+                    // Store the database path
+                    *state.db_path.lock_or_recover() = Some(path.clone());
+                },
+                Err(e) => {
+                    eprintln!("Failed to initialize database: {}", e);
+                }
+            };
+            
+            let clear_devices_on_startup = state.settings.lock_or_recover().clear_devices_on_startup;
+            let db_path = state.db_path.lock_or_recover().clone();
+            
+            if clear_devices_on_startup {
+                // Clear all cached/stale connected devices on startup
+                {
+                    let mut devices = state.devices.lock_or_recover();
+                    devices.clear();
+                    println!("Cleared all cached connected devices on startup");
+                }
+                
+                // Clear any pending connections
+                {
+                    let mut pending = state.pending_connections.lock_or_recover();
+                    pending.clear();
+                    println!("Cleared all pending connections on startup");
+                }
+                
+                // Clear discovered devices
+                {
+                    let mut discovered = state.discovered_devices.lock_or_recover();
+                    discovered.clear();
+                    println!("Cleared all discovered devices on startup");
+                }
+            } else if let Some(ref db_path) = db_path {
+                // Restore saved pairings as offline until they answer a heartbeat
+                match load_devices_from_db(db_path) {
+                    Ok(saved_devices) => {
+                        let mut devices = state.devices.lock_or_recover();
+                        for mut device in saved_devices {
+                            device.status = DeviceStatus::Offline;
+                            devices.insert(device.id, device);
+                        }
+                        println!("Restored {} saved devices", devices.len());
+                    },
+                    Err(e) => eprintln!("Failed to load saved devices: {}", e),
+                }
+            }
+            
+            // Set enabled to true by default
+            *enabled.lock_or_recover() = true;
+            
+            println!("🚀 Cliped app starting...");
+            println!("✨ Beautiful UI clipboard manager ready!");
+
+            // Start clipboard monitoring after a short delay to ensure runtime is ready
+            let state: State<AppState> = app.state();
+            
+            let app_handle_for_monitor = app_handle.clone();
+            let clipboard_history_clone = Arc::clone(&state.clipboard_history);
+            let last_content_clone = Arc::clone(&state.last_clipboard_content);
+            let enabled_clone = Arc::clone(&state.enabled);
+            let devices_clone = Arc::clone(&state.devices);
+            let local_device_clone = Arc::clone(&state.local_device);
+            tauri::async_runtime::spawn(async move {
+                // Small delay to ensure everything is initialized
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                monitor_clipboard(app_handle_for_monitor, clipboard_history_clone, last_content_clone, enabled_clone, devices_clone, local_device_clone).await;
+            });
+
+            // Load or generate local device info
+            let interface = network_interface(&state);
+            let local_device = load_or_create_local_device(db_path.as_deref(), interface.as_deref());
+            // `devices` holds peers only; this device lives in `local_device`. Older
+            // versions kept it in both, so drop any copy restored from the database.
+            let restored_devices: Vec<Device> = {
+                let mut devices = state.devices.lock_or_recover();
+                devices.remove(&local_device.id);
+                devices.values().cloned().collect()
+            };
+            *state.local_device.lock_or_recover() = Some(local_device.clone());
+            *state.local_ips.lock_or_recover() = all_local_ips();
+            mark_ready(&state);
+            restart_relay(app.handle());
+
+            // Check which restored devices are still reachable
+            if !restored_devices.is_empty() {
+                let devices_for_probe = Arc::clone(&state.devices);
+                let network_stats = Arc::clone(&state.network_stats);
+                let app_handle_for_probe = app_handle.clone();
+                tauri::async_runtime::spawn(async move {
+                    for device in restored_devices {
+                        if probe_device(&network_stats, interface.as_deref(), &local_device, &device).await {
+                            {
+                                let mut devices = devices_for_probe.lock_or_recover();
+                                if let Some(device) = devices.get_mut(&device.id) {
+                                    device.status = DeviceStatus::Connected;
+                                    device.last_seen = get_current_timestamp();
+                                    println!("Restored device is online: {}", device.name);
+                                }
+                            }
+                            sync_latest_item(&app_handle_for_probe, device.id).await;
+                        }
+                    }
+                });
+            }
+
+            // Delete items whose per-item expiry has passed or that are over their type's limit
+            let app_handle_for_item_expiry = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(ITEM_EXPIRY_SWEEP_INTERVAL_SECS)).await;
+                    purge_expired_items(&app_handle_for_item_expiry);
+                    enforce_retention_limits(&app_handle_for_item_expiry);
+                }
+            });
+
+            // Pause sync while away from the trusted networks
+            let app_handle_for_networks = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                wait_until_ready(&app_handle_for_networks.state::<AppState>()).await;
+                loop {
+                    check_trusted_network(&app_handle_for_networks).await;
+                    tokio::time::sleep(Duration::from_secs(TRUSTED_NETWORK_CHECK_SECS)).await;
+                }
+            });
+
+            // Expire connection requests and incoming transfers whose sender never followed up,
+            // and drop traffic counters for peers that are gone
+            let app_handle_for_expiry = app_handle.clone();
+            tauri::async_runtime::spawn(async move {
+                loop {
+                    tokio::time::sleep(Duration::from_secs(PENDING_SWEEP_INTERVAL_SECS)).await;
+                    expire_pending_connections(&app_handle_for_expiry);
+                    expire_stale_incoming_transfers(&app_handle_for_expiry);
+                    prune_peer_stats(&app_handle_for_expiry);
+                }
+            });
+
+            if let Err(e) = build_tray(app) {
+                eprintln!("Failed to create tray icon: {}", e);
+            }
+
+            // Start network discovery service
+            let state_arc = Arc::new(AppState::default()); // We'll initialize properly later
+            let state_for_discovery = Arc::clone(&state_arc);
+            tauri::async_runtime::spawn(async move {
+                handle_network_discovery(app_handle, state_for_discovery).await;
+            });
+
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            // Keep running in the tray instead of quitting when the window is closed
+            if let tauri::WindowEvent::CloseRequested { api, .. } = event {
+                let close_to_tray = window.app_handle().state::<AppState>().settings.lock_or_recover().close_to_tray;
+                if close_to_tray {
+                    api.prevent_close();
+                    let _ = window.hide();
+                }
+            }
+        })
+        .invoke_handler(tauri::generate_handler![
+            get_clipboard_history,
+            get_clipboard_history_paginated,
+            get_clipboard_history_count,
+            get_clipboard_files_count,
+            get_clipboard_files_paginated,
+            search_clipboard,
+            get_search_count,
+            clear_clipboard_history,
+            delete_clipboard_item,
+            set_clipboard_content,
+            toggle_monitoring,
+            is_monitoring_enabled,
+            add_clipboard_item,
+            add_device,
+            remove_device,
+            sync_clipboard,
+            get_local_device,
+            get_connected_devices,
+            send_connection_request,
+            accept_connection,
+            deny_connection,
+            get_pending_connections,
+            set_sync_mode,
+            discover_devices,
+            update_device_name,
+            send_connection_request_to_device,
+            add_file_to_clipboard,
+            get_file_content,
+            save_received_file,
+            save_file_to_path,
+            show_open_dialog,
+            show_save_dialog,
+            get_file_preview,
+            get_files_storage_directory_path,
+            move_clipboard_item_to_top,
+            restore_from_backup,
+            get_most_used_items,
+            save_snippet,
+            list_snippets,
+            expand_snippet,
+            request_full_history,
+            set_capture_length_bounds,
+            set_capture_debounce_ms,
+            set_inline_text_files,
+            is_device_online,
+            set_trim_captured_whitespace,
+            activate_item,
+            set_clear_devices_on_startup,
+            get_settings,
+            reload_history,
+            get_active_transfers,
+            cancel_file_transfer,
+            set_data_directory,
+            get_device_overview,
+            get_current_clipboard,
+            set_require_explicit_trust,
+            trust_device,
+            set_pending_request_expiry_secs,
+            set_item_attribution,
+            factory_reset,
+            set_max_item_size,
+            announce_presence,
+            set_auto_paste,
+            wait_for_ready,
+            set_dedup_window,
+            get_network_stats,
+            set_network_interface,
+            get_file_hex_preview,
+            set_all_sync_modes,
+            get_sync_modes,
+            check_database_integrity,
+            repair_database,
+            get_status_summary,
+            set_item_metadata,
+            get_item_metadata,
+            get_history_by_source_app,
+            get_source_apps,
+            export_file_item,
+            set_discovery_timeout_ms,
+            set_discovery_retries,
+            set_relay_url,
+            is_relay_connected,
+            diagnose_connection,
+            set_sync_latest_on_connect,
+            set_close_to_tray,
+            minimize_to_tray,
+            set_launch_at_startup,
+            get_launch_at_startup,
+            set_display_name,
+            get_display_name,
+            set_item_expiry,
+            cancel_discovery,
+            export_history_as_text,
+            set_notify_content_types,
+            set_quiet_hours,
+            get_item_bytes,
+            set_trusted_networks,
+            get_current_network,
+            search_advanced,
+            get_content_type_counts,
+            add_url_to_clipboard,
+            set_max_devices,
+            get_max_devices,
+            set_device_enabled,
+            get_image_preview,
+            set_thumbnail_max_dimension,
+            set_thumbnail_quality,
+            clear_thumbnail_cache,
+            query_history,
+            add_directory_to_clipboard,
+            extract_file_item,
+            get_history_at,
+            activate_history_at,
+            set_theme,
+            get_theme,
+            reset_device_sync,
+            set_read_only,
+            is_read_only,
+            share_item,
+            set_trivial_content_filter,
+            get_network_info,
+            create_collection,
+            list_collections,
+            move_item_to_collection,
+            delete_collection,
+            set_retention_limit,
+            get_retention_limits,
+            run_maintenance,
+            set_monitored_formats,
+            get_monitored_formats,
+            diff_items,
+            set_item_no_sync,
+            set_no_sync_patterns,
+            get_connection_audit,
+            clear_connection_audit,
+            set_transform_rules,
+            get_transform_rules,
+            set_keep_original_content,
+            get_item_summary,
+            confirm_device_ip,
+            rotate_device_key
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(not(any(target_os = "android", target_os = "ios")))]
+fn main() {
+    run();
+}
+
+#[cfg(feature = "clipboard")]
+async fn monitor_clipboard(
+    app_handle: AppHandle,
+    clipboard_history: ClipboardState,
+    last_content: Arc<Mutex<String>>,
+    enabled: Arc<Mutex<bool>>,
+    devices: Arc<Mutex<HashMap<u32, Device>>>,
+    local_device: Arc<Mutex<Option<Device>>>,
+) {
+    let mut clipboard = match Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(e) => {
+            let message = format!("Failed to access clipboard - skipping clipboard monitoring: {}", e);
+            eprintln!("{}", message);
+            let _ = app_handle.emit("monitoring-error", &message);
+            return;
+        }
+    };
+
+    // Get ignore flag reference (this won't change)
+    let ignore_flag = {
+        let app_state = app_handle.state::<AppState>();
+        Arc::clone(&app_state.ignore_next_clipboard_change)
+    };
+
+    // Check if clipboard is available first
+    if clipboard.get_text().is_err() {
+        let message = "Clipboard not available on this platform - skipping clipboard monitoring";
+        println!("{}", message);
+        let _ = app_handle.emit("monitoring-error", message);
+        return;
+    }
+
+    println!("Clipboard monitoring started!");
+    let _alive = MonitorAliveGuard::start(&app_handle);
+    let mut was_enabled = true;
+    let mut conflicts = ConflictDetector::default();
+
+    loop {
+        sleep(Duration::from_millis(500)).await;
+        
+        // Check if monitoring is enabled, reporting toggles the loop notices
+        let is_enabled = *enabled.lock_or_recover();
+        if is_enabled != was_enabled {
+            was_enabled = is_enabled;
+            let event = if is_enabled { "monitoring-started" } else { "monitoring-stopped" };
+            let _ = app_handle.emit(event, ());
+        }
+        if !is_enabled {
+            continue;
+        }
+        
+        let formats = app_handle.state::<AppState>().settings.lock_or_recover().monitored_formats.clone();
+        let (mut text, html) = match probe_clipboard(&mut clipboard, &formats) {
+            Some(ClipboardProbe::Text(text)) => (text, None),
+            Some(ClipboardProbe::Html { html, text }) => (text, Some(html)),
+            // Files and images are captured whole, without the text pipeline below
+            Some(probe) => {
+                capture_clipboard_probe(&app_handle, &last_content, &ignore_flag, probe).await;
+                continue;
+            },
+            None => {
+                // Whatever this app put on the clipboard is in a format that isn't
+                // monitored, so consume its ignore flag instead of swallowing the next copy
+                *ignore_flag.lock_or_recover() = false;
+                continue;
+            },
+        };
+
+        // Let rapid successive updates settle so only the final value is captured
+        let debounce_ms = app_handle.state::<AppState>().settings.lock_or_recover().capture_debounce_ms;
+        let is_new_content = !*ignore_flag.lock_or_recover() && text != *last_content.lock_or_recover();
+        if debounce_ms > 0 && is_new_content {
+            text = wait_for_stable_clipboard(&mut clipboard, text, debounce_ms).await;
+        }
+
+        let should_process = {
+            let mut last = last_content.lock_or_recover();
+            let mut ignore = ignore_flag.lock_or_recover();
+            
+            // Check if we should ignore this change (it's from a sync)
+            if *ignore {
+                println!("Ignoring clipboard change from sync");
+                *ignore = false;
+                *last = text.clone(); // Update last content to avoid future triggers
+                conflicts.record(&text, true, std::time::Instant::now());
+                false
+            } else if text != *last && !is_clipboard_noise(&text) {
+                println!("New clipboard content detected: {}", text.chars().take(50).collect::<String>());
+                *last = text.clone();
+                if conflicts.record(&text, false, std::time::Instant::now()) {
+                    let message = "The clipboard keeps flipping between recent values; another clipboard manager may be running";
+                    eprintln!("{}", message);
+                    let _ = app_handle.emit("clipboard-conflict-detected", message);
+                }
+                true
+            } else {
+                false
+            }
+        }; // Drop the locks here
+        
+        if should_process {
+            let capture_copied_files = app_handle.state::<AppState>().settings.lock_or_recover().capture_copied_files;
+            if capture_copied_files {
+                if let Some(paths) = copied_file_paths(&mut clipboard, &text) {
+                    // Fall back to capturing the text if none of the files could be stored
+                    if add_copied_files(&app_handle, &paths).await > 0 {
+                        continue;
+                    }
+                }
+            }
+
+            let (trim_whitespace, transform_rules, keep_original) = {
+                let state = app_handle.state::<AppState>();
+                let settings = state.settings.lock_or_recover();
+                let transform_rules = state.transform_rules.lock_or_recover().clone();
+                (settings.trim_captured_whitespace, transform_rules, settings.keep_original_content)
+            };
+            let content = if trim_whitespace { text.trim().to_string() } else { text };
+            let transformed = apply_transform_rules(&content, &transform_rules);
+            let original = (keep_original && transformed != content).then_some(content);
+            let content = transformed;
+            let content_size = content.len() as u64;
+
+            let mut item = ClipboardItem {
+                id: generate_id().to_string(),
+                content,
+                timestamp: get_current_timestamp().to_string(),
+                device: local_item_attribution(&app_handle.state::<AppState>()),
+                content_type: "text".to_string(),
+                file_path: None,
+                file_size: None,
+                file_name: None,
+                use_count: 0,
+                content_size,
+                mime_type: None,
+                metadata: HashMap::new(),
+                expires_at: None,
+                no_sync: false,
+            };
+            if let Some(source_app) = frontmost_app_name().await {
+                item.metadata.insert(SOURCE_APP_METADATA_KEY.to_string(), source_app);
+            }
+            if let Some(html) = html {
+                item.metadata.insert(HTML_METADATA_KEY.to_string(), html);
+            }
+            if let Some(original) = original {
+                item.metadata.insert(ORIGINAL_CONTENT_METADATA_KEY.to_string(), original);
+            }
+
+            // Get db_path and capture settings fresh from app state
+            let app_state = app_handle.state::<AppState>();
+            let db_path = app_state.db_path.lock_or_recover().clone();
+            let (min_length, max_length, trivial_min_length) = {
+                let settings = app_state.settings.lock_or_recover();
+                (settings.capture_min_length, settings.capture_max_length, settings.trivial_content_min_length)
+            };
+            let no_sync_patterns = app_state.no_sync_patterns.lock_or_recover().clone();
+            item.no_sync = matches_any_pattern(&item.content, &no_sync_patterns);
+
+            // Oversized content is neither stored nor synced; undersized content
+            // is kept out of history but still shared with connected devices
+            let content_length = item.content.chars().count();
+            if content_length > max_length {
+                println!("Ignoring clipboard content longer than {} characters", max_length);
+                continue;
+            }
+            if let Err(e) = check_item_size(&app_state, &item) {
+                eprintln!("Refusing clipboard content: {}", e);
+                let _ = app_handle.emit("item-rejected", &e);
+                continue;
+            }
+            let trivial = trivial_min_length.is_some_and(|min| is_trivial_content(&item.content, min));
+            if trivial {
+                println!("Keeping trivial clipboard content out of history");
+            }
+            let store_in_history = content_length >= min_length && !trivial;
+
+            if store_in_history {
+                let dedup_window = app_state.settings.lock_or_recover().dedup_window;
+
+                // Carry the use count over from earlier copies of the same content
+                if let Some(ref db_path) = db_path {
+                    match merge_duplicate_items_in_db(db_path, &item, dedup_window) {
+                        Ok(count) => item.use_count = count,
+                        Err(e) => eprintln!("Failed to merge duplicate clipboard items: {}", e),
+                    }
+                }
+
+                // Add to local history first
+                {
+                    let mut history = clipboard_history.lock_or_recover();
+                    
+                    // Remove duplicates
+                    dedup_history(&mut history, &item.content, dedup_window);
+                    
+                    // Insert at beginning
+                    history.insert(0, item.clone());
+                    
+                    // Limit to 50 items
+                    if history.len() > 50 {
+                        history.truncate(50);
+                    }
+                    
+                    println!("Clipboard history now has {} items", history.len());
+                } // Drop the history lock here
+
+                // Save to database
+                if let Some(ref db_path) = db_path {
+                    match save_clipboard_item_to_db(db_path, &item) {
+                        Ok(_) => println!("✓ Saved clipboard item to database"),
+                        Err(e) => eprintln!("✗ Failed to save clipboard item to database: {}", e),
+                    }
+                } else {
+                    eprintln!("✗ Database not initialized - cannot save clipboard item");
+                }
+            } else {
+                println!("Clipboard content shorter than {} characters - not adding to history", min_length);
+            }
+
+            // Check if we have connected devices before syncing
+            let require_trust = app_state.settings.lock_or_recover().require_explicit_trust;
+            let relay = relay_outbox(&app_state);
+            let has_connected_devices = {
+                let local_id = local_device.lock_or_recover().as_ref().map(|l| l.id);
+                let devices = devices.lock_or_recover();
+                devices.values().any(|device| is_sync_target(device, local_id, require_trust, relay.is_some()))
+            };
+
+            // Only sync if we have connected devices with sync enabled
+            if is_sync_paused(&app_state) {
+                println!("Sync paused on an untrusted network - skipping clipboard sync");
+            } else if has_connected_devices {
+                let results = sync_to_connected_devices(&app_state.network_stats, network_interface(&app_state).as_deref(), &devices, &local_device, &item, require_trust, relay.as_ref()).await;
+                for (device_id, result) in results {
+                    match result {
+                        Ok(()) => mark_device_synced(&app_state, device_id),
+                        Err(e) => report_sync_send_failure(&app_handle, device_id, &e),
+                    }
+                }
+            } else {
+                println!("No connected devices with sync enabled - skipping clipboard sync");
+            }
+
+            // Emit to frontend
+            if store_in_history {
+                let _ = app_handle.emit("clipboard-updated", &item);
+                println!("Emitted clipboard-updated event");
+            }
+        }
+    }
+}
+
+// Re-reads the clipboard until it holds the same value for a full debounce window,
+// discarding intermediate states. Gives up after a few windows so constant churn
+// can't stall the monitor.
+#[cfg(feature = "clipboard")]
+async fn wait_for_stable_clipboard(clipboard: &mut Clipboard, initial: String, debounce_ms: u64) -> String {
+    const MAX_WINDOWS: u32 = 10;
+    let mut candidate = initial;
+
+    for _ in 0..MAX_WINDOWS {
+        sleep(Duration::from_millis(debounce_ms)).await;
+
+        match clipboard.get_text() {
+            Ok(current) if current != candidate => {
+                println!("Clipboard changed again within {}ms - discarding intermediate value", debounce_ms);
+                candidate = current;
+            }
+            _ => break,
+        }
+    }
+
+    candidate
+}
+
+// Name of the application in the foreground, which is where a fresh copy came
+// from. None where the platform offers no way to ask (e.g. Wayland).
+#[cfg(target_os = "macos")]
+async fn frontmost_app_name() -> Option<String> {
+    use objc2_app_kit::NSWorkspace;
+
+    let app = NSWorkspace::sharedWorkspace().frontmostApplication()?;
+    app.localizedName().map(|name| name.to_string())
+}
+
+#[cfg(target_os = "windows")]
+async fn frontmost_app_name() -> Option<String> {
+    use windows_sys::Win32::Foundation::CloseHandle;
+    use windows_sys::Win32::System::Threading::{
+        OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+    use windows_sys::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+    // SAFETY: every pointer passed points at a live local buffer, and the
+    // process handle is closed before returning
+    let exe_path = unsafe {
+        let window = GetForegroundWindow();
+        if window.is_null() {
+            return None;
+        }
+        let mut pid = 0u32;
+        GetWindowThreadProcessId(window, &mut pid);
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process.is_null() {
+            return None;
+        }
+        let mut buf = [0u16; 260];
+        let mut len = buf.len() as u32;
+        let ok = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, buf.as_mut_ptr(), &mut len);
+        CloseHandle(process);
+        if ok == 0 {
+            return None;
+        }
+        String::from_utf16_lossy(&buf[..len as usize])
+    };
+
+    // "C:\Program Files\App\app.exe" -> "app"
+    std::path::Path::new(&exe_path).file_stem().map(|stem| stem.to_string_lossy().to_string())
+}
+
+// X11 only, through xdotool when it is installed. The subprocess runs on the
+// blocking pool so a slow xdotool doesn't stall the capture loop.
+#[cfg(target_os = "linux")]
+async fn frontmost_app_name() -> Option<String> {
+    tauri::async_runtime::spawn_blocking(|| {
+        let output = std::process::Command::new("xdotool")
+            .args(["getactivewindow", "getwindowpid"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())?;
+        let pid = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if pid.is_empty() || !pid.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let name = std::fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+        Some(name.trim().to_string()).filter(|name| !name.is_empty())
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+async fn frontmost_app_name() -> Option<String> {
+    None
+}
+
+fn is_sync_paused(state: &AppState) -> bool {
+    *state.sync_paused_untrusted.lock_or_recover()
+}
+
+// Pauses or resumes sync as the machine moves on or off the trusted networks,
+// emitting `sync-paused-untrusted` (with the current network) or `sync-resumed`
+async fn check_trusted_network(app_handle: &AppHandle) {
+    let trusted = app_handle.state::<AppState>().settings.lock_or_recover().trusted_networks.clone();
+    let (paused, network) = if trusted.is_empty() {
+        (false, None)
+    } else {
+        let current = tauri::async_runtime::spawn_blocking(current_network_ids).await.unwrap_or_default();
+        let is_trusted = current.iter().any(|id| trusted.iter().any(|t| t.eq_ignore_ascii_case(id)));
+        (!is_trusted, current.into_iter().next())
+    };
+
+    let state = app_handle.state::<AppState>();
+    let was_paused = std::mem::replace(&mut *state.sync_paused_untrusted.lock_or_recover(), paused);
+    if paused && !was_paused {
+        println!("Pausing sync on untrusted network {:?}", network);
+        let _ = app_handle.emit("sync-paused-untrusted", network);
+    } else if !paused && was_paused {
+        println!("Back on a trusted network - resuming sync");
+        let _ = app_handle.emit("sync-resumed", ());
+    }
+}
+
+// Identifiers of the network this machine is on: the Wi-Fi SSID if connected
+// to one, then the default gateway's MAC address as a fingerprint for wired networks
+fn current_network_ids() -> Vec<String> {
+    [current_wifi_ssid(), default_gateway_mac()].into_iter().flatten().collect()
+}
+
+#[cfg(any(target_os = "macos", target_os = "windows", target_os = "linux"))]
+fn command_stdout(program: &str, args: &[&str]) -> Option<String> {
+    let output = std::process::Command::new(program).args(args).output().ok()
+        .filter(|output| output.status.success())?;
+    Some(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn current_wifi_ssid() -> Option<String> {
+    if let Some(ssid) = command_stdout("iwgetid", &["-r"]).map(|out| out.trim().to_string()).filter(|ssid| !ssid.is_empty()) {
+        return Some(ssid);
+    }
+    // NetworkManager lists every visible network; the active one is marked "yes"
+    command_stdout("nmcli", &["-t", "-f", "active,ssid", "dev", "wifi"])?
+        .lines()
+        .find_map(|line| line.strip_prefix("yes:").map(str::to_string))
+        .filter(|ssid| !ssid.is_empty())
+}
+
+#[cfg(target_os = "macos")]
+fn current_wifi_ssid() -> Option<String> {
+    command_stdout("networksetup", &["-getairportnetwork", "en0"])?
+        .trim()
+        .strip_prefix("Current Wi-Fi Network: ")
+        .map(str::to_string)
+}
+
+#[cfg(target_os = "windows")]
+fn current_wifi_ssid() -> Option<String> {
+    // "    SSID                   : HomeWifi" (BSSID lines are the access point's MAC)
+    command_stdout("netsh", &["wlan", "show", "interfaces"])?
+        .lines()
+        .map(str::trim)
+        .find(|line| line.starts_with("SSID"))
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, ssid)| ssid.trim().to_string())
+        .filter(|ssid| !ssid.is_empty())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
+fn current_wifi_ssid() -> Option<String> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn default_gateway_mac() -> Option<String> {
+    // /proc/net/route holds the gateway as little-endian hex, e.g. "0101A8C0" for 192.168.1.1
+    let routes = std::fs::read_to_string("/proc/net/route").ok()?;
+    let gateway_hex = routes.lines().skip(1).find_map(|line| {
+        let mut fields = line.split_whitespace().skip(1);
+        match (fields.next(), fields.next()) {
+            (Some("00000000"), Some(gateway)) => Some(gateway.to_string()),
+            _ => None,
+        }
+    })?;
+    let gateway = std::net::Ipv4Addr::from(u32::from_str_radix(&gateway_hex, 16).ok()?.swap_bytes()).to_string();
+
+    // IP address, HW type, flags, HW address, ...
+    std::fs::read_to_string("/proc/net/arp").ok()?
+        .lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.first() == Some(&gateway.as_str()))
+        .and_then(|fields| fields.get(3).map(|mac| mac.to_string()))
+        .filter(|mac| mac != "00:00:00:00:00:00")
+}
+
+#[cfg(target_os = "macos")]
+fn default_gateway_mac() -> Option<String> {
+    let gateway = command_stdout("route", &["-n", "get", "default"])?
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("gateway: ").map(str::to_string))?;
+    // "? (192.168.1.1) at a4:2b:b0:11:22:33 on en0 ifscope [ethernet]"
+    let arp = command_stdout("arp", &["-n", &gateway])?;
+    let mac = arp.split(" at ").nth(1)?.split_whitespace().next()?;
+    Some(mac.to_string()).filter(|mac| mac.contains(':'))
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn default_gateway_mac() -> Option<String> {
+    None
+}
+
+// What one poll found on the clipboard, in the first monitored format that had content
+#[cfg(feature = "clipboard")]
+enum ClipboardProbe {
+    Files(Vec<std::path::PathBuf>),
+    Html { html: String, text: String },
+    Text(String),
+    Image(arboard::ImageData<'static>),
+}
+
+#[cfg(feature = "clipboard")]
+impl ClipboardProbe {
+    // Stands in for the content in `last_clipboard_content`, so a format that
+    // isn't text can still be told apart from what was seen last
+    fn signature(&self) -> String {
+        use std::hash::{Hash, Hasher};
+        match self {
+            ClipboardProbe::Files(paths) => {
+                let paths: Vec<String> = paths.iter().map(|path| path.to_string_lossy().to_string()).collect();
+                format!("files:{}", paths.join("\n"))
+            },
+            ClipboardProbe::Html { text, .. } | ClipboardProbe::Text(text) => text.clone(),
+            ClipboardProbe::Image(image) => {
+                let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                image.bytes.hash(&mut hasher);
+                format!("image:{}x{}:{:x}", image.width, image.height, hasher.finish())
+            },
+        }
+    }
+}
+
+// Richer formats first, so rich text isn't captured as plain text. Text comes
+// before images: apps like spreadsheets put both, and the text is what was meant.
+#[cfg(feature = "clipboard")]
+const CAPTURE_PRIORITY: [ClipboardFormat; 4] = [ClipboardFormat::Files, ClipboardFormat::Html, ClipboardFormat::Text, ClipboardFormat::Image];
+
+// Reads only the monitored formats, stopping at the first one with content
+#[cfg(feature = "clipboard")]
+fn probe_clipboard(clipboard: &mut Clipboard, formats: &[ClipboardFormat]) -> Option<ClipboardProbe> {
+    for format in CAPTURE_PRIORITY.iter().filter(|format| formats.contains(format)) {
+        let probe = match format {
+            ClipboardFormat::Files => clipboard.get().file_list().ok()
+                .filter(|paths| !paths.is_empty())
+                .map(ClipboardProbe::Files),
+            // The plain text goes into history; the HTML is kept alongside it
+            ClipboardFormat::Html => clipboard.get().html().ok()
+                .and_then(|html| clipboard.get_text().ok().map(|text| ClipboardProbe::Html { html, text })),
+            ClipboardFormat::Text => clipboard.get_text().ok().map(ClipboardProbe::Text),
+            ClipboardFormat::Image => clipboard.get_image().ok().map(ClipboardProbe::Image),
+        };
+        if probe.is_some() {
+            return probe;
+        }
+    }
+    None
+}
+
+// Captures copied files or an image unless it's what was seen last or
+// something this app put on the clipboard itself
+#[cfg(feature = "clipboard")]
+async fn capture_clipboard_probe(app_handle: &AppHandle, last_content: &Mutex<String>, ignore_flag: &Mutex<bool>, probe: ClipboardProbe) {
+    let signature = probe.signature();
+    let is_new = {
+        let mut last = last_content.lock_or_recover();
+        let changed = *last != signature;
+        *last = signature;
+        !std::mem::take(&mut *ignore_flag.lock_or_recover()) && changed
+    };
+    if !is_new {
+        return;
+    }
+
+    match probe {
+        ClipboardProbe::Files(paths) => {
+            add_copied_files(app_handle, &paths).await;
+        },
+        ClipboardProbe::Image(image) => match capture_clipboard_image(app_handle, image).await {
+            Ok(item) => emit_item_added(app_handle, &item),
+            Err(e) => {
+                eprintln!("Failed to capture copied image: {}", e);
+                let _ = app_handle.emit("item-rejected", &e);
+            },
+        },
+        ClipboardProbe::Html { .. } | ClipboardProbe::Text(_) => {},
+    }
+}
+
+// Stores a copied image as a PNG image item and syncs it like a file
+#[cfg(feature = "clipboard")]
+async fn capture_clipboard_image(app_handle: &AppHandle, image: arboard::ImageData<'static>) -> Result<ClipboardItem, String> {
+    let (width, height) = (image.width as u32, image.height as u32);
+    let rgba = image::RgbaImage::from_raw(width, height, image.bytes.into_owned())
+        .ok_or("Copied image has inconsistent dimensions".to_string())?;
+    let mut png = Vec::new();
+    rgba.write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to encode image: {}", e))?;
+    let size = png.len() as u64;
+    if size > MAX_FILE_ITEM_SIZE {
+        return Err(format!("Copied image is {} bytes, over the {} byte limit", size, MAX_FILE_ITEM_SIZE));
+    }
+
+    let state = app_handle.state::<AppState>();
+    let id = generate_id().to_string();
+    let file_name = format!("image-{}.png", id);
+    let stored_path = store_file_content(&png, &file_name, &id)?;
+
+    let mut item = ClipboardItem {
+        id,
+        content: format!("Image {}x{}", width, height),
+        timestamp: get_current_timestamp().to_string(),
+        device: local_item_attribution(&state),
+        content_type: "image".to_string(),
+        file_path: Some(stored_path),
+        file_size: Some(size),
+        file_name: Some(file_name),
+        use_count: 0,
+        content_size: size,
+        mime_type: Some("image/png".to_string()),
+        metadata: HashMap::new(),
+        expires_at: None,
+        no_sync: false,
+    };
+    if let Some(source_app) = frontmost_app_name().await {
+        item.metadata.insert(SOURCE_APP_METADATA_KEY.to_string(), source_app);
+    }
+
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        save_clipboard_item_to_db(&db_path, &item)?;
+    }
+    println!("Captured copied image: {}x{} ({} bytes)", width, height, size);
+
+    sync_file_to_connected_devices(app_handle, &state.devices, &state.local_device, &item, &png).await;
+    Ok(item)
+}
+
+// Files the clipboard refers to, either as a platform file list or as text made
+// up entirely of file:// URIs (one per line, as file managers put them)
+#[cfg(feature = "clipboard")]
+fn copied_file_paths(clipboard: &mut Clipboard, text: &str) -> Option<Vec<std::path::PathBuf>> {
+    if let Ok(paths) = clipboard.get().file_list() {
+        if !paths.is_empty() {
+            return Some(paths);
+        }
+    }
+
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    if lines.is_empty() || !lines.iter().all(|line| line.starts_with("file://")) {
+        return None;
+    }
+    lines.iter().map(|uri| file_uri_to_path(uri)).collect()
+}
+
+// Converts "file:///home/me/My%20File.txt" (or "file://localhost/...") to a path
+fn file_uri_to_path(uri: &str) -> Option<std::path::PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    let rest = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !rest.starts_with('/') {
+        return None; // Remote hosts aren't reachable as local files
+    }
+
+    let raw = rest.as_bytes();
+    let mut decoded = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        let escaped = (raw[i] == b'%' && i + 2 < raw.len())
+            .then(|| std::str::from_utf8(&raw[i + 1..i + 3]).ok())
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            None => {
+                decoded.push(raw[i]);
+                i += 1;
+            },
+        }
+    }
+    let path = String::from_utf8(decoded).ok()?;
+
+    // "/C:/Users/..." on Windows
+    #[cfg(windows)]
+    let path = path.strip_prefix('/').filter(|p| p.get(1..2) == Some(":")).map(str::to_string).unwrap_or(path);
+
+    Some(std::path::PathBuf::from(path))
+}
+
+// Stores each copied file as a file item, skipping ones that are missing or
+// can't be read. Returns how many were added.
+#[cfg(feature = "clipboard")]
+async fn add_copied_files(app_handle: &AppHandle, paths: &[std::path::PathBuf]) -> usize {
+    let mut added = 0;
+    for path in paths {
+        if !path.is_file() {
+            println!("Skipping copied file that doesn't exist: {}", path.display());
+            continue;
+        }
+        match add_file_item(app_handle, &app_handle.state::<AppState>(), &path.to_string_lossy()).await {
+            Ok(item) => {
+                emit_item_added(app_handle, &item);
+                added += 1;
+            },
+            Err(e) => eprintln!("Failed to add copied file {}: {}", path.display(), e),
+        }
+    }
+    added
+}
+
+// Marks the monitor loop as running for its lifetime. Dropping it, whether
+// monitor_clipboard returns or the task panics, reports the loop as dead.
+#[cfg(feature = "clipboard")]
+struct MonitorAliveGuard(AppHandle);
+
+#[cfg(feature = "clipboard")]
+impl MonitorAliveGuard {
+    fn start(app_handle: &AppHandle) -> Self {
+        *app_handle.state::<AppState>().monitor_alive.lock_or_recover() = true;
+        let _ = app_handle.emit("monitoring-started", ());
+        MonitorAliveGuard(app_handle.clone())
+    }
+}
+
+#[cfg(feature = "clipboard")]
+impl Drop for MonitorAliveGuard {
+    fn drop(&mut self) {
+        *self.0.state::<AppState>().monitor_alive.lock_or_recover() = false;
+        eprintln!("Clipboard monitoring loop stopped");
+        let _ = self.0.emit("monitoring-stopped", ());
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+async fn monitor_clipboard(
+    app_handle: AppHandle,
+    _clipboard_history: ClipboardState,
+    _last_content: Arc<Mutex<String>>,
+    _enabled: Arc<Mutex<bool>>,
+    _devices: Arc<Mutex<HashMap<u32, Device>>>,
+    _local_device: Arc<Mutex<Option<Device>>>,
+) {
+    println!("Clipboard monitoring not available on this platform (mobile)");
+    let _ = app_handle.emit("monitoring-error", "Clipboard monitoring not available on this platform");
+    // On mobile, clipboard monitoring is handled differently or not available
+    // This function exists to satisfy the type system but does nothing
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+    }
+}
+
+// Name recorded as the `device` of items captured on this machine. Only new
+// items use it; existing rows keep whatever they were saved with.
+fn local_item_attribution(state: &AppState) -> String {
+    let attribution = state.settings.lock_or_recover().item_attribution;
+    let hostname = || whoami::fallible::hostname().unwrap_or("Unknown".to_string());
+
+    match attribution {
+        ItemAttribution::Hostname => hostname(),
+        ItemAttribution::DeviceName => state.local_device.lock_or_recover()
+            .as_ref()
+            .map(|device| device.display_name.clone().unwrap_or_else(|| device.name.clone()))
+            .unwrap_or_else(hostname),
+    }
+}
+
+// Sends the newest history item to a device that just (re)connected, so both
+// share the current clipboard. Only the side that initiated the connection calls
+// this, which keeps the two devices from swapping clipboards.
+async fn sync_latest_item<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32) {
+    let app_state = app_handle.state::<AppState>();
+    let (enabled, require_trust) = {
+        let settings = app_state.settings.lock_or_recover();
+        (settings.sync_latest_on_connect, settings.require_explicit_trust)
+    };
+    if !enabled || is_sync_paused(&app_state) {
+        return;
+    }
+
+    let device = app_state.devices.lock_or_recover().get(&device_id).cloned();
+    let local = app_state.local_device.lock_or_recover().clone();
+    let (Some(device), Some(local)) = (device, local) else {
+        return;
+    };
+    if matches!(device.sync_mode, SyncMode::Disabled) || !is_sync_allowed(&device, require_trust) {
+        return;
+    }
+
+    let Some(latest) = app_state.clipboard_history.lock_or_recover().first().cloned() else {
+        return;
+    };
+    if latest.no_sync {
+        println!("Latest item is marked no-sync - not syncing it to {} on connect", device.name);
+        return;
+    }
+    // File contents travel separately as chunked transfers
+    if latest.content_type == "file" {
+        println!("Latest item is a file - not syncing it to {} on connect", device.name);
+        return;
+    }
+
+    let message = NetworkMessage {
+        msg_type: MessageType::ClipboardSync,
+        device_id: local.id,
+        device_name: local.name.clone(),
+        data: Some(serde_json::to_string(&latest).unwrap_or_default()),
+        display_name: None,
+    };
+    if let Ok(socket) = bind_udp_socket(network_interface(&app_state).as_deref()).await {
+        let message_json = serde_json::to_string(&message).unwrap_or_default();
+        let target_addr = format!("{}:51847", device.ip);
+        match send_datagram(&app_state.network_stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&device.ip)).await {
+            Ok(()) => {
+                mark_device_synced(&app_state, device.id);
+                println!("Synced latest item to {} on connect", device.name);
+            },
+            Err(e) => report_sync_send_failure(app_handle, device.id, &e),
+        }
+    }
+}
+
+// Puts an item received from a peer on the system clipboard unless it is
+// already there; the monitor then adds it to history
+#[cfg(feature = "clipboard")]
+fn apply_synced_clipboard_item(app_state: &AppState, synced_item: &ClipboardItem, device_name: &str) {
+    // Check if this content is different from what's currently in clipboard
+    let should_update = {
+        if let Ok(mut clipboard) = Clipboard::new() {
+            if let Ok(current_text) = clipboard.get_text() {
+                current_text != synced_item.content
+            } else {
+                true // If we can't read clipboard, assume we should update
+            }
+        } else {
+            true // If we can't access clipboard, assume we should update
+        }
+    };
+    
+    if should_update {
+        // Set ignore flag to prevent sync loop - the monitor will handle adding to history
+        {
+            let mut ignore = app_state.ignore_next_clipboard_change.lock_or_recover();
+            *ignore = true;
+            println!("Setting ignore flag for synced content from {}", device_name);
+        }
+        
+        // Set the clipboard content - the monitor will detect this and add to history
+        if let Ok(mut clipboard) = Clipboard::new() {
+            if let Err(e) = clipboard.set_text(&synced_item.content) {
+                eprintln!("Failed to set clipboard content: {}", e);
+            } else {
+                println!("Set clipboard content from connected device {}: {}", 
+                        device_name, 
+                        synced_item.content.chars().take(50).collect::<String>());
+            }
+        }
+    } else {
+        println!("Synced content is same as current clipboard, skipping update");
+    }
+}
+
+// Records a successful clipboard exchange with a paired device
+fn mark_device_synced(state: &AppState, device_id: u32) {
+    let updated = {
+        let mut devices = state.devices.lock_or_recover();
+        devices.get_mut(&device_id).map(|device| {
+            device.last_synced = Some(get_current_timestamp());
+            device.clone()
+        })
+    };
+    if let Some(device) = updated {
+        persist_device(state, &device);
+    }
+}
+
+// Sends one datagram and counts it, or its failure, in `stats`. A short write
+// is a failure too, since the receiver can't parse a truncated message.
+async fn send_datagram<A>(stats: &NetworkStats, socket: &UdpSocket, msg_type: &MessageType, payload: &str, target: A, peer: Option<&str>) -> Result<(), String>
+where
+    A: tokio::net::ToSocketAddrs + std::fmt::Display + Send + Sync,
+{
+    let result = match socket.send_to(payload.as_bytes(), &target).await {
+        Ok(sent) if sent == payload.len() => Ok(()),
+        Ok(sent) => Err(format!("Only {} of {} bytes of {:?} sent to {}", sent, payload.len(), msg_type, target)),
+        Err(e) => Err(format!("Failed to send {:?} to {}: {}", msg_type, target, e)),
+    };
+    match result {
+        Ok(()) => stats.record_sent(msg_type, peer, payload.len()),
+        Err(_) => stats.record_send_failure(msg_type, peer),
+    }
+    result
+}
+
+// Tells the frontend an item couldn't be sent to a device
+fn report_sync_send_failure<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, error: &str) {
+    eprintln!("Sync to device {} failed: {}", device_id, error);
+    let _ = app_handle.emit("sync-send-failed", serde_json::json!({
+        "device_id": device_id,
+        "error": error,
+    }));
+}
+
+// Sends a fresh datagram to a device's sync port
+async fn send_message_to_device(stats: &NetworkStats, interface: Option<&str>, device_ip: &str, message: &NetworkMessage) -> Result<(), String> {
+    let socket = bind_udp_socket(interface).await
+        .map_err(|_| "Failed to create UDP socket".to_string())?;
+    let message_json = serde_json::to_string(message).map_err(|e| e.to_string())?;
+    let target_addr = format!("{}:51847", device_ip);
+    send_datagram(stats, &socket, &message.msg_type, &message_json, &target_addr, Some(device_ip)).await
+}
+
+// Pages through the local history and sends every item to the requesting device
+async fn send_history_to_device(stats: &NetworkStats, interface: Option<&str>, db_path: &str, local: &Device, requester: &Device) {
+    const PAGE_SIZE: u32 = 50;
+    let mut offset = 0;
+    let mut sent = 0;
+
+    loop {
+        let page = match load_clipboard_history_paginated(db_path, offset, PAGE_SIZE) {
+            Ok(page) => page,
+            Err(e) => {
+                eprintln!("Failed to load history page for {}: {}", requester.name, e);
+                break;
+            }
+        };
+
+        if page.is_empty() {
+            break;
+        }
+
+        for item in page.iter().filter(|item| !item.no_sync) {
+            let message = NetworkMessage {
+                msg_type: MessageType::HistoryResponse,
+                device_id: local.id,
+                device_name: local.name.clone(),
+                data: Some(serde_json::to_string(item).unwrap_or_default()),
+                display_name: None,
+            };
+
+            match send_message_to_device(stats, interface, &requester.ip, &message).await {
+                Ok(()) => sent += 1,
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+
+        offset += page.len() as u32;
+        // Give the receiver a moment to keep up between pages
+        sleep(Duration::from_millis(20)).await;
+    }
+
+    println!("Sent {} history items to {} at {}", sent, requester.name, requester.ip);
+}
+
+fn history_item_exists_in_db(db_path: &str, item: &ClipboardItem) -> Result<bool, String> {
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+
+    let count: u32 = conn.query_row(
+        "SELECT COUNT(*) FROM clipboard_items WHERE id = ?1 OR (content = ?2 AND content_type = ?3)",
+        [&item.id, &item.content, &item.content_type],
+        |row| row.get(0)
+    ).map_err(|e| e.to_string())?;
+
+    Ok(count > 0)
+}
+
+// Stores an item received from a peer's history unless we already have it.
+// Returns the stored item, or None when it was a duplicate.
+fn merge_history_item(
+    db_path: &str,
+    clipboard_history: &ClipboardState,
+    item: ClipboardItem,
+) -> Result<Option<ClipboardItem>, String> {
+    if !is_history_visible(&item.content_type) || history_item_exists_in_db(db_path, &item)? {
+        return Ok(None);
+    }
+
+    save_clipboard_item_to_db(db_path, &item)?;
+
+    {
+        let mut history = clipboard_history.lock_or_recover();
+        history.retain(|existing| existing.content != item.content);
+        history.push(item.clone());
+        // Keep the in-memory view ordered newest first
+        history.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+        history.truncate(50);
+    }
+
+    Ok(Some(item))
+}
+
+// Counts an item from an incoming history stream. Instead of one
+// `clipboard-updated` per item, the stream produces a single
+// `history-batch-updated` carrying the number of new items once it goes quiet.
+fn record_history_batch_item<R: Runtime>(app_handle: &AppHandle<R>, merged: usize) {
+    let state = app_handle.state::<AppState>();
+    let mut batch = state.history_batch.lock_or_recover();
+
+    if let Some(batch) = batch.as_mut() {
+        batch.merged += merged;
+        batch.last_item_at = tokio::time::Instant::now();
+        return;
+    }
+
+    *batch = Some(HistoryBatch {
+        merged,
+        last_item_at: tokio::time::Instant::now(),
+    });
+
+    let app_handle = app_handle.clone();
+    tauri::async_runtime::spawn(async move {
+        let quiet = Duration::from_millis(HISTORY_BATCH_QUIET_MS);
+        loop {
+            tokio::time::sleep(quiet / 2).await;
+
+            let state = app_handle.state::<AppState>();
+            let finished = {
+                let mut batch = state.history_batch.lock_or_recover();
+                match batch.as_ref() {
+                    Some(current) if current.last_item_at.elapsed() >= quiet => batch.take(),
+                    Some(_) => None,
+                    None => break,
+                }
+            };
+
+            if let Some(finished) = finished {
+                if finished.merged > 0 {
+                    let _ = app_handle.emit("history-batch-updated", finished.merged);
+                }
+                println!("History sync finished with {} new items", finished.merged);
+                break;
+            }
+        }
+    });
+}
+
+// A peer cancelled a transfer; it may be either end of it
+fn handle_transfer_cancel<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, cancel: FileTransferEnd) {
+    let state = app_handle.state::<AppState>();
+    let key = (cancel.file_id.clone(), device_id);
+    if !discard_incoming_transfer(app_handle, &cancel.file_id, device_id)
+        && state.active_transfers.lock_or_recover().contains_key(&key)
+    {
+        state.cancelled_transfers.lock_or_recover().insert((cancel.file_id, Some(device_id)));
+    }
+}
+
+fn is_transfer_cancelled(state: &AppState, file_id: &str, device_id: u32) -> bool {
+    let cancelled = state.cancelled_transfers.lock_or_recover();
+    cancelled.contains(&(file_id.to_string(), None)) || cancelled.contains(&(file_id.to_string(), Some(device_id)))
+}
+
+// Streams a file to one device as a FileTransfer header, a series of
+// FileTransferChunk messages and a closing FileTransferComplete
+async fn send_file_to_device(
+    app_handle: &AppHandle,
+    local: &Device,
+    device: &Device,
+    item: &ClipboardItem,
+    file_content: &[u8]
+) -> Result<(), String> {
+    let network_stats = Arc::clone(&app_handle.state::<AppState>().network_stats);
+    let route = PeerRoute::for_device(&app_handle.state::<AppState>(), device).await?;
+    let total_bytes = file_content.len() as u64;
+
+    let message = |msg_type: MessageType, data: String| NetworkMessage {
+        msg_type,
+        device_id: local.id,
+        device_name: local.name.clone(),
+        data: Some(data),
+        display_name: None,
+    };
+
+    let header = FileTransferHeader {
+        item: item.clone(),
+        total_size: total_bytes,
+        chunk_count: file_content.len().div_ceil(FILE_CHUNK_SIZE),
+    };
+    if is_transfer_cancelled(&app_handle.state::<AppState>(), &item.id, device.id) {
+        return Err("Transfer cancelled".to_string());
+    }
+
+    let header_json = serde_json::to_string(&header).map_err(|e| e.to_string())?;
+    route.send(&network_stats, device, message(MessageType::FileTransfer, header_json)).await?;
+
+    let mut progress = FileTransferProgress {
+        file_id: item.id.clone(),
+        file_name: item.file_name.clone().unwrap_or_default(),
+        device_id: device.id,
+        direction: TransferDirection::Send,
+        bytes_transferred: 0,
+        total_bytes,
+    };
+    report_transfer_progress(app_handle, &progress);
+
+    for (index, bytes) in file_content.chunks(FILE_CHUNK_SIZE).enumerate() {
+        if is_transfer_cancelled(&app_handle.state::<AppState>(), &item.id, device.id) {
+            let cancel = FileTransferEnd { file_id: item.id.clone() };
+            let cancel_json = serde_json::to_string(&cancel).map_err(|e| e.to_string())?;
+            let _ = route.send(&network_stats, device, message(MessageType::FileTransferCancel, cancel_json)).await;
+            return Err("Transfer cancelled".to_string());
+        }
+
+        let chunk = FileChunk {
+            file_id: item.id.clone(),
+            index,
+            data: general_purpose::STANDARD.encode(bytes),
+        };
+        let chunk_json = serde_json::to_string(&chunk).map_err(|e| e.to_string())?;
+        route.send(&network_stats, device, message(MessageType::FileTransferChunk, chunk_json)).await?;
+
+        progress.bytes_transferred += bytes.len() as u64;
+        report_transfer_progress(app_handle, &progress);
+
+        // Pace the chunks so the receiver's socket buffer doesn't overflow
+        tokio::time::sleep(Duration::from_millis(2)).await;
+    }
+
+    let end = FileTransferEnd { file_id: item.id.clone() };
+    let end_json = serde_json::to_string(&end).map_err(|e| e.to_string())?;
+    route.send(&network_stats, device, message(MessageType::FileTransferComplete, end_json)).await?;
+
+    Ok(())
+}
+
+// How messages reach a paired device: directly over UDP while it answers on
+// the LAN, otherwise through the relay
+enum PeerRoute {
+    Udp { socket: UdpSocket, target_addr: String },
+    Relay(RelayOutbox),
+}
+
+impl PeerRoute {
+    async fn for_device(state: &AppState, device: &Device) -> Result<PeerRoute, String> {
+        if !matches!(device.status, DeviceStatus::Connected) && device.relay_key.is_some() {
+            if let Some(outbox) = relay_outbox(state) {
+                return Ok(PeerRoute::Relay(outbox));
+            }
+        }
+        let interface = network_interface(state);
+        let socket = bind_udp_socket(interface.as_deref()).await.map_err(|e| e.to_string())?;
+        Ok(PeerRoute::Udp { socket, target_addr: format!("{}:51847", device.ip) })
+    }
+
+    async fn send(&self, stats: &NetworkStats, device: &Device, message: NetworkMessage) -> Result<(), String> {
+        match self {
+            PeerRoute::Udp { socket, target_addr } => {
+                let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+                send_datagram(stats, socket, &message.msg_type, &message_json, target_addr, Some(&device.ip)).await
+            },
+            PeerRoute::Relay(outbox) => outbox
+                .send(RelayFrame::forward(device, message)?)
+                .map_err(|_| "Relay connection closed".to_string()),
+        }
+    }
+}
+
+// Records the latest progress of a transfer and forwards it to the frontend
+fn report_transfer_progress<R: Runtime>(app_handle: &AppHandle<R>, progress: &FileTransferProgress) {
+    let state = app_handle.state::<AppState>();
+    state.active_transfers.lock_or_recover()
+        .insert((progress.file_id.clone(), progress.device_id), progress.clone());
+    let _ = app_handle.emit("file-transfer-progress", progress);
+}
+
+// Drops a transfer from the active set and emits its final outcome
+fn finish_transfer<R: Runtime>(app_handle: &AppHandle<R>, file_id: &str, device_id: u32, result: Result<(), String>) {
+    let state = app_handle.state::<AppState>();
+    let progress = state.active_transfers.lock_or_recover()
+        .remove(&(file_id.to_string(), device_id));
+
+    match result {
+        Ok(()) => {
+            if let Some(progress) = progress {
+                let _ = app_handle.emit("file-transfer-complete", &progress);
+            }
+        },
+        Err(error) => {
+            let _ = app_handle.emit("file-transfer-failed", serde_json::json!({
+                "file_id": file_id,
+                "device_id": device_id,
+                "error": error,
+            }));
+        }
+    }
+}
+
+// Whether a message claiming to be from `device_id` comes from a connected device at its known IP
+fn is_connected_sender(state: &AppState, device_id: u32, sender_ip: &str) -> bool {
+    state.devices.lock_or_recover()
+        .get(&device_id)
+        .map(|device| matches!(device.status, DeviceStatus::Connected) && device.ip == sender_ip)
+        .unwrap_or(false)
+}
+
+fn begin_incoming_transfer<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, header: FileTransferHeader) {
+    // Checked before anything is allocated for the transfer
+    if header.total_size > MAX_FILE_ITEM_SIZE {
+        eprintln!("Ignoring file transfer {} of {} bytes, over the {} byte limit", header.item.id, header.total_size, MAX_FILE_ITEM_SIZE);
+        return;
+    }
+    if header.chunk_count != (header.total_size as usize).div_ceil(FILE_CHUNK_SIZE) {
+        eprintln!("Ignoring file transfer {} with inconsistent chunk count", header.item.id);
+        return;
+    }
+
+    let progress = FileTransferProgress {
+        file_id: header.item.id.clone(),
+        file_name: header.item.file_name.clone().unwrap_or_default(),
+        device_id,
+        direction: TransferDirection::Receive,
+        bytes_transferred: 0,
+        total_bytes: header.total_size,
+    };
+
+    let state = app_handle.state::<AppState>();
+    state.incoming_transfers.lock_or_recover().insert(header.item.id.clone(), IncomingFileTransfer {
+        item: header.item,
+        device_id,
+        total_size: header.total_size,
+        chunks: vec![None; header.chunk_count],
+        last_chunk_at: std::time::Instant::now(),
+    });
+    report_transfer_progress(app_handle, &progress);
+}
+
+fn receive_file_chunk<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, chunk: FileChunk) {
+    let Ok(bytes) = general_purpose::STANDARD.decode(&chunk.data) else {
+        eprintln!("Ignoring undecodable chunk {} of {}", chunk.index, chunk.file_id);
+        return;
+    };
+
+    let progress = {
+        let state = app_handle.state::<AppState>();
+        let mut incoming = state.incoming_transfers.lock_or_recover();
+        let Some(transfer) = incoming.get_mut(&chunk.file_id) else {
+            return;
+        };
+        if transfer.device_id != device_id {
+            return;
+        }
+        let Some(slot) = transfer.chunks.get_mut(chunk.index) else {
+            eprintln!("Ignoring out-of-range chunk {} of {}", chunk.index, chunk.file_id);
+            return;
+        };
+        *slot = Some(bytes);
+        transfer.last_chunk_at = std::time::Instant::now();
+
+        FileTransferProgress {
+            file_id: chunk.file_id.clone(),
+            file_name: transfer.item.file_name.clone().unwrap_or_default(),
+            device_id,
+            direction: TransferDirection::Receive,
+            bytes_transferred: transfer.chunks.iter().flatten().map(|c| c.len() as u64).sum(),
+            total_bytes: transfer.total_size,
+        }
+    };
+
+    report_transfer_progress(app_handle, &progress);
+}
+
+// Reassembles a finished incoming transfer; UDP has no retransmission, so a
+// transfer with any chunk missing fails as a whole
+fn complete_incoming_transfer<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, device_name: &str, file_id: &str) {
+    let transfer = {
+        let state = app_handle.state::<AppState>();
+        let mut incoming = state.incoming_transfers.lock_or_recover();
+        match incoming.get(file_id) {
+            Some(transfer) if transfer.device_id == device_id => incoming.remove(file_id),
+            _ => None,
+        }
+    };
+    let Some(transfer) = transfer else {
+        return;
+    };
+
+    let missing = transfer.chunks.iter().filter(|c| c.is_none()).count();
+    if missing > 0 {
+        eprintln!("File transfer {} from {} is missing {} chunks", file_id, device_name, missing);
+        finish_transfer(app_handle, file_id, device_id, Err(format!("{} chunks were lost in transit", missing)));
+        return;
+    }
+
+    let file_content: Vec<u8> = transfer.chunks.into_iter().flatten().flatten().collect();
+    if file_content.len() as u64 != transfer.total_size {
+        finish_transfer(app_handle, file_id, device_id, Err("Received file size does not match".to_string()));
+        return;
+    }
+
+    let result = store_received_file(app_handle, transfer.item, &file_content, device_name);
+    if let Err(e) = &result {
+        eprintln!("Failed to store received file: {}", e);
+    }
+    finish_transfer(app_handle, file_id, device_id, result);
+}
+
+// Discards the partial reassembly of an incoming transfer. Chunks are only
+// buffered in memory, so there is no temp file to remove.
+fn discard_incoming_transfer<R: Runtime>(app_handle: &AppHandle<R>, file_id: &str, device_id: u32) -> bool {
+    let state = app_handle.state::<AppState>();
+    let removed = {
+        let mut incoming = state.incoming_transfers.lock_or_recover();
+        match incoming.get(file_id) {
+            Some(transfer) if transfer.device_id == device_id => incoming.remove(file_id).is_some(),
+            _ => false,
+        }
+    };
+    if removed {
+        finish_transfer(app_handle, file_id, device_id, Err("Transfer cancelled".to_string()));
+    }
+    removed
+}
+
+// An incoming transfer that goes this long without a chunk is dropped
+const INCOMING_TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Drops incoming transfers whose sender stopped sending, freeing their chunks
+fn expire_stale_incoming_transfers<R: Runtime>(app_handle: &AppHandle<R>) {
+    let state = app_handle.state::<AppState>();
+    let stale: Vec<(String, u32)> = {
+        let mut incoming = state.incoming_transfers.lock_or_recover();
+        let stale: Vec<(String, u32)> = incoming.iter()
+            .filter(|(_, transfer)| transfer.last_chunk_at.elapsed() >= INCOMING_TRANSFER_TIMEOUT)
+            .map(|(file_id, transfer)| (file_id.clone(), transfer.device_id))
+            .collect();
+        for (file_id, _) in &stale {
+            incoming.remove(file_id);
+        }
+        stale
+    };
+
+    for (file_id, device_id) in stale {
+        println!("Incoming transfer {} from device {} timed out", file_id, device_id);
+        finish_transfer(app_handle, &file_id, device_id, Err("Transfer timed out".to_string()));
+    }
+}
+
+// Stores a file received from a peer and records it in the database
+fn store_received_file<R: Runtime>(app_handle: &AppHandle<R>, received_item: ClipboardItem, file_content: &[u8], device_name: &str) -> Result<(), String> {
+    let file_name = received_item.file_name.clone().unwrap_or_else(|| "received_file".to_string());
+    let stored_path = store_file_content(file_content, &file_name, &received_item.id)?;
+
+    // Create new item with our local storage path
+    let local_item = ClipboardItem {
+        id: received_item.id,
+        content: received_item.content,
+        timestamp: received_item.timestamp,
+        device: received_item.device,
+        content_type: received_item.content_type,
+        file_path: Some(stored_path),
+        file_size: received_item.file_size,
+        file_name: received_item.file_name,
+        use_count: 0,
+        content_size: file_content.len() as u64,
+        mime_type: detect_mime_type(&file_name, file_content),
+        metadata: received_item.metadata,
+        expires_at: received_item.expires_at,
+        no_sync: false,
+    };
+
+    // Files are not added to in-memory history - only stored in database
+    let app_state = app_handle.state::<AppState>();
+    let db_path = app_state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        let _ = save_clipboard_item_to_db(&db_path, &local_item);
+    }
+
+    // Emit to frontend
+    emit_item_added(app_handle, &local_item);
+    notify_synced_item(app_handle, &local_item, device_name);
+
+    println!("Received and stored file: {} ({} bytes) from {}", file_name, file_content.len(), device_name);
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_clipboard_history(state: State<'_, AppState>) -> Result<Vec<ClipboardItem>, String> {
+    let history = state.clipboard_history.lock_or_recover();
+    Ok(history.clone())
+}
+
+#[tauri::command]
+async fn get_clipboard_history_paginated(state: State<'_, AppState>, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        load_clipboard_history_paginated(&db_path, offset, limit)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn get_history_by_source_app(state: State<'_, AppState>, source_app: String, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    load_history_by_source_app_from_db(&db_path, &source_app, offset, limit)
+}
+
+// Every source app seen so far, for populating a filter menu
+#[tauri::command]
+async fn get_source_apps(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    load_source_apps_from_db(&db_path)
+}
+
+#[tauri::command]
+async fn get_clipboard_history_count(state: State<'_, AppState>) -> Result<u32, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        get_clipboard_history_count_from_db(&db_path)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn get_clipboard_files_count(state: State<'_, AppState>) -> Result<u32, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        get_clipboard_files_count_from_db(&db_path)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn get_clipboard_files_paginated(state: State<'_, AppState>, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        get_clipboard_files_paginated_from_db(&db_path, offset, limit)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn search_clipboard(state: State<'_, AppState>, query: String, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        search_clipboard_items(&db_path, &query, offset, limit)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+// Search, filters, sort and paging in one call, with the total for the pager
+#[tauri::command]
+async fn query_history(state: State<'_, AppState>, params: HistoryQuery) -> Result<HistoryPage, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    query_history_from_db(&db_path, &params)
+}
+
+// Bounds for search_advanced. The regex crate matches in linear time, so
+// limiting the compiled program size and the number of items scanned caps the work.
+const MAX_SEARCH_PATTERN_LEN: usize = 1024;
+const SEARCH_REGEX_SIZE_LIMIT: usize = 1 << 20;
+const SEARCH_SCAN_LIMIT: u32 = 10_000;
+
+#[derive(Debug, Clone, Serialize)]
+struct SearchMatch {
+    item: ClipboardItem,
+    matched_fields: Vec<String>,
+}
+
+// Searches history by substring (case-insensitive) or regex within the chosen
+// fields: "content", "device" and/or "metadata" (keys and values). No fields
+// means content only. The newest SEARCH_SCAN_LIMIT items are searched.
+#[tauri::command]
+async fn search_advanced(state: State<'_, AppState>, query: String, regex: bool, fields: Vec<String>) -> Result<Vec<SearchMatch>, String> {
+    const SEARCH_FIELDS: &[&str] = &["content", "device", "metadata"];
+    let fields: Vec<String> = if fields.is_empty() { vec!["content".to_string()] } else { fields };
+    if let Some(unknown) = fields.iter().find(|f| !SEARCH_FIELDS.contains(&f.as_str())) {
+        return Err(format!("Unknown search field \"{}\"; expected one of {:?}", unknown, SEARCH_FIELDS));
+    }
+    if query.len() > MAX_SEARCH_PATTERN_LEN {
+        return Err(format!("Search query is longer than {} characters", MAX_SEARCH_PATTERN_LEN));
+    }
+
+    let matches: Box<dyn Fn(&str) -> bool + Send> = if regex {
+        let pattern = regex::RegexBuilder::new(&query)
+            .size_limit(SEARCH_REGEX_SIZE_LIMIT)
+            .dfa_size_limit(SEARCH_REGEX_SIZE_LIMIT)
+            .build()
+            .map_err(|e| format!("Invalid regular expression: {}", e))?;
+        Box::new(move |text| pattern.is_match(text))
+    } else {
+        let needle = query.to_lowercase();
+        Box::new(move |text| text.to_lowercase().contains(&needle))
+    };
+
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let items = load_clipboard_history_paginated(&db_path, 0, SEARCH_SCAN_LIMIT)?;
+
+    Ok(items.into_iter()
+        .filter_map(|item| {
+            let matched_fields: Vec<String> = fields.iter()
+                .filter(|field| match field.as_str() {
+                    "content" => matches(&item.content),
+                    "device" => matches(&item.device),
+                    _ => item.metadata.iter().any(|(key, value)| matches(key) || matches(value)),
+                })
+                .cloned()
+                .collect();
+            (!matched_fields.is_empty()).then_some(SearchMatch { item, matched_fields })
+        })
+        .collect())
+}
+
+// Number of stored items per content type, e.g. {"text": 1203, "file": 45}
+#[tauri::command]
+async fn get_content_type_counts(state: State<'_, AppState>) -> Result<HashMap<String, u32>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    get_content_type_counts_from_db(&db_path)
+}
+
+#[tauri::command]
+async fn get_search_count(state: State<'_, AppState>, query: String) -> Result<u32, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        get_search_results_count(&db_path, &query)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn clear_clipboard_history(state: State<'_, AppState>) -> Result<(), String> {
+    ensure_writable(&state)?;
+    // Clear in-memory history
+    {
+        let mut history = state.clipboard_history.lock_or_recover();
+        history.clear();
+    }
+    
+    // Clear database
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        if let Err(e) = clear_clipboard_history_from_db(&db_path) {
+            eprintln!("Failed to clear clipboard history from database: {}", e);
+            return Err(e);
+        }
+    }
+    
+    Ok(())
+}
+
+#[tauri::command]
+async fn delete_clipboard_item(state: State<'_, AppState>, id: String) -> Result<(), String> {
+    ensure_writable(&state)?;
+    // Delete from in-memory history
+    {
+        let mut history = state.clipboard_history.lock_or_recover();
+        history.retain(|item| item.id != id);
+    }
+    
+    // Delete from database
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        if let Err(e) = delete_clipboard_item_from_db(&db_path, &id) {
+            eprintln!("Failed to delete clipboard item from database: {}", e);
+            return Err(e);
+        }
+    }
+    remove_item_thumbnails(&id);
+    
+    Ok(())
+}
+
+#[cfg(feature = "clipboard")]
+#[tauri::command]
+async fn set_clipboard_content(content: String, state: State<'_, AppState>) -> Result<(), String> {
+    // Set ignore flag to prevent the monitor from detecting this as a new change
+    {
+        let mut ignore = state.ignore_next_clipboard_change.lock_or_recover();
+        *ignore = true;
+    }
+    
+    if let Ok(mut clipboard) = Clipboard::new() {
+        clipboard.set_text(content).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+// Puts an item's payload on the system clipboard: file items as their stored path,
+// image items as the decoded stored image, everything else as text
+#[cfg(feature = "clipboard")]
+fn set_system_clipboard_for_item(item: &ClipboardItem) -> Result<(), String> {
+    let mut clipboard = Clipboard::new().map_err(|e| e.to_string())?;
+
+    match item.content_type.as_str() {
+        "image" => {
+            let image_path = item.file_path.as_deref()
+                .filter(|path| !path.is_empty())
+                .ok_or("Image item has no stored image".to_string())?;
+            let rgba = image::open(image_path)
+                .map_err(|e| format!("Failed to decode image: {}", e))?
+                .to_rgba8();
+            let (width, height) = rgba.dimensions();
+            clipboard.set_image(arboard::ImageData {
+                width: width as usize,
+                height: height as usize,
+                bytes: std::borrow::Cow::Owned(rgba.into_raw()),
+            }).map_err(|e| e.to_string())
+        },
+        "file" => {
+            let file_path = item.file_path.as_deref()
+                .filter(|path| !path.is_empty())
+                .ok_or("File item has no stored file".to_string())?;
+            clipboard.set_text(file_path).map_err(|e| e.to_string())
+        },
+        _ => clipboard.set_text(&item.content).map_err(|e| e.to_string()),
+    }
+}
+
+// Sends the platform paste shortcut to whichever app has focus
+#[cfg(feature = "clipboard")]
+fn simulate_paste() -> Result<(), String> {
+    use enigo::{Direction, Enigo, Key, Keyboard, NewConError};
+
+    let mut enigo = Enigo::new(&enigo::Settings::default()).map_err(|e| match e {
+        NewConError::NoPermission => "Auto-paste needs permission to control the keyboard \
+            (on macOS, allow Cliped under Privacy & Security > Accessibility)".to_string(),
+        e => e.to_string(),
+    })?;
+
+    #[cfg(target_os = "macos")]
+    let modifier = Key::Meta;
+    #[cfg(not(target_os = "macos"))]
+    let modifier = Key::Control;
+
+    enigo.key(modifier, Direction::Press).map_err(|e| e.to_string())?;
+    let result = enigo.key(Key::Unicode('v'), Direction::Click);
+    enigo.key(modifier, Direction::Release).map_err(|e| e.to_string())?;
+    result.map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn simulate_paste() -> Result<(), String> {
+    Err("Auto-paste not available on this platform".to_string())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn set_system_clipboard_for_item(_item: &ClipboardItem) -> Result<(), String> {
+    Err("Clipboard functionality not available on this platform".to_string())
+}
+
+// Reads whatever is on the system clipboard right now as a transient item
+// that is neither stored nor synced
+#[cfg(feature = "clipboard")]
+#[tauri::command]
+async fn get_current_clipboard(state: State<'_, AppState>) -> Result<Option<ClipboardItem>, String> {
+    let Ok(mut clipboard) = Clipboard::new() else {
+        return Ok(None);
+    };
+    let device = local_item_attribution(&state);
+
+    let transient_item = |content: String, content_type: &str, file_size: Option<u64>| {
+        let content_size = file_size.unwrap_or(content.len() as u64);
+        ClipboardItem {
+            id: generate_id().to_string(),
+            content,
+            timestamp: get_current_timestamp().to_string(),
+            device: device.clone(),
+            content_type: content_type.to_string(),
+            file_path: None,
+            file_size,
+            file_name: None,
+            use_count: 0,
+            content_size,
+            mime_type: None,
+            metadata: HashMap::new(),
+            expires_at: None,
+            no_sync: false,
+        }
+    };
+
+    if let Ok(text) = clipboard.get_text() {
+        return Ok(Some(transient_item(text, "text", None)));
+    }
+
+    // Images have no history representation, so only their dimensions are described
+    if let Ok(image) = clipboard.get_image() {
+        let description = format!("Image {}x{}", image.width, image.height);
+        return Ok(Some(transient_item(description, "image", Some(image.bytes.len() as u64))));
+    }
+
+    Ok(None)
+}
+
+#[cfg(not(feature = "clipboard"))]
+#[tauri::command]
+async fn get_current_clipboard(_state: State<'_, AppState>) -> Result<Option<ClipboardItem>, String> {
+    Ok(None)
+}
+
+#[cfg(not(feature = "clipboard"))]
+#[tauri::command]
+async fn set_clipboard_content(_content: String, _state: State<'_, AppState>) -> Result<(), String> {
+    Err("Clipboard functionality not available on this platform".to_string())
+}
+
+#[tauri::command]
+async fn toggle_monitoring(state: State<'_, AppState>) -> Result<bool, String> {
+    let mut enabled = state.enabled.lock_or_recover();
+    *enabled = !*enabled;
+    let is_enabled = *enabled;
+    println!("Clipboard monitoring {}", if is_enabled { "enabled" } else { "disabled" });
+    Ok(is_enabled)
+}
+
+#[tauri::command]
+async fn is_monitoring_enabled(state: State<'_, AppState>) -> Result<bool, String> {
+    // Only report monitoring as on when the loop is actually there to do it
+    let enabled = *state.enabled.lock_or_recover();
+    Ok(enabled && *state.monitor_alive.lock_or_recover())
+}
+
+#[tauri::command]
+async fn set_capture_length_bounds(state: State<'_, AppState>, min: usize, max: usize) -> Result<(), String> {
+    if max < min {
+        return Err("Maximum length must not be smaller than minimum length".to_string());
+    }
+
+    {
+        let mut settings = state.settings.lock_or_recover();
+        settings.capture_min_length = min;
+        settings.capture_max_length = max;
+    }
+    persist_settings(&state);
+    println!("Capture length bounds set to {}..={} characters", min, max);
+    Ok(())
+}
+
+// Captured text matching any of these regexes is flagged no_sync and stays on
+// this device. An empty list flags nothing.
+#[tauri::command]
+async fn set_no_sync_patterns(state: State<'_, AppState>, patterns: Vec<String>) -> Result<(), String> {
+    let mut compiled = Vec::with_capacity(patterns.len());
+    for pattern in &patterns {
+        if pattern.len() > MAX_SEARCH_PATTERN_LEN {
+            return Err(format!("Pattern is longer than {} characters", MAX_SEARCH_PATTERN_LEN));
+        }
+        compiled.push(regex::Regex::new(pattern).map_err(|e| format!("Invalid pattern \"{}\": {}", pattern, e))?);
+    }
+    println!("Flagging copies matching {} patterns as no-sync", patterns.len());
+    state.settings.lock_or_recover().no_sync_patterns = patterns;
+    *state.no_sync_patterns.lock_or_recover() = compiled;
+    persist_settings(&state);
+    Ok(())
+}
+
+// Some(n) keeps single short words and repeated characters out of history
+// (URLs and emails excepted); None turns the filter off
+#[tauri::command]
+async fn set_trivial_content_filter(state: State<'_, AppState>, min_length: Option<usize>) -> Result<(), String> {
+    state.settings.lock_or_recover().trivial_content_min_length = min_length;
+    persist_settings(&state);
+    match min_length {
+        Some(min) => println!("Ignoring trivial copies shorter than {} characters", min),
+        None => println!("Trivial copy filter disabled"),
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_capture_debounce_ms(state: State<'_, AppState>, ms: u64) -> Result<(), String> {
+    const MAX_DEBOUNCE_MS: u64 = 5000;
+    if ms > MAX_DEBOUNCE_MS {
+        return Err(format!("Debounce must be at most {}ms", MAX_DEBOUNCE_MS));
+    }
+
+    state.settings.lock_or_recover().capture_debounce_ms = ms;
+    persist_settings(&state);
+    println!("Capture debounce set to {}ms", ms);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_inline_text_files(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().inline_text_files = enabled;
+    persist_settings(&state);
+    println!("Inline text files {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_trim_captured_whitespace(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().trim_captured_whitespace = enabled;
+    persist_settings(&state);
+    println!("Trimming captured whitespace {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+// Replaces the rules applied to captured text; rejected as a whole if any rule's
+// regex doesn't compile
+#[tauri::command]
+async fn set_transform_rules(state: State<'_, AppState>, rules: Vec<TransformRule>) -> Result<(), String> {
+    let compiled = rules.iter().map(CompiledTransformRule::new).collect::<Result<Vec<_>, _>>()?;
+    println!("Applying {} transform rules to captured text", rules.len());
+    state.settings.lock_or_recover().transform_rules = rules;
+    *state.transform_rules.lock_or_recover() = compiled;
+    persist_settings(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_transform_rules(state: State<AppState>) -> Vec<TransformRule> {
+    state.settings.lock_or_recover().transform_rules.clone()
+}
+
+#[tauri::command]
+async fn set_keep_original_content(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().keep_original_content = enabled;
+    persist_settings(&state);
+    println!("Keeping pre-transform text {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_clear_devices_on_startup(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().clear_devices_on_startup = enabled;
+    persist_settings(&state);
+    println!("Clearing devices on startup {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_require_explicit_trust(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().require_explicit_trust = enabled;
+    persist_settings(&state);
+    println!("Explicit device trust {}", if enabled { "required" } else { "not required" });
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_pending_request_expiry_secs(state: State<'_, AppState>, secs: u64) -> Result<(), String> {
+    if secs == 0 {
+        return Err("Expiry must be at least one second".to_string());
+    }
+
+    state.settings.lock_or_recover().pending_request_expiry_secs = secs;
+    persist_settings(&state);
+    println!("Connection requests now expire after {}s", secs);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_item_attribution(state: State<'_, AppState>, source: String) -> Result<(), String> {
+    let attribution = match source.as_str() {
+        "hostname" => ItemAttribution::Hostname,
+        "device_name" => ItemAttribution::DeviceName,
+        _ => return Err("Invalid attribution source".to_string()),
+    };
+
+    state.settings.lock_or_recover().item_attribution = attribution;
+    persist_settings(&state);
+    println!("New items are attributed by {:?}", attribution);
+    Ok(())
+}
+
+// Persisted so the window can be styled before the webview has loaded
+#[tauri::command]
+async fn set_theme(app_handle: AppHandle, state: State<'_, AppState>, theme: String) -> Result<(), String> {
+    let theme = match theme.as_str() {
+        "light" => Theme::Light,
+        "dark" => Theme::Dark,
+        "system" => Theme::System,
+        _ => return Err("Invalid theme".to_string()),
+    };
+
+    state.settings.lock_or_recover().theme = theme;
+    persist_settings(&state);
+    let _ = app_handle.emit("theme-changed", theme);
+    println!("Theme set to {:?}", theme);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_theme(state: State<AppState>) -> Theme {
+    state.settings.lock_or_recover().theme
+}
+
+// Content types ("text", "image", "file", ...) whose synced items raise a notification
+#[tauri::command]
+async fn set_notify_content_types(state: State<'_, AppState>, types: Vec<String>) -> Result<(), String> {
+    let mut types: Vec<String> = types.iter().map(|t| t.trim().to_lowercase()).filter(|t| !t.is_empty()).collect();
+    types.sort();
+    types.dedup();
+
+    println!("Sync notifications for: {:?}", types);
+    state.settings.lock_or_recover().notify_content_types = types;
+    persist_settings(&state);
+    Ok(())
+}
+
+// Formats are "text", "html", "image" and "files"; the monitor checks them in a
+// fixed priority order, whatever order they're given in
+#[tauri::command]
+async fn set_monitored_formats(state: State<'_, AppState>, formats: Vec<String>) -> Result<(), String> {
+    let mut parsed = Vec::new();
+    for format in &formats {
+        let format = match format.trim().to_lowercase().as_str() {
+            "text" => ClipboardFormat::Text,
+            "html" => ClipboardFormat::Html,
+            "image" => ClipboardFormat::Image,
+            "files" => ClipboardFormat::Files,
+            _ => return Err(format!("Unknown clipboard format: {}", format)),
+        };
+        if !parsed.contains(&format) {
+            parsed.push(format);
+        }
+    }
+    if parsed.is_empty() {
+        return Err("At least one clipboard format must be monitored".to_string());
+    }
+
+    println!("Monitoring clipboard formats: {:?}", parsed);
+    state.settings.lock_or_recover().monitored_formats = parsed;
+    persist_settings(&state);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_monitored_formats(state: State<AppState>) -> Vec<ClipboardFormat> {
+    state.settings.lock_or_recover().monitored_formats.clone()
+}
+
+// Keeps at most `max` items of `content_type` ("text", "image", "file", ...);
+// None removes the limit. The oldest items beyond it are deleted right away.
+#[tauri::command]
+async fn set_retention_limit(app_handle: AppHandle, state: State<'_, AppState>, content_type: String, max: Option<u32>) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let content_type = content_type.trim().to_lowercase();
+    if content_type.is_empty() {
+        return Err("Content type cannot be empty".to_string());
+    }
+
+    {
+        let mut settings = state.settings.lock_or_recover();
+        match max {
+            Some(max) => settings.retention_limits.insert(content_type.clone(), max),
+            None => settings.retention_limits.remove(&content_type),
+        };
+    }
+    persist_settings(&state);
+    match max {
+        Some(max) => println!("Keeping at most {} {} items", max, content_type),
+        None => println!("No retention limit for {} items", content_type),
+    }
+
+    enforce_retention_limits(&app_handle);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_retention_limits(state: State<AppState>) -> HashMap<String, u32> {
+    state.settings.lock_or_recover().retention_limits.clone()
+}
+
+// Mutes sync notifications between `start` and `end` ("HH:MM", local time).
+// Two empty strings turn quiet hours off.
+#[tauri::command]
+async fn set_quiet_hours(state: State<'_, AppState>, start: String, end: String) -> Result<(), String> {
+    let quiet_hours = if start.trim().is_empty() && end.trim().is_empty() {
+        None
+    } else {
+        let (start_time, end_time) = (parse_clock_time(&start)?, parse_clock_time(&end)?);
+        if start_time == end_time {
+            return Err("Quiet hours must start and end at different times".to_string());
+        }
+        Some(QuietHours {
+            start: start_time.format("%H:%M").to_string(),
+            end: end_time.format("%H:%M").to_string(),
+        })
+    };
+
+    match &quiet_hours {
+        Some(quiet) => println!("Quiet hours set to {}-{}", quiet.start, quiet.end),
+        None => println!("Quiet hours disabled"),
+    }
+    state.settings.lock_or_recover().quiet_hours = quiet_hours;
+    persist_settings(&state);
+    Ok(())
+}
+
+// Networks (Wi-Fi SSIDs or gateway MAC addresses) sync is allowed on; sync pauses
+// everywhere else. An empty list allows sync on every network.
+#[tauri::command]
+async fn set_trusted_networks(app_handle: AppHandle, state: State<'_, AppState>, networks: Vec<String>) -> Result<(), String> {
+    let networks: Vec<String> = networks.iter().map(|n| n.trim().to_string()).filter(|n| !n.is_empty()).collect();
+    println!("Trusted networks: {:?}", networks);
+    state.settings.lock_or_recover().trusted_networks = networks;
+    persist_settings(&state);
+
+    check_trusted_network(&app_handle).await;
+    Ok(())
+}
+
+// Identifiers of the current network, in the form set_trusted_networks expects
+#[tauri::command]
+async fn get_current_network() -> Result<Vec<String>, String> {
+    tauri::async_runtime::spawn_blocking(current_network_ids).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_max_item_size(state: State<'_, AppState>, bytes: u64) -> Result<(), String> {
+    if bytes == 0 {
+        return Err("Maximum item size must be at least one byte".to_string());
+    }
+
+    state.settings.lock_or_recover().max_item_size = bytes;
+    persist_settings(&state);
+    println!("Maximum item size set to {} bytes", bytes);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_thumbnail_max_dimension(state: State<'_, AppState>, px: u32) -> Result<u32, String> {
+    let px = px.clamp(*THUMBNAIL_DIMENSION_RANGE.start(), *THUMBNAIL_DIMENSION_RANGE.end());
+    state.settings.lock_or_recover().thumbnail_max_dimension = px;
+    persist_settings(&state);
+    if let Err(e) = remove_thumbnails() {
+        eprintln!("{}", e);
+    }
+    println!("Image previews now up to {}px", px);
+    Ok(px)
+}
+
+#[tauri::command]
+async fn set_thumbnail_quality(state: State<'_, AppState>, q: u8) -> Result<u8, String> {
+    let q = q.clamp(*THUMBNAIL_QUALITY_RANGE.start(), *THUMBNAIL_QUALITY_RANGE.end());
+    state.settings.lock_or_recover().thumbnail_quality = q;
+    persist_settings(&state);
+    if let Err(e) = remove_thumbnails() {
+        eprintln!("{}", e);
+    }
+    println!("Image preview quality set to {}", q);
+    Ok(q)
+}
+
+// Devices already connected stay connected; the limit applies to new connections
+#[tauri::command]
+async fn set_max_devices(state: State<'_, AppState>, n: u32) -> Result<(), String> {
+    if n == 0 {
+        return Err("At least one device must be allowed to connect".to_string());
+    }
+
+    state.settings.lock_or_recover().max_devices = n;
+    persist_settings(&state);
+    println!("Maximum connected devices set to {}", n);
+    Ok(())
+}
+
+#[tauri::command]
+fn get_max_devices(state: State<AppState>) -> u32 {
+    state.settings.lock_or_recover().max_devices
+}
+
+// Why another connection would be refused, if max_devices peers are already
+// connected. A device that is already connected doesn't take a second slot.
+fn device_limit_reason(state: &AppState, device_id: u32) -> Option<String> {
+    let max_devices = state.settings.lock_or_recover().max_devices;
+    let local_id = state.local_device.lock_or_recover().as_ref().map(|d| d.id);
+    let connected = state.devices.lock_or_recover()
+        .values()
+        .filter(|d| Some(d.id) != local_id && d.id != device_id && matches!(d.status, DeviceStatus::Connected))
+        .count();
+
+    (connected >= max_devices as usize)
+        .then(|| format!("Device limit reached: {} of {} devices connected", connected, max_devices))
+}
+
+#[tauri::command]
+async fn set_auto_paste(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().auto_paste = enabled;
+    persist_settings(&state);
+    println!("Auto-paste {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_dedup_window(state: State<'_, AppState>, window: usize) -> Result<(), String> {
+    state.settings.lock_or_recover().dedup_window = window;
+    persist_settings(&state);
+    println!("Deduplicating against the last {} items", window);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_sync_latest_on_connect(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().sync_latest_on_connect = enabled;
+    persist_settings(&state);
+    println!("Sync latest item on connect {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_close_to_tray(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state.settings.lock_or_recover().close_to_tray = enabled;
+    persist_settings(&state);
+    println!("Close to tray {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
 }
 
-#[cfg(not(any(target_os = "android", target_os = "ios")))]
-fn main() {
-    run();
+// Hides the window; monitoring and sync keep running and the tray brings it back
+#[tauri::command]
+async fn minimize_to_tray(app_handle: AppHandle) -> Result<(), String> {
+    let window = app_handle.get_webview_window("cliped").ok_or("Main window not found".to_string())?;
+    window.hide().map_err(|e| e.to_string())
 }
 
-#[cfg(feature = "clipboard")]
-async fn monitor_clipboard(
-    app_handle: AppHandle,
-    clipboard_history: ClipboardState,
-    last_content: Arc<Mutex<String>>,
-    enabled: Arc<Mutex<bool>>,
-    devices: Arc<Mutex<HashMap<u32, Device>>>,
-    local_device: Arc<Mutex<Option<Device>>>,
-) {
-    println!("Clipboard monitoring started!");
-    let mut clipboard = Clipboard::new().unwrap();
+// Login item for this executable: a Run registry key on Windows, a LaunchAgent
+// plist on macOS and an XDG autostart .desktop file on Linux
+#[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+fn startup_entry() -> Result<auto_launch::AutoLaunch, String> {
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    // An AppImage runs from a temporary mount, so register the image itself
+    #[cfg(target_os = "linux")]
+    let exe = std::env::var_os("APPIMAGE").map(std::path::PathBuf::from).unwrap_or(exe);
 
-    // Get ignore flag reference (this won't change)
-    let ignore_flag = {
-        let app_state = app_handle.state::<AppState>();
-        Arc::clone(&app_state.ignore_next_clipboard_change)
-    };
+    auto_launch::AutoLaunchBuilder::new()
+        .set_app_name("Cliped")
+        .set_app_path(&exe.to_string_lossy())
+        .set_use_launch_agent(true)
+        .build()
+        .map_err(|e| e.to_string())
+}
 
-    // Check if clipboard is available first
-    if clipboard.get_text().is_err() {
-        println!("Clipboard not available on this platform - skipping clipboard monitoring");
-        return;
+#[tauri::command]
+async fn set_launch_at_startup(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    {
+        let entry = startup_entry()?;
+        if enabled {
+            entry.enable().map_err(|e| e.to_string())?;
+        } else if entry.is_enabled().unwrap_or(false) {
+            entry.disable().map_err(|e| e.to_string())?;
+        }
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    if enabled {
+        return Err("Launching at startup is not supported on this platform".to_string());
     }
 
-    loop {
-        sleep(Duration::from_millis(500)).await;
-        
-        // Check if monitoring is enabled
-        if !*enabled.lock().unwrap() {
-            continue;
-        }
-        
-        if let Ok(text) = clipboard.get_text() {
-            let should_process = {
-                let mut last = last_content.lock().unwrap();
-                let mut ignore = ignore_flag.lock().unwrap();
-                
-                // Check if we should ignore this change (it's from a sync)
-                if *ignore {
-                    println!("Ignoring clipboard change from sync");
-                    *ignore = false;
-                    *last = text.clone(); // Update last content to avoid future triggers
-                    false
-                } else if text != *last && !text.trim().is_empty() {
-                    println!("New clipboard content detected: {}", text.chars().take(50).collect::<String>());
-                    *last = text.clone();
-                    true
-                } else {
-                    false
-                }
-            }; // Drop the locks here
-            
-            if should_process {
-                let item = ClipboardItem {
-                    id: generate_id().to_string(),
-                    content: text,
-                    timestamp: get_current_timestamp().to_string(),
-                    device: whoami::fallible::hostname().unwrap_or("Unknown".to_string()),
-                    content_type: "text".to_string(),
-                    file_path: None,
-                    file_size: None,
-                    file_name: None,
-                };
+    state.settings.lock_or_recover().launch_at_startup = enabled;
+    persist_settings(&state);
+    println!("Launch at startup {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
+}
 
-                // Add to local history first
-                {
-                    let mut history = clipboard_history.lock().unwrap();
-                    
-                    // Remove duplicates
-                    history.retain(|existing| existing.content != item.content);
-                    
-                    // Insert at beginning
-                    history.insert(0, item.clone());
-                    
-                    // Limit to 50 items
-                    if history.len() > 50 {
-                        history.truncate(50);
-                    }
-                    
-                    println!("Clipboard history now has {} items", history.len());
-                } // Drop the history lock here
+// Reports whether the OS will actually start the app, which can differ from the
+// saved preference if the user removed the login item elsewhere
+#[tauri::command]
+async fn get_launch_at_startup(state: State<'_, AppState>) -> Result<bool, String> {
+    #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows"))]
+    if let Ok(enabled) = startup_entry().and_then(|entry| entry.is_enabled().map_err(|e| e.to_string())) {
+        return Ok(enabled);
+    }
+    Ok(state.settings.lock_or_recover().launch_at_startup)
+}
 
-                // Save to database (get db_path fresh from app state)
-                let app_state = app_handle.state::<AppState>();
-                let db_path = app_state.db_path.lock().unwrap().clone();
+const DISCOVERY_TIMEOUT_RANGE_MS: std::ops::RangeInclusive<u64> = 500..=30_000;
+const DISCOVERY_RETRIES_RANGE: std::ops::RangeInclusive<u32> = 1..=10;
 
-                if let Some(ref db_path) = db_path {
-                    match save_clipboard_item_to_db(db_path, &item) {
-                        Ok(_) => println!("✓ Saved clipboard item to database"),
-                        Err(e) => eprintln!("✗ Failed to save clipboard item to database: {}", e),
-                    }
-                } else {
-                    eprintln!("✗ Database not initialized - cannot save clipboard item");
-                }
+#[tauri::command]
+async fn set_discovery_timeout_ms(state: State<'_, AppState>, ms: u64) -> Result<(), String> {
+    let ms = ms.clamp(*DISCOVERY_TIMEOUT_RANGE_MS.start(), *DISCOVERY_TIMEOUT_RANGE_MS.end());
+    state.settings.lock_or_recover().discovery_timeout_ms = ms;
+    persist_settings(&state);
+    println!("Discovery passes now listen for {}ms", ms);
+    Ok(())
+}
 
-                // Check if we have connected devices before syncing
-                let has_connected_devices = {
-                    let devices = devices.lock().unwrap();
-                    devices.values().any(|device| {
-                        matches!(device.status, DeviceStatus::Connected) &&
-                        !matches!(device.sync_mode, SyncMode::Disabled)
-                    })
-                };
+#[tauri::command]
+async fn set_discovery_retries(state: State<'_, AppState>, n: u32) -> Result<(), String> {
+    let n = n.clamp(*DISCOVERY_RETRIES_RANGE.start(), *DISCOVERY_RETRIES_RANGE.end());
+    state.settings.lock_or_recover().discovery_retries = n;
+    persist_settings(&state);
+    println!("Discovery scans now make {} passes", n);
+    Ok(())
+}
 
-                // Only sync if we have connected devices with sync enabled
-                if has_connected_devices {
-                    sync_to_connected_devices(&devices, &local_device, &item).await;
-                } else {
-                    println!("No connected devices with sync enabled - skipping clipboard sync");
-                }
+// Pins outgoing traffic and discovery to one local IPv4 address, e.g. the LAN
+// adapter instead of a VPN. An empty string returns to automatic selection.
+// The listener keeps accepting on every interface so broadcasts still arrive.
+#[tauri::command]
+async fn set_network_interface(state: State<'_, AppState>, ip: String) -> Result<(), String> {
+    let interface = if ip.trim().is_empty() {
+        None
+    } else {
+        let parsed: std::net::IpAddr = ip.trim().parse().map_err(|_| format!("Invalid IP address: {}", ip))?;
+        if !parsed.is_ipv4() {
+            return Err("Only IPv4 addresses are supported".to_string());
+        }
+        let is_local = list_afinet_netifas()
+            .map_err(|e| e.to_string())?
+            .iter()
+            .any(|(_, local)| *local == parsed);
+        if !is_local {
+            return Err(format!("{} is not assigned to any local interface", parsed));
+        }
+        Some(parsed.to_string())
+    };
 
-                // Emit to frontend
-                let _ = app_handle.emit("clipboard-updated", &item);
-                println!("Emitted clipboard-updated event");
-            }
+    state.settings.lock_or_recover().network_interface = interface.clone();
+    persist_settings(&state);
+
+    // Peers learn our address from the local device record
+    let updated_local = {
+        let mut local_device = state.local_device.lock_or_recover();
+        if let Some(ref mut device) = *local_device {
+            device.ip = get_local_ip(interface.as_deref());
         }
+        local_device.clone()
+    };
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let (Some(db_path), Some(device)) = (db_path, updated_local) {
+        save_local_device_to_db(&db_path, &device);
+    }
+
+    match interface {
+        Some(ip) => println!("Binding network traffic to {}", ip),
+        None => println!("Network interface selection set to automatic"),
     }
+    Ok(())
 }
 
-#[cfg(not(feature = "clipboard"))]
-async fn monitor_clipboard(
-    _app_handle: AppHandle,
-    _clipboard_history: ClipboardState,
-    _last_content: Arc<Mutex<String>>,
-    _enabled: Arc<Mutex<bool>>,
-    _devices: Arc<Mutex<HashMap<u32, Device>>>,
-    _local_device: Arc<Mutex<Option<Device>>>,
-) {
-    println!("Clipboard monitoring not available on this platform (mobile)");
-    // On mobile, clipboard monitoring is handled differently or not available
-    // This function exists to satisfy the type system but does nothing
-    loop {
-        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+// Frames exchanged with the relay server. The relay forwards each message to the
+// connection that registered the target device id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RelayFrame {
+    Register { device_id: u32 },
+    Forward {
+        to: u32,
+        message: NetworkMessage,
+        #[serde(default)]
+        mac: Option<String>, // HMAC-SHA256 with the pairing's relay key, see relay_mac
+    },
+}
+
+impl RelayFrame {
+    // Wraps a message for `device`, signed so the relay can't alter or forge it
+    fn forward(device: &Device, message: NetworkMessage) -> Result<RelayFrame, String> {
+        let key = device.relay_key.as_deref()
+            .ok_or_else(|| format!("{} was paired without a relay key; pair again to sync through the relay", device.name))?;
+        let mac = relay_mac(key, device.id, &message)?;
+        Ok(RelayFrame::Forward { to: device.id, message, mac: Some(mac) })
     }
 }
 
-async fn sync_to_connected_devices(
-    devices: &Arc<Mutex<HashMap<u32, Device>>>, 
-    local_device: &Arc<Mutex<Option<Device>>>, 
-    item: &ClipboardItem
-) {
-    // Get connected devices and local device info - get fresh data each time
-    let (devices_to_sync, local) = {
-        let devices = devices.lock().unwrap();
-        let local = local_device.lock().unwrap();
-        
-        // Filter devices to sync to (get fresh data, don't clone the entire HashMap)
-        let devices_to_sync: Vec<Device> = devices
-            .values()
-            .filter(|device| {
-                matches!(device.status, DeviceStatus::Connected) &&
-                !matches!(device.sync_mode, SyncMode::Disabled) &&
-                device.id != local.as_ref().map(|l| l.id).unwrap_or(0) // Don't sync to ourselves
-            })
-            .cloned()
-            .collect();
-        
-        (devices_to_sync, local.clone())
+// 32 random bytes, base64 encoded, shared with the peer in its ConnectionAccept
+fn generate_relay_key() -> String {
+    general_purpose::STANDARD.encode(rand::random::<[u8; 32]>())
+}
+
+// Covers the recipient and everything a relayed message acts on, so a frame
+// can't be re-addressed or have its sender or payload swapped
+fn relay_mac(key: &str, to: u32, message: &NetworkMessage) -> Result<String, String> {
+    let key = general_purpose::STANDARD.decode(key).map_err(|e| e.to_string())?;
+    let signed = serde_json::to_vec(&(to, message.device_id, &message.msg_type, &message.data)).map_err(|e| e.to_string())?;
+    let tag = ring::hmac::sign(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &key), &signed);
+    Ok(general_purpose::STANDARD.encode(tag.as_ref()))
+}
+
+fn verify_relay_mac(key: &str, to: u32, message: &NetworkMessage, mac: &str) -> bool {
+    let (Ok(key), Ok(mac)) = (general_purpose::STANDARD.decode(key), general_purpose::STANDARD.decode(mac)) else {
+        return false;
     };
-    
-    // If no connected devices, don't send any broadcasts
-    if devices_to_sync.is_empty() {
-        println!("No connected devices with sync enabled - skipping all clipboard sync broadcasts");
-        return;
-    }
-    
-    if let Some(local) = local {
-        println!("Syncing clipboard item to {} connected devices", devices_to_sync.len());
-        
-        // Only send to specific connected devices, no broadcasting
-        for device in devices_to_sync {
-            // Create sync message
-            let message = NetworkMessage {
-                msg_type: MessageType::ClipboardSync,
-                device_id: local.id,
-                device_name: local.name.clone(),
-                data: Some(serde_json::to_string(item).unwrap_or_default()),
-            };
-            
-            // Send directly to specific device IP
-            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
-                let message_json = serde_json::to_string(&message).unwrap_or_default();
-                let target_addr = format!("{}:51847", device.ip);
-                let _ = socket.send_to(message_json.as_bytes(), &target_addr).await;
-                println!("Synced clipboard to connected device: {} at {}", device.name, device.ip);
-            }
+    let Ok(signed) = serde_json::to_vec(&(to, message.device_id, &message.msg_type, &message.data)) else {
+        return false;
+    };
+    ring::hmac::verify(&ring::hmac::Key::new(ring::hmac::HMAC_SHA256, &key), &signed, &mac).is_ok()
+}
+
+// How long a rotated-out relay key is still accepted, so frames signed before
+// the rotation reached both sides aren't dropped
+const KEY_ROTATION_GRACE: Duration = Duration::from_secs(10 * 60);
+
+struct RetiredRelayKey {
+    key: String,
+    replaced_by: String, // Only honoured while this is still the device's key; pairing again retires it for good
+    retired_at: std::time::Instant,
+}
+
+// The keys a device's signatures are checked against: its relay key, plus the
+// one it replaced while that is within KEY_ROTATION_GRACE
+fn accepted_relay_keys(state: &AppState, device: &Device) -> Vec<String> {
+    let mut keys: Vec<String> = device.relay_key.iter().cloned().collect();
+    if let Some(retired) = state.retired_relay_keys.lock_or_recover().get(&device.id) {
+        if device.relay_key.as_ref() == Some(&retired.replaced_by) && retired.retired_at.elapsed() < KEY_ROTATION_GRACE {
+            keys.push(retired.key.clone());
         }
     }
+    keys
 }
 
-async fn sync_file_to_connected_devices(
-    devices: &Arc<Mutex<HashMap<u32, Device>>>, 
-    local_device: &Arc<Mutex<Option<Device>>>, 
-    item: &ClipboardItem,
-    file_content: &[u8]
-) {
-    // Get connected devices and local device info
-    let (devices_to_sync, local) = {
-        let devices = devices.lock().unwrap();
-        let local = local_device.lock().unwrap();
-        
-        // Filter devices to sync to
-        let devices_to_sync: Vec<Device> = devices
-            .values()
-            .filter(|device| {
-                matches!(device.status, DeviceStatus::Connected) &&
-                !matches!(device.sync_mode, SyncMode::Disabled) &&
-                device.id != local.as_ref().map(|l| l.id).unwrap_or(0)
-            })
-            .cloned()
-            .collect();
-        
-        (devices_to_sync, local.clone())
+// What a KeyRotation request or confirmation signs: the new key, from `sender_id`
+fn key_rotation_message(sender_id: u32, new_key: &str) -> NetworkMessage {
+    NetworkMessage {
+        msg_type: MessageType::KeyRotation,
+        device_id: sender_id,
+        device_name: String::new(),
+        data: Some(new_key.to_string()),
+        display_name: None,
+    }
+}
+
+// Makes `new_key` the device's relay key and keeps the one it replaces for
+// KEY_ROTATION_GRACE
+fn install_relay_key<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, new_key: String) {
+    let app_state = app_handle.state::<AppState>();
+    let updated = {
+        let mut devices = app_state.devices.lock_or_recover();
+        devices.get_mut(&device_id).map(|device| {
+            let previous = device.relay_key.replace(new_key.clone());
+            (previous, device.clone())
+        })
     };
-    
-    if devices_to_sync.is_empty() {
-        println!("No connected devices with sync enabled - skipping file sync");
+    let Some((previous, device)) = updated else {
         return;
+    };
+
+    if let Some(previous) = previous {
+        app_state.retired_relay_keys.lock_or_recover().insert(device_id, RetiredRelayKey {
+            key: previous,
+            replaced_by: new_key,
+            retired_at: std::time::Instant::now(),
+        });
     }
-    
-    if let Some(local) = local {
-        println!("Syncing file to {} connected devices: {} ({} bytes)", 
-                devices_to_sync.len(), 
-                item.file_name.as_ref().unwrap_or(&"unknown".to_string()),
-                file_content.len());
-        
-        for device in devices_to_sync {
-            // Create file transfer message with complete file content
-            let file_data = serde_json::json!({
-                "item": item,
-                "file_content": general_purpose::STANDARD.encode(file_content)
-            });
-            
-            let message = NetworkMessage {
-                msg_type: MessageType::FileTransfer,
-                device_id: local.id,
-                device_name: local.name.clone(),
-                data: Some(file_data.to_string()),
-            };
-            
-            // Send directly to specific device IP
-            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
-                let message_json = serde_json::to_string(&message).unwrap_or_default();
-                let target_addr = format!("{}:51847", device.ip);
-                let _ = socket.send_to(message_json.as_bytes(), &target_addr).await;
-                println!("Synced file to connected device: {} at {}", device.name, device.ip);
-            }
+    persist_device(&app_state, &device);
+    let _ = app_handle.emit("device-key-rotated", device_id);
+    println!("Rotated the relay key shared with {}", device.name);
+}
+
+// Agrees a new relay key with a paired device. The key is sent signed with the
+// current one and only takes effect here once the device confirms it; the old
+// key is still accepted for KEY_ROTATION_GRACE afterwards.
+#[tauri::command]
+async fn rotate_device_key(app_handle: AppHandle, state: State<'_, AppState>, device_id: u32) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let local = state.local_device.lock_or_recover().clone()
+        .ok_or("Local device not initialized".to_string())?;
+    let device = state.devices.lock_or_recover().get(&device_id).cloned()
+        .ok_or("Device not found".to_string())?;
+    let current_key = device.relay_key.clone()
+        .ok_or_else(|| format!("{} was paired without a relay key; pair again to get one", device.name))?;
+    if !matches!(device.status, DeviceStatus::Connected) {
+        return Err(format!("{} must be online to agree on a new key", device.name));
+    }
+
+    let new_key = generate_relay_key();
+    let request = NetworkMessage {
+        msg_type: MessageType::KeyRotation,
+        device_id: local.id,
+        device_name: local.name.clone(),
+        data: serde_json::to_string(&KeyRotationPayload {
+            key: Some(new_key.clone()),
+            mac: relay_mac(&current_key, device.id, &key_rotation_message(local.id, &new_key))?,
+        }).ok(),
+        display_name: None,
+    };
+    let confirmed = await_device_reply(&state.network_stats, network_interface(&state).as_deref(), &device, &request, |reply| {
+        let confirmation = reply.data.as_deref().and_then(|data| serde_json::from_str::<KeyRotationPayload>(data).ok());
+        match (&reply.msg_type, confirmation) {
+            (MessageType::KeyRotation, Some(KeyRotationPayload { key: None, mac })) => {
+                verify_relay_mac(&new_key, local.id, &key_rotation_message(device.id, &new_key), &mac)
+            },
+            _ => false,
         }
+    }).await;
+    if !confirmed {
+        return Err(format!("{} did not confirm the new key", device.name));
     }
+
+    install_relay_key(&app_handle, device_id, new_key);
+    Ok(())
 }
 
-#[tauri::command]
-async fn get_clipboard_history(state: State<'_, AppState>) -> Result<Vec<ClipboardItem>, String> {
-    let history = state.clipboard_history.lock().unwrap();
-    Ok(history.clone())
+fn relay_outbox(state: &AppState) -> Option<RelayOutbox> {
+    state.relay_outbox.lock_or_recover().clone()
 }
 
-#[tauri::command]
-async fn get_clipboard_history_paginated(state: State<'_, AppState>, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        load_clipboard_history_paginated(&db_path, offset, limit)
-    } else {
-        Err("Database not initialized".to_string())
+// Drops the current relay connection and reconnects to the configured URL, if any
+fn restart_relay(app_handle: &AppHandle) {
+    let state = app_handle.state::<AppState>();
+    if let Some(task) = state.relay_task.lock_or_recover().take() {
+        task.abort();
     }
-}
+    *state.relay_outbox.lock_or_recover() = None;
 
-#[tauri::command]
-async fn get_clipboard_history_count(state: State<'_, AppState>) -> Result<u32, String> {
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        get_clipboard_history_count_from_db(&db_path)
+    let relay_url = state.settings.lock_or_recover().relay_url.clone();
+    if let Some(url) = relay_url {
+        let task = tauri::async_runtime::spawn(run_relay(app_handle.clone(), url));
+        *state.relay_task.lock_or_recover() = Some(task);
     } else {
-        Err("Database not initialized".to_string())
+        let _ = app_handle.emit("relay-status", false);
     }
 }
 
-#[tauri::command]
-async fn get_clipboard_files_count(state: State<'_, AppState>) -> Result<u32, String> {
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        get_clipboard_files_count_from_db(&db_path)
-    } else {
-        Err("Database not initialized".to_string())
+// Keeps a connection to the relay open, reconnecting with backoff when it drops
+async fn run_relay(app_handle: AppHandle, url: String) {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message;
+
+    wait_until_ready(&app_handle.state::<AppState>()).await;
+    let mut backoff = RELAY_RECONNECT_SECS;
+
+    loop {
+        let local_id = app_handle.state::<AppState>().local_device.lock_or_recover().as_ref().map(|d| d.id);
+        let Some(local_id) = local_id else {
+            return;
+        };
+
+        match tokio_tungstenite::connect_async(url.as_str()).await {
+            Ok((stream, _)) => {
+                let (mut sink, mut incoming) = stream.split();
+                let register = serde_json::to_string(&RelayFrame::Register { device_id: local_id }).unwrap_or_default();
+                if let Err(e) = sink.send(Message::text(register)).await {
+                    eprintln!("Failed to register with relay {}: {}", url, e);
+                } else {
+                    println!("Connected to relay {}", url);
+                    backoff = RELAY_RECONNECT_SECS;
+                    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<RelayFrame>();
+                    *app_handle.state::<AppState>().relay_outbox.lock_or_recover() = Some(tx);
+                    let _ = app_handle.emit("relay-status", true);
+
+                    loop {
+                        tokio::select! {
+                            outgoing = rx.recv() => {
+                                let Some(frame) = outgoing else { break };
+                                let msg_type = match &frame {
+                                    RelayFrame::Forward { message, .. } => Some(message.msg_type.clone()),
+                                    RelayFrame::Register { .. } => None,
+                                };
+                                let Ok(frame_json) = serde_json::to_string(&frame) else { continue };
+                                let frame_len = frame_json.len();
+                                if let Err(e) = sink.send(Message::text(frame_json)).await {
+                                    eprintln!("Failed to send to relay: {}", e);
+                                    break;
+                                }
+                                if let Some(msg_type) = msg_type {
+                                    app_handle.state::<AppState>().network_stats.record_sent(&msg_type, None, frame_len);
+                                }
+                            },
+                            received = incoming.next() => match received {
+                                Some(Ok(Message::Text(text))) => handle_relay_frame(&app_handle, text.as_str()),
+                                Some(Ok(Message::Ping(payload))) => {
+                                    let _ = sink.send(Message::Pong(payload)).await;
+                                },
+                                Some(Ok(Message::Close(_))) | None => break,
+                                Some(Ok(_)) => {},
+                                Some(Err(e)) => {
+                                    eprintln!("Relay connection error: {}", e);
+                                    break;
+                                },
+                            },
+                        }
+                    }
+
+                    *app_handle.state::<AppState>().relay_outbox.lock_or_recover() = None;
+                    let _ = app_handle.emit("relay-status", false);
+                    println!("Disconnected from relay {}", url);
+                }
+            },
+            Err(e) => eprintln!("Failed to connect to relay {}: {}", url, e),
+        }
+
+        sleep(Duration::from_secs(backoff)).await;
+        backoff = (backoff * 2).min(RELAY_RECONNECT_MAX_SECS);
     }
 }
 
-#[tauri::command]
-async fn get_clipboard_files_paginated(state: State<'_, AppState>, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        get_clipboard_files_paginated_from_db(&db_path, offset, limit)
-    } else {
-        Err("Database not initialized".to_string())
+// Handles a message a peer sent through the relay. Only paired devices may sync
+// this way, and only with a frame signed by the key agreed when pairing, since
+// anyone connected to the relay can claim any device id.
+fn handle_relay_frame<R: Runtime>(app_handle: &AppHandle<R>, text: &str) {
+    let (to, message, mac) = match serde_json::from_str::<RelayFrame>(text) {
+        Ok(RelayFrame::Forward { to, message, mac }) => (to, message, mac),
+        Ok(RelayFrame::Register { .. }) => return,
+        Err(e) => {
+            eprintln!("Ignoring malformed relay frame: {}", e);
+            return;
+        },
+    };
+
+    let app_state = app_handle.state::<AppState>();
+    app_state.network_stats.record_received(&message.msg_type, "relay", text.len());
+    let require_trust = app_state.settings.lock_or_recover().require_explicit_trust;
+    let device = app_state.devices.lock_or_recover()
+        .get(&message.device_id)
+        .filter(|device| is_sync_allowed(device, require_trust))
+        .cloned();
+    let Some(device) = device else {
+        println!("Ignoring relayed message from unpaired device: {} ({})", message.device_name, message.device_id);
+        return;
+    };
+    let local_id = app_state.local_device.lock_or_recover().as_ref().map(|d| d.id);
+    let authentic = match mac {
+        Some(mac) => Some(to) == local_id
+            && accepted_relay_keys(&app_state, &device).iter().any(|key| verify_relay_mac(key, to, &message, &mac)),
+        None => false,
+    };
+    if !authentic {
+        println!("Ignoring relayed message with a missing or bad signature claiming to be {} ({})", message.device_name, message.device_id);
+        return;
+    }
+    if is_sync_paused(&app_state) && matches!(message.msg_type, MessageType::ClipboardSync | MessageType::FileTransfer) {
+        println!("Sync paused on an untrusted network - ignoring relayed {:?}", message.msg_type);
+        return;
     }
-}
 
-#[tauri::command]
-async fn search_clipboard(state: State<'_, AppState>, query: String, offset: u32, limit: u32) -> Result<Vec<ClipboardItem>, String> {
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        search_clipboard_items(&db_path, &query, offset, limit)
-    } else {
-        Err("Database not initialized".to_string())
+    let Some(data) = message.data else {
+        return;
+    };
+    match message.msg_type {
+        MessageType::ClipboardSync => {
+            println!("Clipboard sync through relay from: {} ({})", message.device_name, message.device_id);
+            #[cfg(feature = "clipboard")]
+            if let Ok(synced_item) = serde_json::from_str::<ClipboardItem>(&data) {
+                if let Err(e) = check_synced_item_size(&app_state, &synced_item) {
+                    eprintln!("Refusing relayed clipboard sync from {}: {}", message.device_name, e);
+                    let _ = app_handle.emit("item-rejected", &e);
+                    return;
+                }
+                mark_device_synced(&app_state, message.device_id);
+                apply_synced_clipboard_item(&app_state, &synced_item, &message.device_name);
+                notify_synced_item(app_handle, &synced_item, &message.device_name);
+            }
+        },
+        MessageType::FileTransfer => {
+            if let Ok(header) = serde_json::from_str::<FileTransferHeader>(&data) {
+                begin_incoming_transfer(app_handle, message.device_id, header);
+            }
+        },
+        MessageType::FileTransferChunk => {
+            if let Ok(chunk) = serde_json::from_str::<FileChunk>(&data) {
+                receive_file_chunk(app_handle, message.device_id, chunk);
+            }
+        },
+        MessageType::FileTransferComplete => {
+            if let Ok(end) = serde_json::from_str::<FileTransferEnd>(&data) {
+                complete_incoming_transfer(app_handle, message.device_id, &message.device_name, &end.file_id);
+            }
+        },
+        MessageType::FileTransferCancel => {
+            if let Ok(cancel) = serde_json::from_str::<FileTransferEnd>(&data) {
+                handle_transfer_cancel(app_handle, message.device_id, cancel);
+            }
+        },
+        other => println!("Ignoring relayed {:?} from {}", other, message.device_name),
     }
 }
 
+// Routes sync and file messages through a WebSocket relay when a paired device
+// can't be reached over the LAN. An empty string disables the relay.
 #[tauri::command]
-async fn get_search_count(state: State<'_, AppState>, query: String) -> Result<u32, String> {
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        get_search_results_count(&db_path, &query)
+async fn set_relay_url(app_handle: AppHandle, state: State<'_, AppState>, url: String) -> Result<(), String> {
+    // Plain ws:// would expose every relayed clipboard item on the wire
+    let relay_url = if url.trim().is_empty() {
+        None
+    } else if url.trim().starts_with("wss://") {
+        Some(url.trim().to_string())
     } else {
-        Err("Database not initialized".to_string())
+        return Err("Relay URL must start with wss://".to_string());
+    };
+
+    state.settings.lock_or_recover().relay_url = relay_url.clone();
+    persist_settings(&state);
+    restart_relay(&app_handle);
+    match relay_url {
+        Some(url) => println!("Using relay {}", url),
+        None => println!("Relay disabled"),
     }
+    Ok(())
+}
+
+#[tauri::command]
+async fn is_relay_connected(state: State<'_, AppState>) -> Result<bool, String> {
+    Ok(relay_outbox(&state).is_some())
 }
 
 #[tauri::command]
-async fn clear_clipboard_history(state: State<'_, AppState>) -> Result<(), String> {
-    // Clear in-memory history
-    {
-        let mut history = state.clipboard_history.lock().unwrap();
-        history.clear();
-    }
-    
-    // Clear database
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        if let Err(e) = clear_clipboard_history_from_db(&db_path) {
-            eprintln!("Failed to clear clipboard history from database: {}", e);
-            return Err(e);
-        }
-    }
-    
+async fn trust_device(state: State<'_, AppState>, device_id: u32) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let device = {
+        let mut devices = state.devices.lock_or_recover();
+        let device = devices.get_mut(&device_id).ok_or("Device not found".to_string())?;
+        device.trusted = true;
+        device.clone()
+    };
+    persist_device(&state, &device);
+    println!("Trusted device: {} ({})", device.name, device_id);
     Ok(())
 }
 
+// Mutes or unmutes a device. Unlike remove_device this keeps the pairing and
+// doesn't tell the peer; sync in both directions simply stops until re-enabled.
 #[tauri::command]
-async fn delete_clipboard_item(state: State<'_, AppState>, id: String) -> Result<(), String> {
-    // Delete from in-memory history
-    {
-        let mut history = state.clipboard_history.lock().unwrap();
-        history.retain(|item| item.id != id);
-    }
-    
-    // Delete from database
-    let db_path = state.db_path.lock().unwrap().clone();
-    if let Some(db_path) = db_path {
-        if let Err(e) = delete_clipboard_item_from_db(&db_path, &id) {
-            eprintln!("Failed to delete clipboard item from database: {}", e);
-            return Err(e);
-        }
-    }
-    
+async fn set_device_enabled(state: State<'_, AppState>, device_id: u32, enabled: bool) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let device = {
+        let mut devices = state.devices.lock_or_recover();
+        let device = devices.get_mut(&device_id).ok_or("Device not found".to_string())?;
+        device.disabled = !enabled;
+        device.clone()
+    };
+    persist_device(&state, &device);
+    println!("{} device: {} ({})", if enabled { "Enabled" } else { "Disabled" }, device.name, device_id);
     Ok(())
 }
 
-#[cfg(feature = "clipboard")]
+// Starts a misbehaving pairing over without touching other devices: transfers
+// in either direction are dropped, and only items copied from now on sync.
+// Sync has no per-device queue or acknowledgements, so this is all the state there is.
 #[tauri::command]
-async fn set_clipboard_content(content: String, state: State<'_, AppState>) -> Result<(), String> {
-    // Set ignore flag to prevent the monitor from detecting this as a new change
-    {
-        let mut ignore = state.ignore_next_clipboard_change.lock().unwrap();
-        *ignore = true;
+async fn reset_device_sync(app_handle: AppHandle, state: State<'_, AppState>, device_id: u32) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let device = {
+        let mut devices = state.devices.lock_or_recover();
+        let device = devices.get_mut(&device_id).ok_or("Device not found".to_string())?;
+        device.sync_mode = SyncMode::PartialSync;
+        device.last_synced = None;
+        device.clone()
+    };
+    persist_device(&state, &device);
+    // A fresh switch to total sync sends the whole history again
+    state.total_sync_sent_at.lock_or_recover().remove(&device_id);
+
+    let transfers: Vec<FileTransferProgress> = state.active_transfers.lock_or_recover()
+        .values()
+        .filter(|transfer| transfer.device_id == device_id)
+        .cloned()
+        .collect();
+    for transfer in transfers {
+        match transfer.direction {
+            // The send loop notices this before its next chunk and tells the device
+            TransferDirection::Send => {
+                state.cancelled_transfers.lock_or_recover().insert((transfer.file_id, Some(device_id)));
+            },
+            TransferDirection::Receive => {
+                discard_incoming_transfer(&app_handle, &transfer.file_id, device_id);
+            },
+        }
     }
-    
-    if let Ok(mut clipboard) = Clipboard::new() {
-        clipboard.set_text(content).map_err(|e| e.to_string())?;
+
+    let _ = app_handle.emit("device-sync-reset", device_id);
+    println!("Reset sync state for device: {} ({})", device.name, device_id);
+    Ok(())
+}
+
+// Mutating commands call this first so a curated history can't be changed by accident
+fn ensure_writable(state: &AppState) -> Result<(), String> {
+    if state.settings.lock_or_recover().read_only {
+        return Err("Read-only mode is on".to_string());
     }
     Ok(())
 }
 
-#[cfg(not(feature = "clipboard"))]
+// The UI must confirm with the user before calling this, in either direction
 #[tauri::command]
-async fn set_clipboard_content(_content: String, _state: State<'_, AppState>) -> Result<(), String> {
-    Err("Clipboard functionality not available on this platform".to_string())
+async fn set_read_only(app_handle: AppHandle, state: State<'_, AppState>, enabled: bool, confirmed: bool) -> Result<(), String> {
+    if !confirmed {
+        return Err("Changing read-only mode requires confirmation".to_string());
+    }
+
+    state.settings.lock_or_recover().read_only = enabled;
+    persist_settings(&state);
+    let _ = app_handle.emit("read-only-changed", enabled);
+    println!("Read-only mode {}", if enabled { "enabled" } else { "disabled" });
+    Ok(())
 }
 
 #[tauri::command]
-async fn toggle_monitoring(state: State<'_, AppState>) -> Result<bool, String> {
-    let mut enabled = state.enabled.lock().unwrap();
-    *enabled = !*enabled;
-    let is_enabled = *enabled;
-    println!("Clipboard monitoring {}", if is_enabled { "enabled" } else { "disabled" });
-    Ok(is_enabled)
+fn is_read_only(state: State<AppState>) -> bool {
+    state.settings.lock_or_recover().read_only
 }
 
 #[tauri::command]
-async fn is_monitoring_enabled(state: State<'_, AppState>) -> Result<bool, String> {
-    let enabled = state.enabled.lock().unwrap();
-    Ok(*enabled)
+fn get_settings(state: State<AppState>) -> Settings {
+    state.settings.lock_or_recover().clone()
 }
 
 #[tauri::command]
 async fn add_clipboard_item(item: ClipboardItem, state: State<'_, AppState>) -> Result<(), String> {
-    let mut history = state.clipboard_history.lock().unwrap();
+    ensure_writable(&state)?;
+    check_item_size(&state, &item)?;
+    let mut history = state.clipboard_history.lock_or_recover();
     
     // Add item to the beginning of the history (LIFO)
     history.insert(0, item);
@@ -1313,23 +6557,29 @@ async fn add_clipboard_item(item: ClipboardItem, state: State<'_, AppState>) ->
 }
 
 #[tauri::command]
-fn add_device(state: State<AppState>, device: Device) {
-    let mut devices = state.devices.lock().unwrap();
+fn add_device(state: State<AppState>, device: Device) -> Result<(), String> {
+    ensure_writable(&state)?;
+    persist_device(&state, &device);
+    remove_from_transient_lists(&state, device.id);
+    record_connection_event(&state, ConnectionEvent::Accepted, device.id, &device.name, &device.ip, Some("Added manually"));
+    let mut devices = state.devices.lock_or_recover();
     devices.insert(device.id, device);
+    Ok(())
 }
 
 #[tauri::command]
 async fn remove_device(state: State<'_, AppState>, device_id: u32) -> Result<(), String> {
+    ensure_writable(&state)?;
     // Get device info before removing it
     let device_to_remove = {
-        let devices = state.devices.lock().unwrap();
+        let devices = state.devices.lock_or_recover();
         devices.get(&device_id).cloned()
     };
     
     if let Some(device) = device_to_remove {
         // Get local device info for the disconnection message
         let local_device = {
-            let local = state.local_device.lock().unwrap();
+            let local = state.local_device.lock_or_recover();
             local.clone()
         };
         
@@ -1340,19 +6590,22 @@ async fn remove_device(state: State<'_, AppState>, device_id: u32) -> Result<(),
                 device_id: local.id,
                 device_name: local.name,
                 data: None,
+                display_name: None,
             };
             
-            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
+            if let Ok(socket) = bind_udp_socket(network_interface(&state).as_deref()).await {
                 let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
                 let target_addr = format!("{}:51847", device.ip);
-                let _ = socket.send_to(message_json.as_bytes(), &target_addr).await;
-                println!("Sent disconnection notice to {} at {}", device.name, device.ip);
+                match send_datagram(&state.network_stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&device.ip)).await {
+                    Ok(()) => println!("Sent disconnection notice to {} at {}", device.name, device.ip),
+                    Err(e) => eprintln!("{}", e),
+                }
             }
         }
         
         // Remove from local devices list
         {
-            let mut devices = state.devices.lock().unwrap();
+            let mut devices = state.devices.lock_or_recover();
             let removed = devices.remove(&device_id);
             println!("Device removal from HashMap: {:?}", removed.is_some());
             println!("Remaining connected devices: {}", devices.len());
@@ -1360,6 +6613,13 @@ async fn remove_device(state: State<'_, AppState>, device_id: u32) -> Result<(),
                 println!("  - {} (ID: {}): {:?} at {}", dev.name, id, dev.status, dev.ip);
             }
         }
+        forget_device(&state, device_id);
+        record_connection_event(&state, ConnectionEvent::Removed, device.id, &device.name, &device.ip, None);
+
+        // Only offer it for pairing again if it's been heard from recently
+        if get_current_timestamp().saturating_sub(device.last_seen) <= HEARTBEAT_FRESHNESS_SECS {
+            rediscover_device(&state, &device);
+        }
         
         println!("Removed device: {} ({})", device.name, device_id);
         Ok(())
@@ -1370,30 +6630,50 @@ async fn remove_device(state: State<'_, AppState>, device_id: u32) -> Result<(),
 
 #[tauri::command]
 fn sync_clipboard(state: State<AppState>, item: ClipboardItem) {
-    let mut history = state.clipboard_history.lock().unwrap();
+    let mut history = state.clipboard_history.lock_or_recover();
     history.push(item);
 }
 
 #[tauri::command]
 fn get_local_device(state: State<AppState>) -> Option<Device> {
-    state.local_device.lock().unwrap().clone()
+    state.local_device.lock_or_recover().clone()
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct NetworkInfo {
+    ip: Option<String>, // The address other devices should connect to
+    port: Option<u16>, // None while the UDP server isn't listening
+    interface: Option<String>, // Interface outgoing traffic is bound to; None lets the OS choose
+}
+
+// What to allow through a firewall, or enter on another device
+#[tauri::command]
+fn get_network_info(state: State<AppState>) -> NetworkInfo {
+    NetworkInfo {
+        ip: state.local_device.lock_or_recover().as_ref().map(|device| device.ip.clone()),
+        port: state.server_addr.lock_or_recover().map(|addr| addr.port()),
+        interface: network_interface(&state),
+    }
 }
 
 #[tauri::command]
 fn get_connected_devices(state: State<AppState>) -> Vec<Device> {
-    let devices = state.devices.lock().unwrap();
-    devices.values().cloned().collect()
+    let local_id = state.local_device.lock_or_recover().as_ref().map(|d| d.id);
+    let devices = state.devices.lock_or_recover();
+    devices.values().filter(|d| Some(d.id) != local_id).cloned().collect()
 }
 
 #[tauri::command]
 async fn send_connection_request(state: State<'_, AppState>, ip_or_tag: String) -> Result<(), String> {
-    let local_device = state.local_device.lock().unwrap().clone();
+    ensure_writable(&state)?;
+    let local_device = state.local_device.lock_or_recover().clone();
     if let Some(device) = local_device {
         let message = NetworkMessage {
             msg_type: MessageType::ConnectionRequest,
             device_id: device.id,
             device_name: device.name,
             data: None,
+            display_name: device.display_name,
         };
         
         // Parse IP or tag
@@ -1405,12 +6685,10 @@ async fn send_connection_request(state: State<'_, AppState>, ip_or_tag: String)
         };
         
         // Send UDP message
-        if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
+        if let Ok(socket) = bind_udp_socket(network_interface(&state).as_deref()).await {
             let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
             let target_addr = format!("{}:51847", target_ip);
-            if let Err(e) = socket.send_to(message_json.as_bytes(), &target_addr).await {
-                return Err(format!("Failed to send connection request: {}", e));
-            }
+            send_datagram(&state.network_stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&target_ip)).await?;
             println!("Connection request sent to {}", target_addr);
             Ok(())
         } else {
@@ -1423,13 +6701,28 @@ async fn send_connection_request(state: State<'_, AppState>, ip_or_tag: String)
 
 #[tauri::command]
 async fn accept_connection(state: State<'_, AppState>, device_id: u32) -> Result<(), String> {
+    ensure_writable(&state)?;
+    if let Some(reason) = device_limit_reason(&state, device_id) {
+        // Answer the request so the other side isn't left waiting
+        let pending = {
+            let mut pending = state.pending_connections.lock_or_recover();
+            pending.iter().position(|d| d.id == device_id).map(|pos| pending.remove(pos))
+        };
+        if let Some(device) = pending {
+            record_connection_event(&state, ConnectionEvent::Denied, device.id, &device.name, &device.ip, Some(&reason));
+            send_connection_deny(&state, &device.ip, Some(reason.clone())).await?;
+        }
+        return Err(reason);
+    }
+
     // Extract data from locks before any async operations
     let device_opt = {
-        let mut pending = state.pending_connections.lock().unwrap();
+        let mut pending = state.pending_connections.lock_or_recover();
         if let Some(pos) = pending.iter().position(|d| d.id == device_id) {
             let mut device = pending.remove(pos);
             device.status = DeviceStatus::Connected;
             device.sync_mode = SyncMode::PartialSync; // Default to partial sync
+            device.relay_key = Some(generate_relay_key());
             Some(device)
         } else {
             None
@@ -1439,29 +6732,35 @@ async fn accept_connection(state: State<'_, AppState>, device_id: u32) -> Result
     if let Some(device) = device_opt {
         // Add to connected devices
         {
-            let mut devices = state.devices.lock().unwrap();
+            let mut devices = state.devices.lock_or_recover();
             devices.insert(device_id, device.clone());
         }
+        remove_from_transient_lists(&state, device_id);
+        persist_device(&state, &device);
+        record_connection_event(&state, ConnectionEvent::Accepted, device.id, &device.name, &device.ip, None);
         
         // Get local device info
         let local_device = {
-            let local = state.local_device.lock().unwrap();
+            let local = state.local_device.lock_or_recover();
             local.clone()
         };
         
-        // Send acceptance message
+        // Send acceptance message, handing the peer the key for relayed messages
         if let Some(local) = local_device {
             let message = NetworkMessage {
                 msg_type: MessageType::ConnectionAccept,
                 device_id: local.id,
                 device_name: local.name,
-                data: None,
+                data: device.relay_key.clone(),
+                display_name: local.display_name,
             };
             
-            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
+            if let Ok(socket) = bind_udp_socket(network_interface(&state).as_deref()).await {
                 let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
                 let target_addr = format!("{}:51847", device.ip);
-                let _ = socket.send_to(message_json.as_bytes(), &target_addr).await;
+                if let Err(e) = send_datagram(&state.network_stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&device.ip)).await {
+                    eprintln!("{}", e);
+                }
             }
         }
         
@@ -1474,9 +6773,10 @@ async fn accept_connection(state: State<'_, AppState>, device_id: u32) -> Result
 
 #[tauri::command]
 async fn deny_connection(state: State<'_, AppState>, device_id: u32) -> Result<(), String> {
+    ensure_writable(&state)?;
     // Extract data from locks before any async operations
     let device_opt = {
-        let mut pending = state.pending_connections.lock().unwrap();
+        let mut pending = state.pending_connections.lock_or_recover();
         if let Some(pos) = pending.iter().position(|d| d.id == device_id) {
             Some(pending.remove(pos))
         } else {
@@ -1485,28 +6785,8 @@ async fn deny_connection(state: State<'_, AppState>, device_id: u32) -> Result<(
     };
     
     if let Some(device) = device_opt {
-        // Get local device info
-        let local_device = {
-            let local = state.local_device.lock().unwrap();
-            local.clone()
-        };
-        
-        // Send denial message
-        if let Some(local) = local_device {
-            let message = NetworkMessage {
-                msg_type: MessageType::ConnectionDeny,
-                device_id: local.id,
-                device_name: local.name,
-                data: None,
-            };
-            
-            if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
-                let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
-                let target_addr = format!("{}:51847", device.ip);
-                let _ = socket.send_to(message_json.as_bytes(), &target_addr).await;
-            }
-        }
-        
+        record_connection_event(&state, ConnectionEvent::Denied, device.id, &device.name, &device.ip, None);
+        send_connection_deny(&state, &device.ip, None).await?;
         println!("Connection denied for device: {}", device.name);
         Ok(())
     } else {
@@ -1514,36 +6794,181 @@ async fn deny_connection(state: State<'_, AppState>, device_id: u32) -> Result<(
     }
 }
 
+// Tells a device its connection was refused; the reason, if any, travels in `data`
+async fn send_connection_deny(state: &AppState, ip: &str, reason: Option<String>) -> Result<(), String> {
+    let local_device = {
+        let local = state.local_device.lock_or_recover();
+        local.clone()
+    };
+
+    if let Some(local) = local_device {
+        let message = NetworkMessage {
+            msg_type: MessageType::ConnectionDeny,
+            device_id: local.id,
+            device_name: local.name,
+            data: reason,
+            display_name: None,
+        };
+
+        if let Ok(socket) = bind_udp_socket(network_interface(state).as_deref()).await {
+            let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+            let target_addr = format!("{}:51847", ip);
+            if let Err(e) = send_datagram(&state.network_stats, &socket, &message.msg_type, &message_json, &target_addr, Some(ip)).await {
+                eprintln!("{}", e);
+            }
+        }
+    }
+    Ok(())
+}
+
 #[tauri::command]
 fn get_pending_connections(state: State<AppState>) -> Vec<Device> {
-    state.pending_connections.lock().unwrap().clone()
+    state.pending_connections.lock_or_recover().clone()
+}
+
+// Who asked to connect, and who was accepted, denied or removed, newest first
+#[tauri::command]
+async fn get_connection_audit(state: State<'_, AppState>, limit: u32, offset: u32) -> Result<Vec<ConnectionAuditEntry>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    load_connection_audit_from_db(&db_path, limit, offset)
+}
+
+#[tauri::command]
+async fn clear_connection_audit(state: State<'_, AppState>) -> Result<(), String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let conn = Connection::open(&db_path).map_err(|e| e.to_string())?;
+    conn.execute("DELETE FROM connection_audit", []).map_err(|e| e.to_string())?;
+    println!("Cleared connection audit log");
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DeviceOverview {
+    connected: Vec<Device>,
+    pending: Vec<Device>,
+    discovered: Vec<Device>,
+    local: Option<Device>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct StatusSummary {
+    connected_count: usize,
+    syncing_count: usize, // Connected devices that new items are actually sent to
+    pending_count: usize,
+    monitoring_enabled: bool,
+    monitor_alive: bool, // False when the monitor loop never started or has died
+    history_count: usize,
+    read_only: bool,
 }
 
+// Counts for a tray/status badge in one call. Each lock is taken on its own
+// and only long enough to count, so the monitor and UDP tasks aren't held up.
 #[tauri::command]
-async fn set_sync_mode(state: State<'_, AppState>, device_id: u32, sync_mode: String) -> Result<(), String> {
-    // Parse sync mode first
-    let parsed_sync_mode = match sync_mode.as_str() {
-        "total" => SyncMode::TotalSync,
-        "partial" => SyncMode::PartialSync,
-        "disabled" => SyncMode::Disabled,
-        _ => return Err("Invalid sync mode".to_string()),
+fn get_status_summary(state: State<AppState>) -> StatusSummary {
+    let local_id = state.local_device.lock_or_recover().as_ref().map(|d| d.id);
+    let require_trust = state.settings.lock_or_recover().require_explicit_trust;
+
+    let (connected_count, syncing_count) = {
+        let devices = state.devices.lock_or_recover();
+        let connected = devices.values()
+            .filter(|d| Some(d.id) != local_id && matches!(d.status, DeviceStatus::Connected));
+        connected.fold((0, 0), |(connected, syncing), device| {
+            let is_syncing = !matches!(device.sync_mode, SyncMode::Disabled) && is_sync_allowed(device, require_trust);
+            (connected + 1, syncing + usize::from(is_syncing))
+        })
     };
-    
+
+    StatusSummary {
+        connected_count,
+        syncing_count,
+        pending_count: state.pending_connections.lock_or_recover().len(),
+        monitoring_enabled: *state.enabled.lock_or_recover(),
+        monitor_alive: *state.monitor_alive.lock_or_recover(),
+        history_count: state.clipboard_history.lock_or_recover().len(),
+        read_only: state.settings.lock_or_recover().read_only,
+    }
+}
+
+// Snapshot of every device list at once, so the UI never sees a device
+// mid-move between two lists
+#[tauri::command]
+fn get_device_overview(state: State<AppState>) -> DeviceOverview {
+    let devices = state.devices.lock_or_recover();
+    let pending = state.pending_connections.lock_or_recover();
+    let discovered = state.discovered_devices.lock_or_recover();
+    let local = state.local_device.lock_or_recover();
+
+    let local_id = local.as_ref().map(|l| l.id);
+    DeviceOverview {
+        connected: devices.values().filter(|d| Some(d.id) != local_id).cloned().collect(),
+        pending: pending.clone(),
+        discovered: discovered.clone(),
+        local: local.clone(),
+    }
+}
+
+fn parse_sync_mode(sync_mode: &str) -> Result<SyncMode, String> {
+    match sync_mode {
+        "total" => Ok(SyncMode::TotalSync),
+        "partial" => Ok(SyncMode::PartialSync),
+        "disabled" => Ok(SyncMode::Disabled),
+        _ => Err("Invalid sync mode".to_string()),
+    }
+}
+
+#[tauri::command]
+async fn set_sync_mode(app_handle: AppHandle, state: State<'_, AppState>, device_id: u32, sync_mode: String) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let parsed_sync_mode = parse_sync_mode(&sync_mode)?;
+    apply_sync_mode(&app_handle, device_id, parsed_sync_mode).await
+}
+
+// A device switched back into total sync this soon after its last full
+// history send isn't sent it again, so flapping modes can't flood the peer
+const TOTAL_SYNC_DEBOUNCE: Duration = Duration::from_secs(30);
+
+// Whether moving a device from `previous` to `next` should send it our whole
+// history: only on an actual switch into total sync, and not again within
+// TOTAL_SYNC_DEBOUNCE of the last send
+fn starts_total_sync(previous: SyncMode, next: SyncMode, last_sent: Option<std::time::Instant>) -> bool {
+    !matches!(previous, SyncMode::TotalSync)
+        && matches!(next, SyncMode::TotalSync)
+        && last_sent.is_none_or(|sent| sent.elapsed() >= TOTAL_SYNC_DEBOUNCE)
+}
+
+// Updates one paired device's sync mode, sending it our history when switching to total sync
+async fn apply_sync_mode<R: Runtime>(app_handle: &AppHandle<R>, device_id: u32, parsed_sync_mode: SyncMode) -> Result<(), String> {
+    let state = app_handle.state::<AppState>();
+    let state = state.inner();
+    let sync_paused = is_sync_paused(state);
     // Extract data before async operations
     let (device_info, history, local_device) = {
-        let mut devices = state.devices.lock().unwrap();
+        let mut devices = state.devices.lock_or_recover();
         if let Some(device) = devices.get_mut(&device_id) {
+            let last_sent = state.total_sync_sent_at.lock_or_recover().get(&device_id).copied();
+            let mut send_history = starts_total_sync(device.sync_mode, parsed_sync_mode, last_sent);
+            if send_history && sync_paused {
+                println!("Sync paused on an untrusted network - not sending our history to {}", device.name);
+                send_history = false;
+            } else if matches!(parsed_sync_mode, SyncMode::TotalSync) && !send_history {
+                println!("{} already had our history recently - not resending it", device.name);
+            }
             device.sync_mode = parsed_sync_mode.clone();
             let device_info = (device.ip.clone(), device.name.clone());
             
             // Get history and local device if needed for total sync
-            let history = if matches!(parsed_sync_mode, SyncMode::TotalSync) {
-                state.clipboard_history.lock().unwrap().clone()
+            let history = if send_history {
+                state.clipboard_history.lock_or_recover().iter()
+                    .filter(|item| !item.no_sync)
+                    .cloned()
+                    .collect()
             } else {
                 Vec::new()
             };
             
-            let local_device = state.local_device.lock().unwrap().clone();
+            let local_device = state.local_device.lock_or_recover().clone();
             
             (Some(device_info), history, local_device)
         } else {
@@ -1551,10 +6976,20 @@ async fn set_sync_mode(state: State<'_, AppState>, device_id: u32, sync_mode: St
         }
     };
     
+    if device_info.is_some() {
+        let updated_device = state.devices.lock_or_recover().get(&device_id).cloned();
+        if let Some(device) = updated_device {
+            persist_device(state, &device);
+        }
+    }
+    
     if let Some((device_ip, device_name)) = device_info {
         // If switching to total sync, send entire history
-        if matches!(parsed_sync_mode, SyncMode::TotalSync) && !history.is_empty() {
+        if !history.is_empty() {
             if let Some(local) = local_device {
+                state.total_sync_sent_at.lock_or_recover().insert(device_id, std::time::Instant::now());
+                let total = history.len();
+                let mut failures = Vec::new();
                 for item in history {
                     // Send each item to the device
                     let message = NetworkMessage {
@@ -1562,14 +6997,22 @@ async fn set_sync_mode(state: State<'_, AppState>, device_id: u32, sync_mode: St
                         device_id: local.id,
                         device_name: local.name.clone(),
                         data: Some(serde_json::to_string(&item).unwrap_or_default()),
+                        display_name: None,
                     };
                     
-                    if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
+                    if let Ok(socket) = bind_udp_socket(network_interface(state).as_deref()).await {
                         let message_json = serde_json::to_string(&message).unwrap_or_default();
                         let target_addr = format!("{}:51847", device_ip);
-                        let _ = socket.send_to(message_json.as_bytes(), &target_addr).await;
+                        if let Err(e) = send_datagram(&state.network_stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&device_ip)).await {
+                            failures.push(e);
+                        }
                     }
                 }
+                // One report per total sync rather than one per item
+                if let Some(last_error) = failures.last() {
+                    let error = format!("{} of {} history items not sent: {}", failures.len(), total, last_error);
+                    report_sync_send_failure(app_handle, device_id, &error);
+                }
                 println!("Total sync initiated for device: {}", device_name);
             }
         }
@@ -1581,94 +7024,450 @@ async fn set_sync_mode(state: State<'_, AppState>, device_id: u32, sync_mode: St
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+struct SyncModeChange {
+    device_id: u32,
+    device_name: String,
+    error: Option<String>, // None when the new mode was applied
+}
+
+// Applies one sync mode to every paired device, e.g. to pause all syncing at once
+#[tauri::command]
+async fn set_all_sync_modes(app_handle: AppHandle, state: State<'_, AppState>, sync_mode: String) -> Result<Vec<SyncModeChange>, String> {
+    ensure_writable(&state)?;
+    let parsed_sync_mode = parse_sync_mode(&sync_mode)?;
+
+    let local_id = state.local_device.lock_or_recover().as_ref().map(|d| d.id);
+    let targets: Vec<(u32, String)> = state.devices.lock_or_recover()
+        .values()
+        .filter(|d| Some(d.id) != local_id)
+        .map(|d| (d.id, d.name.clone()))
+        .collect();
+
+    let mut changes = Vec::with_capacity(targets.len());
+    for (device_id, device_name) in targets {
+        let error = apply_sync_mode(&app_handle, device_id, parsed_sync_mode).await.err();
+        if let Some(ref e) = error {
+            eprintln!("Failed to set sync mode for {}: {}", device_name, e);
+        }
+        changes.push(SyncModeChange { device_id, device_name, error });
+    }
+
+    Ok(changes)
+}
+
+#[tauri::command]
+fn get_sync_modes(state: State<AppState>) -> HashMap<u32, SyncMode> {
+    let local_id = state.local_device.lock_or_recover().as_ref().map(|d| d.id);
+    state.devices.lock_or_recover()
+        .values()
+        .filter(|d| Some(d.id) != local_id)
+        .map(|d| (d.id, d.sync_mode))
+        .collect()
+}
+
+// Snapshot of the UDP traffic counters; peers are matched to known devices by address
+#[tauri::command]
+fn get_network_stats(state: State<AppState>) -> NetworkStatsSnapshot {
+    // Copy the counters first so the stats lock is never held while taking the device locks
+    let (total, by_message_type, by_peer, last_discovery_scan) = {
+        let counters = state.network_stats.counters.lock_or_recover();
+        (counters.total.clone(), counters.by_message_type.clone(), counters.by_peer.clone(), counters.last_discovery_scan.clone())
+    };
+    let known_devices = known_peer_devices(&state);
+
+    let by_device = by_peer.into_iter()
+        .map(|(ip, traffic)| {
+            let device = known_devices.iter().find(|d| d.ip == ip);
+            PeerTraffic {
+                device_id: device.map(|d| d.id),
+                device_name: device.map(|d| d.name.clone()),
+                ip,
+                traffic,
+            }
+        })
+        .collect();
+
+    NetworkStatsSnapshot {
+        uptime_secs: state.network_stats.started_at.elapsed().as_secs(),
+        total,
+        by_message_type,
+        by_device,
+        last_discovery_scan,
+    }
+}
+
+// Paired, discovered and pending devices, i.e. every peer worth keeping traffic counters for
+fn known_peer_devices(state: &AppState) -> Vec<Device> {
+    let devices = state.devices.lock_or_recover();
+    let discovered = state.discovered_devices.lock_or_recover();
+    let pending = state.pending_connections.lock_or_recover();
+    devices.values().chain(discovered.iter()).chain(pending.iter()).cloned().collect()
+}
+
+// Forgets traffic counters for addresses that aren't a known device any more
+fn prune_peer_stats<R: Runtime>(app_handle: &AppHandle<R>) {
+    let state = app_handle.state::<AppState>();
+    let known_ips: HashSet<String> = known_peer_devices(&state).into_iter().map(|d| d.ip).collect();
+    state.network_stats.prune_peers(&known_ips);
+}
+
+// How long diagnose_connection waits for the peer's echo
+const DIAGNOSTIC_ECHO_TIMEOUT_MS: u64 = 2000;
+
+#[derive(Debug, Clone, Serialize)]
+struct DiagnosticStep {
+    name: String,
+    passed: bool,
+    latency_ms: Option<u64>,
+    detail: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ConnectionDiagnosis {
+    device_id: u32,
+    device_name: String,
+    ip: String,
+    passed: bool,
+    steps: Vec<DiagnosticStep>,
+}
+
+fn diagnostic_step(name: &str, started: std::time::Instant, result: Result<String, String>) -> DiagnosticStep {
+    let latency_ms = Some(started.elapsed().as_millis() as u64);
+    match result {
+        Ok(detail) => DiagnosticStep { name: name.to_string(), passed: true, latency_ms, detail },
+        Err(detail) => DiagnosticStep { name: name.to_string(), passed: false, latency_ms, detail },
+    }
+}
+
+// Sends an Echo probe to `target_addr` and waits for the reply carrying the same nonce
+async fn send_echo(stats: &NetworkStats, socket: &UdpSocket, local: &Device, device: &Device, target_addr: &str) -> Result<(NetworkMessage, EchoPayload), String> {
+    let nonce = generate_id().to_string();
+    let message = NetworkMessage {
+        msg_type: MessageType::Echo,
+        device_id: local.id,
+        device_name: local.name.clone(),
+        data: serde_json::to_string(&EchoPayload { nonce: nonce.clone(), paired: None }).ok(),
+        display_name: None,
+    };
+    let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+    send_datagram(stats, socket, &message.msg_type, &message_json, target_addr, Some(&device.ip)).await?;
+
+    let mut buf = [0; 1024];
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(DIAGNOSTIC_ECHO_TIMEOUT_MS);
+    while let Ok(Ok((len, addr))) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+        let Ok(reply) = serde_json::from_slice::<NetworkMessage>(&buf[..len]) else {
+            continue;
+        };
+        stats.record_received(&reply.msg_type, &addr.ip().to_string(), len);
+        let payload = reply.data.as_deref().and_then(|data| serde_json::from_str::<EchoPayload>(data).ok());
+        if let (MessageType::Echo, Some(payload)) = (&reply.msg_type, payload) {
+            if payload.nonce == nonce && addr.ip().to_string() == device.ip {
+                return Ok((reply, payload));
+            }
+        }
+    }
+
+    Err(format!("No echo within {}ms; the app may not be running or UDP port 51847 is blocked", DIAGNOSTIC_ECHO_TIMEOUT_MS))
+}
+
+// Checks step by step whether a paired device can be reached: its address, the
+// UDP port (via an Echo round trip) and whether the peer still has us paired.
+// Steps after the first failure are reported as skipped.
+#[tauri::command]
+async fn diagnose_connection(state: State<'_, AppState>, device_id: u32) -> Result<ConnectionDiagnosis, String> {
+    let local = state.local_device.lock_or_recover().clone()
+        .ok_or("Local device not initialized".to_string())?;
+    let device = state.devices.lock_or_recover().get(&device_id).cloned()
+        .ok_or("Device not found".to_string())?;
+    let interface = network_interface(&state);
+    let target_addr = format!("{}:51847", device.ip);
+    let mut steps = Vec::new();
+
+    // 1. The address parses and the OS has a route to it
+    let started = std::time::Instant::now();
+    let socket = bind_udp_socket(interface.as_deref()).await.map_err(|e| e.to_string())?;
+    let route = match device.ip.parse::<std::net::IpAddr>() {
+        Ok(ip) => match std::net::UdpSocket::bind((interface.as_deref().unwrap_or("0.0.0.0"), 0))
+            .and_then(|probe| probe.connect((ip, 51847)).and_then(|_| probe.local_addr()))
+        {
+            Ok(local_addr) => Ok(format!("{} is routable from {}", ip, local_addr.ip())),
+            Err(e) => Err(format!("No route to {}: {}", ip, e)),
+        },
+        Err(_) => Err(format!("{} is not a valid IP address", device.ip)),
+    };
+    steps.push(diagnostic_step("address", started, route));
+
+    // 2. The peer's app answers on its UDP port
+    let mut echo = None;
+    if steps.iter().all(|step| step.passed) {
+        let started = std::time::Instant::now();
+        let result = send_echo(&state.network_stats, &socket, &local, &device, &target_addr).await;
+        let outcome = result.as_ref()
+            .map(|(reply, _)| format!("{} answered on {}", reply.device_name, target_addr))
+            .map_err(|e| e.clone());
+        steps.push(diagnostic_step("port", started, outcome));
+        echo = result.ok();
+    }
+
+    // 3. The responder is the device we paired with, and it still has us paired
+    if let Some((reply, payload)) = echo {
+        let started = std::time::Instant::now();
+        let outcome = if reply.device_id != device.id {
+            Err(format!("{} answered as a different device ({})", target_addr, reply.device_id))
+        } else if payload.paired != Some(true) {
+            Err(format!("{} doesn't have this device paired, or requires it to be trusted", reply.device_name))
+        } else {
+            Ok(format!("{} has this device paired", reply.device_name))
+        };
+        steps.push(diagnostic_step("pairing", started, outcome));
+    }
+
+    for name in ["address", "port", "pairing"] {
+        if !steps.iter().any(|step| step.name == name) {
+            steps.push(DiagnosticStep {
+                name: name.to_string(),
+                passed: false,
+                latency_ms: None,
+                detail: "Skipped after an earlier check failed".to_string(),
+            });
+        }
+    }
+
+    Ok(ConnectionDiagnosis {
+        device_id: device.id,
+        device_name: device.name,
+        ip: device.ip,
+        passed: steps.iter().all(|step| step.passed),
+        steps,
+    })
+}
+
+// Lets the frontend hold networking calls until setup has finished
+#[tauri::command]
+async fn wait_for_ready(state: State<'_, AppState>) -> Result<(), String> {
+    tokio::time::timeout(Duration::from_secs(READY_TIMEOUT_SECS), wait_until_ready(&state))
+        .await
+        .map_err(|_| "App did not finish starting".to_string())
+}
+
+// Announces this device to peers without waiting for replies; their passive
+// discovery handlers add it to their discovered lists
+#[tauri::command]
+async fn announce_presence(state: State<'_, AppState>) -> Result<(), String> {
+    let local = state.local_device.lock_or_recover().clone()
+        .ok_or("Local device not initialized".to_string())?;
+
+    let message = NetworkMessage {
+        msg_type: MessageType::Discovery,
+        device_id: local.id,
+        device_name: local.name,
+        data: None,
+        display_name: local.display_name,
+    };
+    let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
+
+    let socket = bind_udp_socket(network_interface(&state).as_deref()).await.map_err(|e| e.to_string())?;
+    socket.set_broadcast(true).map_err(|e| e.to_string())?;
+
+    let network_bases = get_local_network_bases(network_interface(&state).as_deref());
+    if network_bases.is_empty() {
+        return Err("Could not determine the local network".to_string());
+    }
+    for network_base in network_bases {
+        let broadcast_addr = format!("{}.255:51847", network_base);
+        match send_datagram(&state.network_stats, &socket, &message.msg_type, &message_json, &broadcast_addr, None).await {
+            Ok(()) => println!("Announced presence on {}", broadcast_addr),
+            Err(e) => eprintln!("Failed to announce presence: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+// Marks the running discovery scan as finished however discover_devices returns
+struct DiscoveryGuard<'a>(&'a AppState);
+
+impl Drop for DiscoveryGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.discovery_in_progress.lock_or_recover() = false;
+        self.0.discovery_done.notify_waiters();
+    }
+}
+
 #[tauri::command]
-async fn discover_devices(state: State<'_, AppState>) -> Result<Vec<Device>, String> {
+async fn discover_devices(app_handle: AppHandle, state: State<'_, AppState>) -> Result<Vec<Device>, String> {
+    scan_for_devices(&app_handle, &state).await
+}
+
+async fn scan_for_devices<R: Runtime>(app_handle: &AppHandle<R>, state: &AppState) -> Result<Vec<Device>, String> {
+    // A call made while a scan is running shares that scan's results
+    // instead of clearing its list and sweeping the network again
+    let already_running = std::mem::replace(&mut *state.discovery_in_progress.lock_or_recover(), true);
+    if already_running {
+        println!("Discovery already in progress, waiting for it to finish");
+        loop {
+            // Created before the check so a notification in between isn't missed
+            let notified = state.discovery_done.notified();
+            if !*state.discovery_in_progress.lock_or_recover() {
+                break;
+            }
+            notified.await;
+        }
+        return Ok(state.discovered_devices.lock_or_recover().clone());
+    }
+    let _guard = DiscoveryGuard(state);
+
     println!("Starting device discovery...");
     
+    // cancel_discovery bumps the generation to stop this scan early
+    let generation = *state.discovery_generation.lock_or_recover();
+    let is_cancelled = || *state.discovery_generation.lock_or_recover() != generation;
+    
     // Clear previous discoveries
     {
-        let mut discovered = state.discovered_devices.lock().unwrap();
+        let mut discovered = state.discovered_devices.lock_or_recover();
         discovered.clear();
     }
     
     // Get local device info to broadcast
     let local_device = {
-        let local = state.local_device.lock().unwrap();
+        let local = state.local_device.lock_or_recover();
         local.clone()
     };
     
     if let Some(local) = local_device {
-        // Create discovery message
+        // Create discovery message; replies echo the scan id so they can be
+        // told apart from unrelated announcements
+        let scan_id = generate_id().to_string();
         let discovery_message = NetworkMessage {
             msg_type: MessageType::Discovery,
             device_id: local.id,
             device_name: local.name.clone(),
-            data: None,
+            data: Some(scan_id.clone()),
+            display_name: local.display_name.clone(),
         };
         
         // Broadcast discovery message to the network
-        if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
+        if let Ok(socket) = bind_udp_socket(network_interface(state).as_deref()).await {
             let message_json = serde_json::to_string(&discovery_message).map_err(|e| e.to_string())?;
             
             // Get the local port this socket is bound to
             let local_port = socket.local_addr().map_err(|e| e.to_string())?.port();
             println!("Discovery socket listening on port {}", local_port);
             
-            // Broadcast to local network
-            let local_ip = get_local_ip();
-            let ip_parts: Vec<&str> = local_ip.split('.').collect();
+            let (timeout_ms, retries) = {
+                let settings = state.settings.lock_or_recover();
+                (settings.discovery_timeout_ms, settings.discovery_retries)
+            };
+            
+            // Broadcast to every attached network; the OS routes each subnet
+            // through its own interface
+            let local_ips = state.local_ips.lock_or_recover().clone();
+            let mut probe_sent_at = HashMap::new();
+            let mut seen_ids = HashSet::new();
+            let mut scan_stats = DiscoveryScanStats::default();
             
-            if ip_parts.len() == 4 {
-                let network_base = format!("{}.{}.{}", ip_parts[0], ip_parts[1], ip_parts[2]);
-                
-                // Try broadcasting to common IP ranges
-                for i in 1..255 {
-                    let target_ip = format!("{}.{}", network_base, i);
-                    if target_ip != local_ip {  // Don't send to ourselves
-                        let target_addr = format!("{}:51847", target_ip);
-                        let _ = socket.send_to(message_json.as_bytes(), &target_addr).await;
+            // Each pass repeats the sweep and listen window; responders accumulate
+            // in the discovered list, so late replies from earlier passes still count
+            let mut buf = [0; 1024];
+            for pass in 1..=retries {
+                if is_cancelled() {
+                    break;
+                }
+                if retries > 1 {
+                    println!("Discovery pass {} of {}", pass, retries);
+                }
+                for network_base in get_local_network_bases(network_interface(state).as_deref()) {
+                    // Try broadcasting to common IP ranges
+                    for i in 1..255 {
+                        let target_ip = format!("{}.{}", network_base, i);
+                        if !local_ips.contains(&target_ip) {  // Don't send to ourselves
+                            let target_addr = format!("{}:51847", target_ip);
+                            // Most swept addresses have nobody listening; failures only show in the stats
+                            let _ = send_datagram(&state.network_stats, &socket, &discovery_message.msg_type, &message_json, &target_addr, None).await;
+                            probe_sent_at.insert(target_ip, tokio::time::Instant::now());
+                        }
                     }
+                    
+                    println!("Discovery broadcast sent to network {}.x", network_base);
                 }
                 
-                println!("Discovery broadcast sent to network {}.x", network_base);
-            }
-            
-            // Listen for responses on this socket
-            let mut buf = [0; 1024];
-            let start_time = tokio::time::Instant::now();
-            let timeout = tokio::time::Duration::from_millis(3000); // 3 second timeout
-            
-            while tokio::time::Instant::now().duration_since(start_time) < timeout {
-                // Set a shorter timeout for each receive attempt
-                let receive_timeout = tokio::time::timeout(
-                    tokio::time::Duration::from_millis(100), 
-                    socket.recv_from(&mut buf)
-                ).await;
+                // Listen for responses on this socket
+                let start_time = tokio::time::Instant::now();
+                let timeout = tokio::time::Duration::from_millis(timeout_ms);
                 
-                if let Ok(Ok((len, addr))) = receive_timeout {
-                    let message_str = String::from_utf8_lossy(&buf[..len]);
-                    println!("Discovery response from {}: {}", addr, message_str);
+                while tokio::time::Instant::now().duration_since(start_time) < timeout {
+                    if is_cancelled() {
+                        println!("Discovery scan cancelled");
+                        break;
+                    }
+                    
+                    // Set a shorter timeout for each receive attempt
+                    let receive_timeout = tokio::time::timeout(
+                        tokio::time::Duration::from_millis(100), 
+                        socket.recv_from(&mut buf)
+                    ).await;
                     
-                    // Try to parse as NetworkMessage
-                    if let Ok(network_msg) = serde_json::from_str::<NetworkMessage>(&message_str) {
-                        if matches!(network_msg.msg_type, MessageType::Discovery) && network_msg.device_id != local.id {
-                            let sender_ip = addr.ip().to_string();
-                            let discovered_device = Device {
-                                id: network_msg.device_id,
-                                name: network_msg.device_name.clone(),
-                                icon: "laptop".to_string(),
-                                ip: sender_ip.clone(),
-                                status: DeviceStatus::Offline,
-                                sync_mode: SyncMode::Disabled,
-                                last_seen: get_current_timestamp(),
-                            };
-                            
-                            // Add to discovered devices
-                            {
-                                let mut discovered = state.discovered_devices.lock().unwrap();
-                                if !discovered.iter().any(|d| d.id == network_msg.device_id) {
-                                    discovered.push(discovered_device);
-                                    println!("Added discovered device: {} at {}", network_msg.device_name, sender_ip);
+                    if let Ok(Ok((len, addr))) = receive_timeout {
+                        // Our own broadcasts and their reflections, on any of our interfaces
+                        if local_ips.contains(&addr.ip().to_string()) {
+                            continue;
+                        }
+                        let message_str = String::from_utf8_lossy(&buf[..len]);
+                        
+                        // Try to parse as NetworkMessage
+                        if let Ok(network_msg) = serde_json::from_str::<NetworkMessage>(&message_str) {
+                            state.network_stats.record_received(&network_msg.msg_type, &addr.ip().to_string(), len);
+                            let is_reply = matches!(network_msg.msg_type, MessageType::DiscoveryResponse)
+                                && network_msg.data.as_deref() == Some(scan_id.as_str());
+                            if !is_reply {
+                                continue;
+                            }
+                            // Each probe pass (and some peers) can produce several replies per device
+                            if !seen_ids.insert(network_msg.device_id) {
+                                scan_stats.duplicate_responses += 1;
+                                continue;
+                            }
+                            scan_stats.unique_responses += 1;
+                            match probe_sent_at.get(&addr.ip().to_string()) {
+                                Some(sent_at) => println!("Discovery response from {} after {}ms", addr, sent_at.elapsed().as_millis()),
+                                None => println!("Discovery response from {}", addr),
+                            }
+
+                            let is_paired = state.devices.lock_or_recover().contains_key(&network_msg.device_id);
+                            if is_paired {
+                                update_peer_display_name(state, network_msg.device_id, &addr.ip().to_string(), &network_msg.display_name);
+                            }
+                            if network_msg.device_id != local.id && !is_paired {
+                                let sender_ip = addr.ip().to_string();
+                                let discovered_device = Device {
+                                    id: network_msg.device_id,
+                                    name: network_msg.device_name.clone(),
+                                    icon: "laptop".to_string(),
+                                    ip: sender_ip.clone(),
+                                    status: DeviceStatus::Offline,
+                                    sync_mode: SyncMode::Disabled,
+                                    last_seen: get_current_timestamp(),
+                                    trusted: false,
+                                    last_synced: None,
+                                    display_name: network_msg.display_name.clone(),
+                                    disabled: false,
+                                    relay_key: None,
+                                };
+                                
+                                // Add to discovered devices, letting the UI show it before the scan ends
+                                let added = {
+                                    let mut discovered = state.discovered_devices.lock_or_recover();
+                                    let added = !discovered.iter().any(|d| d.id == network_msg.device_id);
+                                    if added {
+                                        discovered.push(discovered_device.clone());
+                                        println!("Added discovered device: {} at {}", network_msg.device_name, sender_ip);
+                                    }
+                                    added
+                                };
+                                if added {
+                                    let _ = app_handle.emit("device-discovered", &discovered_device);
                                 }
                             }
                         }
@@ -1676,8 +7475,11 @@ async fn discover_devices(state: State<'_, AppState>) -> Result<Vec<Device>, Str
                 }
             }
             
+            println!("Discovery replies: {} unique, {} duplicate", scan_stats.unique_responses, scan_stats.duplicate_responses);
+            state.network_stats.counters.lock_or_recover().last_discovery_scan = Some(scan_stats);
+            
             // Return discovered devices
-            let discovered = state.discovered_devices.lock().unwrap();
+            let discovered = state.discovered_devices.lock_or_recover();
             let result = discovered.clone();
             println!("Discovery scan completed. Found {} devices.", result.len());
             Ok(result)
@@ -1689,41 +7491,79 @@ async fn discover_devices(state: State<'_, AppState>) -> Result<Vec<Device>, Str
     }
 }
 
+// Stops a running discover_devices scan; it returns the devices found so far
+#[tauri::command]
+fn cancel_discovery(state: State<AppState>) {
+    *state.discovery_generation.lock_or_recover() += 1;
+}
+
 #[tauri::command]
 async fn update_device_name(state: State<'_, AppState>, new_name: String) -> Result<(), String> {
     // Update local device name
-    let mut local_device = state.local_device.lock().unwrap();
-    if let Some(ref mut device) = *local_device {
-        device.name = new_name.clone();
-        
-        // Also update in the devices map
-        let mut devices = state.devices.lock().unwrap();
-        if let Some(device_in_map) = devices.get_mut(&device.id) {
-            device_in_map.name = new_name;
+    let updated_device = {
+        let mut local_device = state.local_device.lock_or_recover();
+        if let Some(ref mut device) = *local_device {
+            device.name = new_name;
         }
+        local_device.clone()
+    };
+    
+    // Keep the saved identity in step so the name survives restarts
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let (Some(db_path), Some(device)) = (db_path, updated_device) {
+        save_local_device_to_db(&db_path, &device);
     }
     
     Ok(())
 }
 
+// Sets the friendly name peers show for this device; an empty name clears it.
+// The generated `name` and the id stay as they are, so pairings are unaffected.
+#[tauri::command]
+async fn set_display_name(state: State<'_, AppState>, name: String) -> Result<(), String> {
+    let display_name = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+    let updated_device = {
+        let mut local_device = state.local_device.lock_or_recover();
+        let device = local_device.as_mut().ok_or("Local device not initialized".to_string())?;
+        device.display_name = display_name;
+        device.clone()
+    };
+
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        save_local_device_to_db(&db_path, &updated_device);
+    }
+    println!("Display name set to {:?}", updated_device.display_name);
+    Ok(())
+}
+
+// The name to show for this device: the display name if set, else the generated name
+#[tauri::command]
+fn get_display_name(state: State<AppState>) -> Result<String, String> {
+    state.local_device.lock_or_recover()
+        .as_ref()
+        .map(|device| device.display_name.clone().unwrap_or_else(|| device.name.clone()))
+        .ok_or("Local device not initialized".to_string())
+}
+
 #[tauri::command]
 async fn send_connection_request_to_device(state: State<'_, AppState>, target_device: Device) -> Result<(), String> {
-    let local_device = state.local_device.lock().unwrap().clone();
+    ensure_writable(&state)?;
+    let local_device = state.local_device.lock_or_recover().clone();
     if let Some(device) = local_device {
         let message = NetworkMessage {
             msg_type: MessageType::ConnectionRequest,
             device_id: device.id,
             device_name: device.name,
             data: None,
+            display_name: device.display_name,
         };
         
         // Send UDP message to target device
-        if let Ok(socket) = UdpSocket::bind("0.0.0.0:0").await {
+        if let Ok(socket) = bind_udp_socket(network_interface(&state).as_deref()).await {
             let message_json = serde_json::to_string(&message).map_err(|e| e.to_string())?;
             let target_addr = format!("{}:51847", target_device.ip);
-            if let Err(e) = socket.send_to(message_json.as_bytes(), &target_addr).await {
-                return Err(format!("Failed to send connection request: {}", e));
-            }
+            send_datagram(&state.network_stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&target_device.ip)).await?;
             println!("Connection request sent to {} at {}", target_device.name, target_addr);
             Ok(())
         } else {
@@ -1735,11 +7575,17 @@ async fn send_connection_request_to_device(state: State<'_, AppState>, target_de
 }
 
 #[tauri::command]
-async fn add_file_to_clipboard(state: State<'_, AppState>, file_path: String) -> Result<(), String> {
+async fn add_file_to_clipboard(app_handle: AppHandle, state: State<'_, AppState>, file_path: String) -> Result<(), String> {
+    ensure_writable(&state)?;
+    add_file_item(&app_handle, &state, &file_path).await.map(|_| ())
+}
+
+// Stores a local file as a file item and sends it to connected devices
+async fn add_file_item(app_handle: &AppHandle, state: &AppState, file_path: &str) -> Result<ClipboardItem, String> {
     use std::fs;
     use std::path::Path;
     
-    let path = Path::new(&file_path);
+    let path = Path::new(file_path);
     if !path.exists() {
         return Err("File does not exist".to_string());
     }
@@ -1751,8 +7597,7 @@ async fn add_file_to_clipboard(state: State<'_, AppState>, file_path: String) ->
         .to_string();
     
     // Check file size limit (10MB)
-    const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024; // 10MB
-    if metadata.len() > MAX_FILE_SIZE {
+    if metadata.len() > MAX_FILE_ITEM_SIZE {
         return Err(format!("File '{}' is too large ({}MB). Maximum size is 10MB.", 
                           file_name, metadata.len() / 1024 / 1024));
     }
@@ -1761,9 +7606,24 @@ async fn add_file_to_clipboard(state: State<'_, AppState>, file_path: String) ->
     
     // Read the full file content into memory
     println!("Reading file content: {} ({} bytes)", file_name, metadata.len());
-    let file_content = fs::read(&file_path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let file_content = fs::read(file_path).map_err(|e| format!("Failed to read file: {}", e))?;
     println!("Successfully read {} bytes from file", file_content.len());
-    
+
+    let mime_type = detect_mime_type(&file_name, &file_content);
+    add_file_content(app_handle, state, file_name, file_content, mime_type).await
+}
+
+// Stores already-loaded file bytes as a file item, saves it to the database
+// and syncs it to connected devices. Shared by local files and URL imports.
+async fn add_file_content(
+    app_handle: &AppHandle,
+    state: &AppState,
+    file_name: String,
+    file_content: Vec<u8>,
+    mime_type: Option<String>,
+) -> Result<ClipboardItem, String> {
+    let file_size = file_content.len() as u64;
+
     // Create a unique file ID and store the file in our files directory
     let file_id = generate_id().to_string();
     let stored_file_path = store_file_content(&file_content, &file_name, &file_id)?;
@@ -1771,30 +7631,322 @@ async fn add_file_to_clipboard(state: State<'_, AppState>, file_path: String) ->
     
     let item = ClipboardItem {
         id: file_id.clone(),
-        content: format!("File: {} ({} bytes)", file_name, file_content.len()),
+        content: format!("File: {} ({} bytes)", file_name, file_size),
         timestamp: get_current_timestamp().to_string(),
-        device: whoami::fallible::hostname().unwrap_or("Unknown".to_string()),
+        device: local_item_attribution(state),
         content_type: "file".to_string(),
         file_path: Some(stored_file_path), // Now points to our stored copy
-        file_size: Some(metadata.len()),
+        file_size: Some(file_size),
         file_name: Some(file_name),
+        use_count: 0,
+        content_size: file_size,
+        mime_type: mime_type.clone(),
+        metadata: HashMap::new(),
+        expires_at: None,
+        no_sync: false,
     };
     
     // Files are not added to in-memory history - they're only stored in database
     // and retrieved via files-specific queries
     
     // Save to database
-    let db_path = state.db_path.lock().unwrap().clone();
+    let db_path = state.db_path.lock_or_recover().clone();
     if let Some(db_path) = db_path {
         save_clipboard_item_to_db(&db_path, &item)?;
     }
     
+    // Optionally add the contents of small text files to the text history as well
+    let inline_text_files = state.settings.lock_or_recover().inline_text_files;
+    if inline_text_files
+        && file_size <= MAX_INLINE_TEXT_FILE_SIZE
+        && mime_type.as_deref().is_some_and(is_text_mime_type)
+    {
+        match String::from_utf8(file_content.clone()) {
+            Ok(text) if !text.trim().is_empty() => {
+                let content_size = text.len() as u64;
+                let text_item = ClipboardItem {
+                    id: generate_id().to_string(),
+                    content: text,
+                    timestamp: get_current_timestamp().to_string(),
+                    device: item.device.clone(),
+                    content_type: "text".to_string(),
+                    file_path: None,
+                    file_size: None,
+                    file_name: None,
+                    use_count: 0,
+                    content_size,
+                    mime_type: None,
+                    metadata: HashMap::new(),
+                    expires_at: None,
+                    no_sync: false,
+                };
+
+                let dedup_window = state.settings.lock_or_recover().dedup_window;
+                {
+                    let mut history = state.clipboard_history.lock_or_recover();
+                    dedup_history(&mut history, &text_item.content, dedup_window);
+                    history.insert(0, text_item.clone());
+                    history.truncate(50);
+                }
+
+                let db_path = state.db_path.lock_or_recover().clone();
+                if let Some(db_path) = db_path {
+                    save_clipboard_item_to_db(&db_path, &text_item)?;
+                }
+
+                let _ = app_handle.emit("clipboard-updated", &text_item);
+                println!("Added text contents of {} to history", item.file_name.as_deref().unwrap_or("file"));
+            },
+            Ok(_) => {},
+            Err(_) => println!("File is not valid UTF-8 - skipping inline text copy"),
+        }
+    }
+
     // Sync to connected devices with full file content
-    sync_file_to_connected_devices(&state.devices, &state.local_device, &item, &file_content).await;
+    sync_file_to_connected_devices(app_handle, &state.devices, &state.local_device, &item, &file_content).await;
     
+    Ok(item)
+}
+
+#[tauri::command]
+async fn add_url_to_clipboard(app_handle: AppHandle, state: State<'_, AppState>, url: String) -> Result<ClipboardItem, String> {
+    ensure_writable(&state)?;
+    let parsed = reqwest::Url::parse(url.trim()).map_err(|e| format!("Invalid URL: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("Unsupported URL scheme '{}'. Only http and https are allowed.", parsed.scheme()));
+    }
+
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(URL_DOWNLOAD_TIMEOUT_SECS))
+        .build()
+        .map_err(|e| e.to_string())?;
+    let mut response = client
+        .get(parsed.clone())
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to download {}: {}", parsed, e))?;
+
+    // Reject early when the server announces an oversized body, and again
+    // while streaming since Content-Length can be missing or wrong
+    if response.content_length().is_some_and(|len| len > MAX_URL_DOWNLOAD_SIZE) {
+        return Err(format!("Download is too large. Maximum size is {}MB.", MAX_URL_DOWNLOAD_SIZE / 1024 / 1024));
+    }
+
+    let header_mime_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(|v| v.trim().to_lowercase())
+        .filter(|v| !v.is_empty() && v != "application/octet-stream");
+    let file_name = url_download_file_name(&response, &parsed, header_mime_type.as_deref());
+
+    let mut file_content = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| format!("Failed to download {}: {}", parsed, e))? {
+        if (file_content.len() + chunk.len()) as u64 > MAX_URL_DOWNLOAD_SIZE {
+            return Err(format!("Download is too large. Maximum size is {}MB.", MAX_URL_DOWNLOAD_SIZE / 1024 / 1024));
+        }
+        file_content.extend_from_slice(&chunk);
+    }
+    println!("Downloaded {} bytes from {}", file_content.len(), parsed);
+
+    let mime_type = header_mime_type.or_else(|| detect_mime_type(&file_name, &file_content));
+    add_file_content(&app_handle, &state, file_name, file_content, mime_type).await
+}
+
+// Zips a directory and adds the archive as a single file item, synced like
+// any other file
+#[tauri::command]
+async fn add_directory_to_clipboard(app_handle: AppHandle, state: State<'_, AppState>, dir_path: String) -> Result<ClipboardItem, String> {
+    ensure_writable(&state)?;
+    let dir = std::path::PathBuf::from(&dir_path);
+    if !dir.is_dir() {
+        return Err("Directory does not exist".to_string());
+    }
+    let dir_name = dir.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("folder")
+        .to_string();
+
+    let archive = tauri::async_runtime::spawn_blocking(move || zip_directory(&dir))
+        .await
+        .map_err(|e| e.to_string())??;
+    println!("Zipped {} into {} bytes", dir_path, archive.len());
+
+    let file_name = format!("{}.zip", dir_name);
+    add_file_content(&app_handle, &state, file_name, archive, Some("application/zip".to_string())).await
+}
+
+// Builds a zip archive of `dir` in memory. Empty subdirectories are kept as
+// directory entries; symlinks are skipped so the archive can't reach outside
+// the directory or loop.
+fn zip_directory(dir: &std::path::Path) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut pending = vec![dir.to_path_buf()];
+    let mut content_size = 0u64;
+    let mut entries = 0usize;
+
+    while let Some(current) = pending.pop() {
+        let mut children: Vec<_> = std::fs::read_dir(&current)
+            .map_err(|e| format!("Failed to read {}: {}", current.display(), e))?
+            .filter_map(|entry| entry.ok())
+            .collect();
+        children.sort_by_key(|entry| entry.file_name());
+
+        for entry in children {
+            let path = entry.path();
+            let metadata = std::fs::symlink_metadata(&path).map_err(|e| e.to_string())?;
+            let relative = path.strip_prefix(dir).map_err(|e| e.to_string())?;
+            let name = relative.components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+
+            if metadata.file_type().is_symlink() {
+                println!("Skipping symlink {}", path.display());
+            } else if metadata.is_dir() {
+                writer.add_directory(name, options).map_err(|e| e.to_string())?;
+                pending.push(path);
+                entries += 1;
+            } else if metadata.is_file() {
+                content_size += metadata.len();
+                if content_size > MAX_DIRECTORY_CONTENT_SIZE {
+                    return Err(format!("Directory is too large to zip. Maximum is {}MB of files.", MAX_DIRECTORY_CONTENT_SIZE / 1024 / 1024));
+                }
+                let content = std::fs::read(&path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                writer.start_file(name, options).map_err(|e| e.to_string())?;
+                writer.write_all(&content).map_err(|e| e.to_string())?;
+                entries += 1;
+            }
+        }
+    }
+
+    if entries == 0 {
+        return Err("Directory is empty".to_string());
+    }
+
+    let archive = writer.finish().map_err(|e| e.to_string())?.into_inner();
+    if archive.len() as u64 > MAX_FILE_ITEM_SIZE {
+        return Err(format!("Zipped directory is too large. Maximum size is {}MB.", MAX_FILE_ITEM_SIZE / 1024 / 1024));
+    }
+    Ok(archive)
+}
+
+// Unpacks a zip file item into `dest`, creating it if needed. Entries that
+// would land outside `dest` and symlinks are skipped.
+#[tauri::command]
+async fn extract_file_item(state: State<'_, AppState>, id: String, dest: String) -> Result<String, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let item = load_clipboard_item_from_db(&db_path, &id)?;
+    let stored_path = item.file_path.filter(|path| !path.is_empty())
+        .ok_or("Item has no stored file".to_string())?;
+
+    let dest = std::path::PathBuf::from(dest.trim());
+    if !dest.is_absolute() {
+        return Err("Destination must be an absolute path".to_string());
+    }
+
+    let extracted = dest.clone();
+    tauri::async_runtime::spawn_blocking(move || unzip_file(std::path::Path::new(&stored_path), &extracted))
+        .await
+        .map_err(|e| e.to_string())??;
+
+    println!("Extracted {} into {}", item.file_name.as_deref().unwrap_or(&id), dest.display());
+    Ok(dest.to_string_lossy().to_string())
+}
+
+fn unzip_file(archive_path: &std::path::Path, dest: &std::path::Path) -> Result<(), String> {
+    use std::io::Read;
+
+    let file = std::fs::File::open(archive_path).map_err(|e| format!("Failed to open archive: {}", e))?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|_| "File is not a zip archive".to_string())?;
+    std::fs::create_dir_all(dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+
+    let mut remaining = MAX_DIRECTORY_CONTENT_SIZE;
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(|e| e.to_string())?;
+        let Some(relative) = entry.enclosed_name() else {
+            println!("Skipping unsafe archive entry {}", entry.name());
+            continue;
+        };
+        if entry.is_symlink() {
+            println!("Skipping symlink {}", entry.name());
+            continue;
+        }
+
+        let target = dest.join(relative);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&target).map_err(|e| e.to_string())?;
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+
+        // Count what is actually written, not the sizes the archive claims
+        let mut output = std::fs::File::create(&target)
+            .map_err(|e| format!("Failed to create {}: {}", target.display(), e))?;
+        let written = std::io::copy(&mut (&mut entry).take(remaining + 1), &mut output)
+            .map_err(|e| format!("Failed to extract {}: {}", target.display(), e))?;
+        if written > remaining {
+            return Err(format!("Archive is too large to extract. Maximum is {}MB.", MAX_DIRECTORY_CONTENT_SIZE / 1024 / 1024));
+        }
+        remaining -= written;
+    }
     Ok(())
 }
 
+// Picks a name for a downloaded file: Content-Disposition first, then the
+// last URL path segment, adding an extension from the content type if needed
+fn url_download_file_name(response: &reqwest::Response, url: &reqwest::Url, mime_type: Option<&str>) -> String {
+    let from_header = response
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(';')
+                .map(str::trim)
+                .find_map(|part| part.strip_prefix("filename="))
+                .map(|name| name.trim_matches('"').to_string())
+        });
+    let from_path = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.to_string());
+
+    let name = from_header
+        .or(from_path)
+        .and_then(|name| sanitize_file_name(&name).ok())
+        .unwrap_or_else(|| "download".to_string());
+
+    let has_extension = std::path::Path::new(&name).extension().is_some();
+    let extension = mime_type.and_then(|mime| {
+        IMAGE_FILE_EXTENSIONS
+            .iter()
+            .find(|(_, m)| *m == mime)
+            .map(|(ext, _)| *ext)
+            .or(match mime {
+                "text/plain" => Some("txt"),
+                "text/html" => Some("html"),
+                "application/json" => Some("json"),
+                "application/pdf" => Some("pdf"),
+                _ => None,
+            })
+    });
+    match extension {
+        Some(ext) if !has_extension => format!("{}.{}", name, ext),
+        _ => name,
+    }
+}
+
 #[tauri::command]
 async fn get_file_content(file_path: String) -> Result<Vec<u8>, String> {
     use std::fs;
@@ -1802,18 +7954,50 @@ async fn get_file_content(file_path: String) -> Result<Vec<u8>, String> {
     fs::read(&file_path).map_err(|e| format!("Failed to read file: {}", e))
 }
 
+// Where save_received_file puts files: the Downloads folder, or a "received"
+// folder in the app's files directory on systems without one (headless, containers)
+fn received_files_directory() -> Result<std::path::PathBuf, String> {
+    if let Some(downloads_dir) = dirs::download_dir() {
+        return Ok(downloads_dir);
+    }
+    let fallback = files_directory()?.join("received");
+    println!("No Downloads folder on this system - saving received files to {}", fallback.display());
+    Ok(fallback)
+}
+
 #[tauri::command]
 async fn save_received_file(content: Vec<u8>, file_name: String) -> Result<String, String> {
     use std::fs;
     
-    // Save to Downloads folder
-    let downloads_dir = dirs::download_dir()
-        .ok_or("Could not find downloads directory".to_string())?;
+    let downloads_dir = received_files_directory()?;
+    fs::create_dir_all(&downloads_dir)
+        .map_err(|e| format!("Failed to create {}: {}", downloads_dir.display(), e))?;
+    
+    let final_path = received_file_path(&downloads_dir, &file_name)?;
     
-    let file_path = downloads_dir.join(&file_name);
+    fs::write(&final_path, content)
+        .map_err(|e| format!("Failed to save file: {}", e))?;
     
+    Ok(final_path.to_string_lossy().to_string())
+}
+
+// Where a received file named `file_name` is saved: always directly inside
+// `dir`, whatever path components the sender put in the name
+fn received_file_path(dir: &std::path::Path, file_name: &str) -> Result<std::path::PathBuf, String> {
+    let file_name = sanitize_file_name(file_name)?;
+    let file_path = dir.join(&file_name);
+    if file_path.parent() != Some(dir) {
+        return Err(format!("File name escapes the downloads directory: {}", file_name));
+    }
+
     // Handle file name conflicts
-    let mut final_path = file_path.clone();
+    Ok(unique_file_path(dir, &file_path))
+}
+
+// Appends " (1)", " (2)", ... to the file name until it no longer clashes
+// with an existing file in `dir`
+fn unique_file_path(dir: &std::path::Path, file_path: &std::path::Path) -> std::path::PathBuf {
+    let mut final_path = file_path.to_path_buf();
     let mut counter = 1;
     while final_path.exists() {
         let stem = file_path.file_stem()
@@ -1829,16 +8013,222 @@ async fn save_received_file(content: Vec<u8>, file_name: String) -> Result<Strin
             format!("{} ({}).{}", stem, counter, extension)
         };
         
-        final_path = downloads_dir.join(new_name);
+        final_path = dir.join(new_name);
         counter += 1;
     }
-    
-    fs::write(&final_path, content)
-        .map_err(|e| format!("Failed to save file: {}", e))?;
-    
+    final_path
+}
+
+// Copies a stored file item out of managed storage into a folder the user
+// picked, keeping its original name. Returns where it was written.
+#[tauri::command]
+async fn export_file_item(state: State<'_, AppState>, id: String, dest_dir: String) -> Result<String, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let item = load_clipboard_item_from_db(&db_path, &id)?;
+
+    let stored_path = item.file_path.as_deref()
+        .filter(|path| !path.is_empty())
+        .ok_or("Item has no stored file".to_string())?;
+    if !std::path::Path::new(stored_path).is_file() {
+        return Err("Stored file is missing".to_string());
+    }
+
+    let dest_dir = std::path::PathBuf::from(dest_dir);
+    if !dest_dir.is_dir() {
+        return Err(format!("Destination is not a folder: {}", dest_dir.display()));
+    }
+
+    let file_name = sanitize_file_name(item.file_name.as_deref().unwrap_or("file"))?;
+    let final_path = unique_file_path(&dest_dir, &dest_dir.join(&file_name));
+
+    std::fs::copy(stored_path, &final_path)
+        .map_err(|e| format!("Failed to export file: {}", e))?;
+
+    println!("Exported {} to {}", file_name, final_path.display());
     Ok(final_path.to_string_lossy().to_string())
 }
 
+// Longest text get_item_summary returns before cutting it short, in characters
+const ITEM_SUMMARY_MAX_CHARS: usize = 80;
+
+// The leading `max_chars` characters of `text`, cut on a character boundary,
+// and whether anything was cut off
+fn truncate_chars(text: &str, max_chars: usize) -> (&str, bool) {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => (&text[..end], true),
+        None => (text, false),
+    }
+}
+
+// Sizes as people read them: "512 B", "1.5 KB", "2.0 MB"
+fn format_byte_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+// One line describing an item: the first line of text, "name (size)" for files,
+// or "image WxH" for images
+fn item_summary(item: &ClipboardItem) -> String {
+    match item.content_type.as_str() {
+        "file" => format!(
+            "{} ({})",
+            item.file_name.as_deref().unwrap_or("file"),
+            format_byte_size(item.file_size.unwrap_or(item.content_size))
+        ),
+        "image" => {
+            // Only the header is read to get the dimensions
+            let dimensions = item.file_path.as_deref().and_then(|path| image::image_dimensions(path).ok());
+            match dimensions {
+                Some((width, height)) => format!("image {}x{}", width, height),
+                None => format!("image ({})", format_byte_size(item.file_size.unwrap_or(item.content_size))),
+            }
+        },
+        _ => {
+            let mut lines = item.content.trim().lines();
+            let first_line = lines.next().unwrap_or("");
+            let (summary, cut) = truncate_chars(first_line, ITEM_SUMMARY_MAX_CHARS);
+            if cut || lines.next().is_some() {
+                format!("{}…", summary.trim_end())
+            } else {
+                summary.to_string()
+            }
+        },
+    }
+}
+
+#[tauri::command]
+async fn get_item_summary(state: State<'_, AppState>, id: String) -> Result<String, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    Ok(item_summary(&load_clipboard_item_from_db(&db_path, &id)?))
+}
+
+// Lines of unchanged context kept around each change in diff_items output
+const DIFF_CONTEXT_LINES: usize = 3;
+
+// A unified line diff from item A to item B, for comparing two copied versions
+// of the same text. Empty when their content is identical.
+#[tauri::command]
+async fn diff_items(state: State<'_, AppState>, id_a: String, id_b: String) -> Result<String, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let item_a = load_clipboard_item_from_db(&db_path, &id_a)?;
+    let item_b = load_clipboard_item_from_db(&db_path, &id_b)?;
+
+    for item in [&item_a, &item_b] {
+        if item.content_type != "text" {
+            return Err(format!("Only text items can be compared, not {} items", item.content_type));
+        }
+    }
+
+    let diff = similar::TextDiff::from_lines(&item_a.content, &item_b.content);
+    Ok(diff.unified_diff()
+        .context_radius(DIFF_CONTEXT_LINES)
+        .header(&id_a, &id_b)
+        .to_string())
+}
+
+// What an item hands to the share sheet: stored files and images as the file
+// itself, everything else as its text
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+enum ShareContent {
+    Text(String),
+    File(String),
+}
+
+#[tauri::command]
+async fn share_item(app_handle: AppHandle, state: State<'_, AppState>, id: String) -> Result<(), String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let item = load_clipboard_item_from_db(&db_path, &id)?;
+
+    let content = match item.content_type.as_str() {
+        "file" | "image" => {
+            let stored_path = item.file_path
+                .filter(|path| !path.is_empty())
+                .ok_or("Item has no stored file".to_string())?;
+            if !std::path::Path::new(&stored_path).is_file() {
+                return Err("Stored file is missing".to_string());
+            }
+            ShareContent::File(stored_path)
+        },
+        _ => ShareContent::Text(item.content),
+    };
+
+    show_share_sheet(&app_handle, content)
+}
+
+// NSSharingServicePicker anchored to the main window. AppKit objects may only
+// be touched on the main thread, so the picker is built there.
+#[cfg(target_os = "macos")]
+fn show_share_sheet(app_handle: &AppHandle, content: ShareContent) -> Result<(), String> {
+    use objc2::rc::Retained;
+    use objc2::runtime::AnyObject;
+    use objc2::MainThreadMarker;
+    use objc2_app_kit::{NSSharingServicePicker, NSView};
+    use objc2_foundation::{NSArray, NSRectEdge, NSString, NSURL};
+
+    let window = app_handle.get_webview_window("cliped").ok_or("Main window not found".to_string())?;
+    // Raw pointers aren't Send; the view outlives this call as the window is still open
+    let ns_view = window.ns_view().map_err(|e| e.to_string())? as usize;
+
+    app_handle.run_on_main_thread(move || {
+        let Some(mtm) = MainThreadMarker::new() else {
+            return;
+        };
+        let object: Retained<AnyObject> = match content {
+            ShareContent::File(path) => Retained::into_super(Retained::into_super(NSURL::fileURLWithPath(&NSString::from_str(&path)))),
+            ShareContent::Text(text) => Retained::into_super(Retained::into_super(NSString::from_str(&text))),
+        };
+        let items = NSArray::from_retained_slice(&[object]);
+
+        // SAFETY: ns_view is the window's content view, which AppKit keeps alive
+        // while the window exists, and we're on the main thread
+        unsafe {
+            let view = &*(ns_view as *const NSView);
+            let picker = NSSharingServicePicker::initWithItems(mtm.alloc(), &items);
+            picker.showRelativeToRect_ofView_preferredEdge(view.bounds(), view, NSRectEdge::MinY);
+        }
+    }).map_err(|e| e.to_string())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn show_share_sheet(_app_handle: &AppHandle, _content: ShareContent) -> Result<(), String> {
+    Err("Sharing is not supported on this platform".to_string())
+}
+
+// An item's payload by id: the stored file for file and image items, the UTF-8
+// text of `content` for everything else
+#[tauri::command]
+async fn get_item_bytes(state: State<'_, AppState>, id: String) -> Result<Vec<u8>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let item = load_clipboard_item_from_db(&db_path, &id)?;
+
+    match item.content_type.as_str() {
+        "file" | "image" => {
+            let stored_path = item.file_path.as_deref()
+                .filter(|path| !path.is_empty())
+                .ok_or("Item has no stored file".to_string())?;
+            std::fs::read(stored_path).map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => format!("Stored file is missing: {}", stored_path),
+                _ => format!("Failed to read stored file: {}", e),
+            })
+        },
+        _ => Ok(item.content.into_bytes()),
+    }
+}
+
 #[tauri::command]
 async fn save_file_to_path(content: Vec<u8>, file_path: String) -> Result<String, String> {
     use std::fs;
@@ -1893,14 +8283,201 @@ async fn show_save_dialog(suggested_name: String) -> Result<Option<String>, Stri
         println!("Save location selected: {}", path);
         return Ok(Some(path));
     }
-    
-    println!("Save dialog cancelled");
-    Ok(None)
+    
+    println!("Save dialog cancelled");
+    Ok(None)
+}
+
+// Enough leading bytes for every signature infer knows about
+const PREVIEW_SNIFF_BYTES: u64 = 8192;
+
+// Allowed ranges for the image preview settings
+const THUMBNAIL_DIMENSION_RANGE: std::ops::RangeInclusive<u32> = 32..=2048;
+const THUMBNAIL_QUALITY_RANGE: std::ops::RangeInclusive<u8> = 10..=100;
+
+fn thumbnails_directory() -> Result<std::path::PathBuf, String> {
+    Ok(files_directory()?.join("thumbnails"))
+}
+
+// Cached previews are named after the settings that produced them, so changing
+// a setting only needs the old ones deleted; new ones are made on demand
+fn remove_thumbnails() -> Result<(), String> {
+    match std::fs::remove_dir_all(thumbnails_directory()?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(format!("Failed to clear thumbnails: {}", e)),
+        _ => Ok(()),
+    }
+}
+
+// Drops every cached preview of one item, whatever settings made it
+fn remove_item_thumbnails(id: &str) {
+    let Ok(entries) = thumbnails_directory().and_then(|dir| std::fs::read_dir(dir).map_err(|e| e.to_string())) else {
+        return;
+    };
+    let prefix = format!("{}-", id);
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+// Deletes cached previews of items that no longer exist or that were made with
+// other thumbnail settings. Returns how many were deleted and their total size.
+fn trim_thumbnails(db_path: &str, max_dimension: u32, quality: u8) -> Result<(usize, u64), String> {
+    let Ok(entries) = thumbnails_directory().and_then(|dir| std::fs::read_dir(dir).map_err(|e| e.to_string())) else {
+        return Ok((0, 0));
+    };
+
+    let conn = Connection::open(db_path).map_err(|e| e.to_string())?;
+    let mut stmt = conn.prepare("SELECT id FROM clipboard_items WHERE content_type = 'image'")
+        .map_err(|e| e.to_string())?;
+    let image_ids: HashSet<String> = stmt.query_map([], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .filter_map(|id| id.ok())
+        .collect();
+
+    // Previews are named "{id}-{dimension}-{quality}.jpg"
+    let current_suffix = format!("-{}-{}.jpg", max_dimension, quality);
+    let (mut count, mut bytes) = (0, 0);
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let keep = name.strip_suffix(&current_suffix).is_some_and(|id| image_ids.contains(id));
+        if !keep {
+            bytes += remove_file_counting_bytes(&entry.path());
+            count += 1;
+        }
+    }
+    Ok((count, bytes))
+}
+
+// A cached preview is only good while it's newer than the image it was made from
+fn is_thumbnail_fresh(thumbnail: &std::path::Path, source: &str) -> bool {
+    let modified = |path: &std::path::Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+    match (modified(thumbnail), modified(std::path::Path::new(source))) {
+        (Some(thumbnail), Some(source)) => thumbnail >= source,
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct MaintenanceSummary {
+    expired_items: usize,    // Deleted because their expires_at passed
+    pruned_items: usize,     // Deleted for being over their type's retention limit
+    orphaned_files: usize,   // Stored files no item referred to
+    stale_thumbnails: usize, // Previews of deleted items or made with old settings
+    bytes_reclaimed: u64,    // Disk space freed by the deleted files
+}
+
+// Runs every cleanup sweep now instead of waiting for the timers, and reports
+// what each one removed
+#[tauri::command]
+async fn run_maintenance(app_handle: AppHandle, state: State<'_, AppState>) -> Result<MaintenanceSummary, String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let (max_dimension, quality) = {
+        let settings = state.settings.lock_or_recover();
+        (settings.thumbnail_max_dimension, settings.thumbnail_quality)
+    };
+
+    let mut summary = MaintenanceSummary {
+        expired_items: purge_expired_items(&app_handle),
+        ..Default::default()
+    };
+
+    let (pruned_items, pruned_bytes) = enforce_retention_limits(&app_handle);
+    summary.pruned_items = pruned_items;
+    summary.bytes_reclaimed += pruned_bytes;
+
+    // After the item sweeps, so files of the items they just deleted are orphans too
+    let (orphaned_files, orphan_bytes) = remove_orphaned_files(&db_path)?;
+    summary.orphaned_files = orphaned_files;
+    summary.bytes_reclaimed += orphan_bytes;
+
+    let (stale_thumbnails, thumbnail_bytes) = trim_thumbnails(&db_path, max_dimension, quality)?;
+    summary.stale_thumbnails = stale_thumbnails;
+    summary.bytes_reclaimed += thumbnail_bytes;
+
+    println!("Maintenance finished: {:?}", summary);
+    Ok(summary)
+}
+
+#[tauri::command]
+async fn clear_thumbnail_cache() -> Result<(), String> {
+    remove_thumbnails()?;
+    println!("Cleared thumbnail cache");
+    Ok(())
+}
+
+// A downscaled JPEG of an image item as a data URL, sized and compressed per the
+// thumbnail settings. Generated on first request and cached next to the files.
+#[tauri::command]
+async fn get_image_preview(state: State<'_, AppState>, id: String) -> Result<String, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let (max_dimension, quality) = {
+        let settings = state.settings.lock_or_recover();
+        (settings.thumbnail_max_dimension, settings.thumbnail_quality)
+    };
+    let item = load_clipboard_item_from_db(&db_path, &id)?;
+    let is_image = item.content_type == "image"
+        || item.mime_type.as_deref().is_some_and(|mime| mime.starts_with("image/"));
+    if !is_image {
+        return Err("Item is not an image".to_string());
+    }
+    let source = item.file_path.filter(|path| !path.is_empty())
+        .ok_or("Image item has no stored image".to_string())?;
+
+    let cache_dir = thumbnails_directory()?;
+    let cache_path = cache_dir.join(sanitize_file_name(&format!("{}-{}-{}.jpg", id, max_dimension, quality))?);
+    let cached = is_thumbnail_fresh(&cache_path, &source)
+        .then(|| std::fs::read(&cache_path).ok())
+        .flatten();
+    let jpeg = match cached {
+        Some(cached) => cached,
+        None => {
+            let jpeg = tauri::async_runtime::spawn_blocking(move || render_thumbnail(&source, max_dimension, quality))
+                .await
+                .map_err(|e| e.to_string())??;
+            std::fs::create_dir_all(&cache_dir).map_err(|e| e.to_string())?;
+            if let Err(e) = std::fs::write(&cache_path, &jpeg) {
+                eprintln!("Failed to cache thumbnail for {}: {}", id, e);
+            }
+            jpeg
+        }
+    };
+
+    Ok(format!("data:image/jpeg;base64,{}", general_purpose::STANDARD.encode(jpeg)))
+}
+
+fn render_thumbnail(source: &str, max_dimension: u32, quality: u8) -> Result<Vec<u8>, String> {
+    let image = image::open(source).map_err(|e| format!("Failed to decode image: {}", e))?;
+    let image = if image.width() > max_dimension || image.height() > max_dimension {
+        image.thumbnail(max_dimension, max_dimension)
+    } else {
+        image
+    };
+
+    // JPEG has no alpha channel, so transparent areas are flattened onto white
+    let rgba = image.to_rgba8();
+    let mut rgb = image::RgbImage::new(rgba.width(), rgba.height());
+    for (target, source) in rgb.pixels_mut().zip(rgba.pixels()) {
+        let [r, g, b, a] = source.0;
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+        *target = image::Rgb([blend(r), blend(g), blend(b)]);
+    }
+
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, quality)
+        .encode_image(&rgb)
+        .map_err(|e| format!("Failed to encode thumbnail: {}", e))?;
+    Ok(jpeg)
 }
 
 #[tauri::command]
 async fn get_file_preview(file_path: String, max_length: Option<usize>) -> Result<Option<String>, String> {
     use std::fs;
+    use std::io::Read;
     use std::path::Path;
     
     let path = Path::new(&file_path);
@@ -1908,23 +8485,14 @@ async fn get_file_preview(file_path: String, max_length: Option<usize>) -> Resul
         return Err("File does not exist".to_string());
     }
     
-    // Get file extension to determine if it's likely a text file
-    let extension = path.extension()
-        .and_then(|ext| ext.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+    // Sniff the leading bytes to determine if it's a text file
+    let mut head = Vec::new();
+    if let Ok(file) = fs::File::open(path) {
+        let _ = file.take(PREVIEW_SNIFF_BYTES).read_to_end(&mut head);
+    }
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
     
-    // List of text-based file extensions
-    let text_extensions = [
-        "txt", "md", "json", "xml", "html", "htm", "css", "js", "ts", "jsx", "tsx",
-        "py", "rs", "go", "java", "c", "cpp", "h", "hpp", "cs", "php", "rb", "pl",
-        "sh", "bash", "zsh", "fish", "ps1", "bat", "cmd", "sql", "log", "cfg", "conf",
-        "ini", "toml", "yaml", "yml", "csv", "tsv", "rtf", "tex", "dockerfile", "gitignore",
-        "readme", "license", "changelog", "makefile", "cmake", "vcxproj", "csproj",
-        "swift", "kt", "scala", "clj", "hs", "elm", "dart", "lua", "r", "jl", "m", "mm"
-    ];
-    
-    if !text_extensions.contains(&extension.as_str()) {
+    if !detect_mime_type(file_name, &head).as_deref().is_some_and(is_text_mime_type) {
         return Ok(None); // Not a text file, no preview available
     }
     
@@ -1932,13 +8500,13 @@ async fn get_file_preview(file_path: String, max_length: Option<usize>) -> Resul
     match fs::read_to_string(&file_path) {
         Ok(content) => {
             let max_len = max_length.unwrap_or(200); // Default to 200 characters
-            if content.len() <= max_len {
+            let (truncated, cut) = truncate_chars(&content, max_len);
+            if !cut {
                 Ok(Some(content))
             } else {
                 // Truncate at word boundary if possible
-                let truncated = &content[..max_len];
                 if let Some(last_space) = truncated.rfind(' ') {
-                    Ok(Some(format!("{}...", &content[..last_space])))
+                    Ok(Some(format!("{}...", &truncated[..last_space])))
                 } else {
                     Ok(Some(format!("{}...", truncated)))
                 }
@@ -1951,6 +8519,38 @@ async fn get_file_preview(file_path: String, max_length: Option<usize>) -> Resul
     }
 }
 
+// Upper bound for get_file_hex_preview so a large request can't flood the UI
+const MAX_HEX_PREVIEW_BYTES: usize = 4096;
+
+// Formats bytes as a classic hex dump: offset, 16 hex bytes, printable ASCII
+fn format_hex_dump(bytes: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        dump.push_str(&format!("{:08x}  {:<47}  |{}|\n", line * 16, hex.join(" "), ascii));
+    }
+    dump
+}
+
+#[tauri::command]
+async fn get_file_hex_preview(file_path: String, max_bytes: usize) -> Result<String, String> {
+    use std::io::Read;
+
+    let file = std::fs::File::open(&file_path).map_err(|e| match e.kind() {
+        std::io::ErrorKind::NotFound => "File does not exist".to_string(),
+        _ => format!("Failed to open file: {}", e),
+    })?;
+
+    let limit = max_bytes.min(MAX_HEX_PREVIEW_BYTES);
+    let mut bytes = Vec::with_capacity(limit);
+    file.take(limit as u64).read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+
+    Ok(format_hex_dump(&bytes))
+}
+
 #[tauri::command]
 async fn get_files_storage_directory_path() -> Result<String, String> {
     get_files_storage_directory()
@@ -1958,32 +8558,17 @@ async fn get_files_storage_directory_path() -> Result<String, String> {
 
 #[tauri::command]
 async fn move_clipboard_item_to_top(state: State<'_, AppState>, id: String) -> Result<(), String> {
-    let db_path = state.db_path.lock().unwrap().clone();
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone();
     if let Some(db_path) = db_path {
-        let conn = Connection::open(&db_path).map_err(|e| e.to_string())?;
-        
         // Get the current item
-        let mut stmt = conn.prepare(
-            "SELECT id, content, timestamp, device, content_type, file_path, file_size, file_name FROM clipboard_items WHERE id = ?1"
-        ).map_err(|e| e.to_string())?;
-        
-        let item = stmt.query_row([&id], |row| {
-            Ok(ClipboardItem {
-                id: row.get(0)?,
-                content: row.get(1)?,
-                timestamp: row.get(2)?,
-                device: row.get(3)?,
-                content_type: row.get(4)?,
-                file_path: row.get(5).ok(),
-                file_size: row.get(6).ok(),
-                file_name: row.get(7).ok(),
-            })
-        }).map_err(|e| e.to_string())?;
+        let item = load_clipboard_item_from_db(&db_path, &id)?;
         
         // Update the timestamp to current time to make it appear at the top
         let current_timestamp = get_current_timestamp().to_string();
         let mut updated_item = item;
         updated_item.timestamp = current_timestamp;
+        updated_item.use_count += 1;
         
         // Save the updated item back to the database
         save_clipboard_item_to_db(&db_path, &updated_item)?;
@@ -1993,3 +8578,593 @@ async fn move_clipboard_item_to_top(state: State<'_, AppState>, id: String) -> R
         Err("Database not initialized".to_string())
     }
 }
+
+#[tauri::command]
+async fn activate_item(app_handle: AppHandle, state: State<'_, AppState>, id: String) -> Result<ClipboardItem, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    let mut item = load_clipboard_item_from_db(&db_path, &id)?;
+
+    // Set ignore flag so the monitor doesn't capture this as a new item
+    *state.ignore_next_clipboard_change.lock_or_recover() = true;
+    if let Err(e) = set_system_clipboard_for_item(&item) {
+        *state.ignore_next_clipboard_change.lock_or_recover() = false;
+        return Err(e);
+    }
+
+    // Bump to the top of history and count the use
+    item.timestamp = get_current_timestamp().to_string();
+    item.use_count += 1;
+    save_clipboard_item_to_db(&db_path, &item)?;
+
+    if is_history_visible(&item.content_type) {
+        let mut history = state.clipboard_history.lock_or_recover();
+        history.retain(|existing| existing.id != item.id && existing.content != item.content);
+        history.insert(0, item.clone());
+        history.truncate(50);
+    }
+
+    let _ = app_handle.emit("item-activated", &item);
+    println!("Activated clipboard item: {}", item.id);
+
+    let auto_paste = state.settings.lock_or_recover().auto_paste;
+    if auto_paste {
+        // Hand focus back to the app the user came from before pasting into it
+        if let Some(window) = app_handle.get_webview_window("cliped") {
+            let _ = window.hide();
+        }
+        tokio::time::sleep(Duration::from_millis(150)).await;
+
+        if let Err(e) = simulate_paste() {
+            eprintln!("Auto-paste failed: {}", e);
+            let _ = app_handle.emit("auto-paste-failed", &e);
+        }
+    }
+
+    Ok(item)
+}
+
+// The in-memory history item at `index`, 0 being the most recent
+#[tauri::command]
+fn get_history_at(state: State<AppState>, index: usize) -> Option<ClipboardItem> {
+    state.clipboard_history.lock_or_recover().get(index).cloned()
+}
+
+// Puts the history item at `index` on the clipboard for a clipboard ring hotkey.
+// Unlike activate_item this leaves the history order alone, so stepping through
+// indices 1, 2, 3... visits successively older items.
+#[tauri::command]
+async fn activate_history_at(app_handle: AppHandle, state: State<'_, AppState>, index: usize) -> Result<Option<ClipboardItem>, String> {
+    let Some(item) = state.clipboard_history.lock_or_recover().get(index).cloned() else {
+        return Ok(None);
+    };
+
+    // Set ignore flag so the monitor doesn't capture this as a new item. Cycling
+    // back to what's already on the clipboard changes nothing the monitor would
+    // see, and a flag left set would swallow the user's next real copy.
+    let is_current = *state.last_clipboard_content.lock_or_recover() == item.content;
+    *state.ignore_next_clipboard_change.lock_or_recover() = !is_current;
+    if let Err(e) = set_system_clipboard_for_item(&item) {
+        *state.ignore_next_clipboard_change.lock_or_recover() = false;
+        return Err(e);
+    }
+
+    let _ = app_handle.emit("history-cycled", index);
+    println!("Activated history item {} ({})", index, item.id);
+    Ok(Some(item))
+}
+
+#[tauri::command]
+async fn set_item_metadata(state: State<'_, AppState>, id: String, key: String, value: String) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    let mut item = load_clipboard_item_from_db(&db_path, &id)?;
+    item.metadata.insert(key.clone(), value.clone());
+    save_clipboard_item_to_db(&db_path, &item)?;
+
+    // Keep the in-memory copy in step
+    if let Some(existing) = state.clipboard_history.lock_or_recover().iter_mut().find(|i| i.id == id) {
+        existing.metadata.insert(key, value);
+    }
+    Ok(())
+}
+
+// Keeps an item on this device (true) or lets it sync to peers again (false)
+#[tauri::command]
+async fn set_item_no_sync(state: State<'_, AppState>, id: String, flag: bool) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    let mut item = load_clipboard_item_from_db(&db_path, &id)?;
+    item.no_sync = flag;
+    save_clipboard_item_to_db(&db_path, &item)?;
+
+    if let Some(existing) = state.clipboard_history.lock_or_recover().iter_mut().find(|i| i.id == id) {
+        existing.no_sync = flag;
+    }
+    println!("Item {} {}", id, if flag { "marked no-sync" } else { "allowed to sync" });
+    Ok(())
+}
+
+// Schedules an item for deletion at `expires_at` (Unix seconds); None keeps it indefinitely
+#[tauri::command]
+async fn set_item_expiry(app_handle: AppHandle, state: State<'_, AppState>, id: String, expires_at: Option<u64>) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    let mut item = load_clipboard_item_from_db(&db_path, &id)?;
+    item.expires_at = expires_at;
+    save_clipboard_item_to_db(&db_path, &item)?;
+
+    if let Some(existing) = state.clipboard_history.lock_or_recover().iter_mut().find(|i| i.id == id) {
+        existing.expires_at = expires_at;
+    }
+
+    // An expiry already in the past takes effect now rather than at the next sweep
+    if expires_at.is_some_and(|t| t <= get_current_timestamp()) {
+        purge_expired_items(&app_handle);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_item_metadata(state: State<'_, AppState>, id: String) -> Result<HashMap<String, String>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    Ok(load_clipboard_item_from_db(&db_path, &id)?.metadata)
+}
+
+#[tauri::command]
+async fn get_most_used_items(state: State<'_, AppState>, limit: u32) -> Result<Vec<ClipboardItem>, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        get_most_used_items_from_db(&db_path, limit)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+// Joins the text of the `limit` most recent non-file, non-image items, newest
+// first, for scripts that just want the clips as a string
+#[tauri::command]
+async fn export_history_as_text(state: State<'_, AppState>, separator: String, limit: u32) -> Result<String, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    Ok(load_recent_text_contents_from_db(&db_path, limit)?.join(&separator))
+}
+
+#[tauri::command]
+async fn save_snippet(state: State<'_, AppState>, name: String, template: String) -> Result<Snippet, String> {
+    ensure_writable(&state)?;
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Snippet name cannot be empty".to_string());
+    }
+
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        save_snippet_to_db(&db_path, &name, &template)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn list_snippets(state: State<'_, AppState>) -> Result<Vec<Snippet>, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        load_snippets_from_db(&db_path)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn expand_snippet(state: State<'_, AppState>, name: String, values: HashMap<String, String>) -> Result<String, String> {
+    let db_path = state.db_path.lock_or_recover().clone();
+    if let Some(db_path) = db_path {
+        let template = get_snippet_template_from_db(&db_path, &name)?;
+        expand_template(&template, &values)
+    } else {
+        Err("Database not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn create_collection(state: State<'_, AppState>, name: String, parent_id: Option<i64>) -> Result<Collection, String> {
+    ensure_writable(&state)?;
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err("Collection name cannot be empty".to_string());
+    }
+
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let collection = create_collection_in_db(&db_path, &name, parent_id)?;
+    println!("Created collection: {} ({})", collection.name, collection.id);
+    Ok(collection)
+}
+
+#[tauri::command]
+async fn list_collections(state: State<'_, AppState>) -> Result<Vec<Collection>, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    load_collections_from_db(&db_path)
+}
+
+// A None collection_id takes the item out of its collection
+#[tauri::command]
+async fn move_item_to_collection(state: State<'_, AppState>, id: String, collection_id: Option<i64>) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    move_item_to_collection_in_db(&db_path, &id, collection_id)
+}
+
+// Nested collections go with it. Items are only unfiled unless delete_items is set.
+#[tauri::command]
+async fn delete_collection(state: State<'_, AppState>, collection_id: i64, delete_items: bool) -> Result<(), String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let deleted = delete_collection_from_db(&db_path, collection_id, delete_items)?;
+
+    if !deleted.is_empty() {
+        state.clipboard_history.lock_or_recover().retain(|item| !deleted.contains(&item.id));
+        for id in &deleted {
+            remove_item_thumbnails(id);
+        }
+    }
+    println!("Deleted collection {} and {} of its items", collection_id, deleted.len());
+    Ok(())
+}
+
+#[tauri::command]
+async fn request_full_history(state: State<'_, AppState>, device_id: u32) -> Result<(), String> {
+    let device = {
+        let devices = state.devices.lock_or_recover();
+        devices.get(&device_id).cloned()
+    };
+
+    let device = device.ok_or("Device not found".to_string())?;
+    if !matches!(device.status, DeviceStatus::Connected) {
+        return Err("Device is not connected".to_string());
+    }
+
+    let local_device = state.local_device.lock_or_recover().clone();
+    if let Some(local) = local_device {
+        let message = NetworkMessage {
+            msg_type: MessageType::HistoryRequest,
+            device_id: local.id,
+            device_name: local.name,
+            data: None,
+            display_name: None,
+        };
+
+        send_message_to_device(&state.network_stats, network_interface(&state).as_deref(), &device.ip, &message).await?;
+        println!("Requested full history from {} at {}", device.name, device.ip);
+        Ok(())
+    } else {
+        Err("Local device not initialized".to_string())
+    }
+}
+
+#[tauri::command]
+async fn is_device_online(state: State<'_, AppState>, device_id: u32) -> Result<bool, String> {
+    let device = {
+        let devices = state.devices.lock_or_recover();
+        devices.get(&device_id).cloned()
+    };
+    let device = device.ok_or("Device not found".to_string())?;
+
+    // A recent heartbeat answers without a network round trip
+    if get_current_timestamp().saturating_sub(device.last_seen) <= HEARTBEAT_FRESHNESS_SECS {
+        return Ok(true);
+    }
+
+    let local = state.local_device.lock_or_recover().clone()
+        .ok_or("Local device not initialized".to_string())?;
+
+    if probe_device(&state.network_stats, network_interface(&state).as_deref(), &local, &device).await {
+        let mut devices = state.devices.lock_or_recover();
+        if let Some(device) = devices.get_mut(&device_id) {
+            device.last_seen = get_current_timestamp();
+        }
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+#[tauri::command]
+async fn reload_history(app_handle: AppHandle, state: State<'_, AppState>) -> Result<Vec<ClipboardItem>, String> {
+    let history = reload_history_from_db(&state)?;
+    let _ = app_handle.emit("history-updated", history.len());
+    println!("Reloaded {} items from database", history.len());
+    Ok(history)
+}
+
+#[tauri::command]
+async fn cancel_file_transfer(app_handle: AppHandle, state: State<'_, AppState>, file_id: String) -> Result<(), String> {
+    let transfers: Vec<FileTransferProgress> = state.active_transfers.lock_or_recover()
+        .values()
+        .filter(|transfer| transfer.file_id == file_id)
+        .cloned()
+        .collect();
+
+    if transfers.is_empty() {
+        return Err("No active transfer for this file".to_string());
+    }
+
+    if transfers.iter().any(|transfer| matches!(transfer.direction, TransferDirection::Send)) {
+        // The send loop notices this before its next chunk and tells the receivers
+        state.cancelled_transfers.lock_or_recover().insert((file_id.clone(), None));
+    }
+
+    for transfer in transfers.iter().filter(|transfer| matches!(transfer.direction, TransferDirection::Receive)) {
+        discard_incoming_transfer(&app_handle, &file_id, transfer.device_id);
+
+        // Tell the sender to stop streaming chunks we'd just drop
+        let sender = state.devices.lock_or_recover().get(&transfer.device_id).cloned();
+        let local = state.local_device.lock_or_recover().clone();
+        if let (Some(sender), Some(local)) = (sender, local) {
+            let message = NetworkMessage {
+                msg_type: MessageType::FileTransferCancel,
+                device_id: local.id,
+                device_name: local.name,
+                data: Some(serde_json::to_string(&FileTransferEnd { file_id: file_id.clone() }).map_err(|e| e.to_string())?),
+                display_name: None,
+            };
+            send_message_to_device(&state.network_stats, network_interface(&state).as_deref(), &sender.ip, &message).await?;
+        }
+    }
+
+    println!("Cancelled file transfer {}", file_id);
+    Ok(())
+}
+
+#[tauri::command]
+async fn set_data_directory(state: State<'_, AppState>, path: String) -> Result<String, String> {
+    // Moving the data directory wouldn't move locations pinned by the environment
+    if let Some(var) = [DB_PATH_ENV, FILES_DIR_ENV].into_iter().find(|var| env_path_override(var).is_some()) {
+        return Err(format!("Data location is set by the {} environment variable", var));
+    }
+
+    let new_dir = std::path::PathBuf::from(path.trim());
+    if !new_dir.is_absolute() {
+        return Err("Data directory must be an absolute path".to_string());
+    }
+    let existed = new_dir.exists();
+    std::fs::create_dir_all(&new_dir).map_err(|e| format!("Failed to create {}: {}", new_dir.display(), e))?;
+    check_directory_writable(&new_dir)?;
+
+    let old_dir = data_directory()?;
+    let same_dir = |a: &std::path::Path, b: &std::path::Path| {
+        a.canonicalize().ok().is_some_and(|a| Some(a) == b.canonicalize().ok())
+    };
+    if same_dir(&old_dir, &new_dir) {
+        return Ok(new_dir.to_string_lossy().to_string());
+    }
+    if is_within_directory(&new_dir, &old_dir) {
+        if !existed {
+            let _ = std::fs::remove_dir(&new_dir);
+        }
+        return Err(format!("{} is inside the current data directory", new_dir.display()));
+    }
+    if new_dir.join("clipboard.db").exists() {
+        return Err(format!("{} already contains a clipboard database", new_dir.display()));
+    }
+
+    // Hold the path for the whole move so no writer lands in the old database midway
+    let mut db_path = state.db_path.lock_or_recover();
+    let old_db_path = std::path::PathBuf::from(db_path.clone().ok_or("Database not initialized".to_string())?);
+    let new_db_path = move_data_directory(&old_db_path, &old_dir, &new_dir)?;
+
+    // Record the choice; the default directory needs no pointer
+    let pointer = data_directory_pointer_file()?;
+    if default_data_directory().is_ok_and(|default_dir| same_dir(&default_dir, &new_dir)) {
+        let _ = std::fs::remove_file(&pointer);
+    } else {
+        if let Some(parent) = pointer.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let pointer_tmp = pointer.with_extension("tmp");
+        std::fs::write(&pointer_tmp, new_dir.to_string_lossy().as_bytes()).map_err(|e| e.to_string())?;
+        std::fs::rename(&pointer_tmp, &pointer).map_err(|e| e.to_string())?;
+    }
+
+    *db_path = Some(new_db_path.to_string_lossy().to_string());
+    drop(db_path);
+
+    // The new copy is live, so the old one can go
+    for (_, backup_path) in list_database_backups(&old_db_path) {
+        let _ = std::fs::remove_file(backup_path);
+    }
+    for suffix in ["", "-wal", "-shm"] {
+        let mut old_file = old_db_path.as_os_str().to_owned();
+        old_file.push(suffix);
+        let _ = std::fs::remove_file(std::path::PathBuf::from(old_file));
+    }
+    let _ = std::fs::remove_dir_all(old_dir.join("files"));
+
+    println!("Moved data directory from {} to {}", old_dir.display(), new_dir.display());
+    Ok(new_dir.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+async fn get_active_transfers(state: State<'_, AppState>) -> Result<Vec<FileTransferProgress>, String> {
+    let transfers = state.active_transfers.lock_or_recover();
+    Ok(transfers.values().cloned().collect())
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DatabaseIntegrity {
+    healthy: bool,
+    problems: Vec<String>, // Messages from SQLite's integrity check; empty when healthy
+}
+
+#[tauri::command]
+async fn check_database_integrity(state: State<'_, AppState>) -> Result<DatabaseIntegrity, String> {
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    let rows = check_database_integrity_in_db(&db_path)?;
+    let healthy = rows.len() == 1 && rows[0] == "ok";
+    Ok(DatabaseIntegrity {
+        healthy,
+        problems: if healthy { Vec::new() } else { rows },
+    })
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct DatabaseRepair {
+    was_damaged: bool,
+    recovered_items: usize,
+    damaged_copy: Option<String>, // Where the damaged database was kept for manual recovery
+}
+
+// Rebuilds a damaged database from whatever rows can still be read. The
+// damaged file is kept next to the new one rather than deleted.
+#[tauri::command]
+async fn repair_database(app_handle: AppHandle, state: State<'_, AppState>) -> Result<DatabaseRepair, String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+
+    // A database too damaged to even run the check still gets rebuilt
+    let healthy = check_database_integrity_in_db(&db_path)
+        .map(|rows| rows.len() == 1 && rows[0] == "ok")
+        .unwrap_or(false);
+    if healthy {
+        return Ok(DatabaseRepair { was_damaged: false, recovered_items: 0, damaged_copy: None });
+    }
+
+    // Move the database and its WAL/SHM files aside together so SQLite still pairs them
+    let damaged_path = std::path::Path::new(&db_path)
+        .with_file_name(format!("clipboard.db.damaged-{}", get_current_timestamp()));
+    let move_with_sidecars = |from: &std::path::Path, to: &std::path::Path| -> Result<(), String> {
+        std::fs::rename(from, to).map_err(|e| format!("Failed to move database: {}", e))?;
+        for suffix in ["-wal", "-shm"] {
+            let mut from_sidecar = from.as_os_str().to_owned();
+            from_sidecar.push(suffix);
+            let mut to_sidecar = to.as_os_str().to_owned();
+            to_sidecar.push(suffix);
+            let _ = std::fs::rename(from_sidecar, to_sidecar);
+        }
+        Ok(())
+    };
+    move_with_sidecars(std::path::Path::new(&db_path), &damaged_path)?;
+
+    let new_db_path = match init_database() {
+        Ok(path) => path,
+        Err(e) => {
+            let _ = move_with_sidecars(&damaged_path, std::path::Path::new(&db_path));
+            return Err(format!("Failed to create a new database: {}", e));
+        }
+    };
+
+    let recovered_items = recover_database(&damaged_path, &new_db_path)?;
+    *state.clipboard_history.lock_or_recover() = load_clipboard_history_from_db(&new_db_path).unwrap_or_default();
+
+    println!("Database repaired: recovered {} items, damaged copy kept at {}", recovered_items, damaged_path.display());
+    let _ = app_handle.emit("database-repaired", recovered_items);
+
+    Ok(DatabaseRepair {
+        was_damaged: true,
+        recovered_items,
+        damaged_copy: Some(damaged_path.to_string_lossy().to_string()),
+    })
+}
+
+#[tauri::command]
+async fn factory_reset(app_handle: AppHandle, state: State<'_, AppState>, confirmation: String) -> Result<(), String> {
+    ensure_writable(&state)?;
+    if confirmation != FACTORY_RESET_CONFIRMATION {
+        return Err(format!("Factory reset requires the confirmation \"{}\"", FACTORY_RESET_CONFIRMATION));
+    }
+
+    // Pause capture so the monitor doesn't write new items mid-reset
+    let was_enabled = std::mem::replace(&mut *state.enabled.lock_or_recover(), false);
+
+    // Let paired devices know this identity is going away
+    notify_paired_devices_removed(&state).await;
+
+    // Hold the database path for the rest of the reset so no background
+    // task can save into the database while it's being emptied
+    let db_path = state.db_path.lock_or_recover();
+    if let Some(ref db_path) = *db_path {
+        wipe_database(db_path)?;
+        for (_, backup_path) in list_database_backups(std::path::Path::new(db_path)) {
+            let _ = std::fs::remove_file(backup_path);
+        }
+    }
+    if let Ok(files_dir) = get_files_storage_directory() {
+        if let Err(e) = std::fs::remove_dir_all(&files_dir) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("Failed to delete stored files: {}", e);
+            }
+        }
+    }
+
+    state.clipboard_history.lock_or_recover().clear();
+    state.pending_connections.lock_or_recover().clear();
+    state.discovered_devices.lock_or_recover().clear();
+    state.active_transfers.lock_or_recover().clear();
+    state.incoming_transfers.lock_or_recover().clear();
+    state.cancelled_transfers.lock_or_recover().clear();
+    *state.settings.lock_or_recover() = Settings::default();
+    state.transform_rules.lock_or_recover().clear();
+    state.no_sync_patterns.lock_or_recover().clear();
+
+    // Start over with a fresh identity
+    let new_local = generate_device_info(None);
+    if let Some(ref db_path) = *db_path {
+        save_local_device_to_db(db_path, &new_local);
+    }
+    state.devices.lock_or_recover().clear();
+    *state.local_device.lock_or_recover() = Some(new_local.clone());
+    drop(db_path);
+
+    *state.enabled.lock_or_recover() = was_enabled;
+
+    let _ = app_handle.emit("reset-complete", &new_local);
+    println!("Factory reset complete; new device id {}", new_local.id);
+    Ok(())
+}
+
+#[tauri::command]
+async fn restore_from_backup(app_handle: AppHandle, state: State<'_, AppState>) -> Result<String, String> {
+    ensure_writable(&state)?;
+    let db_path = state.db_path.lock_or_recover().clone()
+        .ok_or("Database not initialized".to_string())?;
+    let db_path = std::path::PathBuf::from(db_path);
+
+    let (version, backup_path) = list_database_backups(&db_path)
+        .into_iter()
+        .next()
+        .ok_or("No database backup found".to_string())?;
+
+    restore_database_file(&backup_path, &db_path)?;
+
+    // Bring the restored database back up to the current schema
+    init_database()?;
+    let history = reload_history_from_db(&state)?;
+    let _ = app_handle.emit("history-updated", history.len());
+
+    println!("Restored database from backup version {}", version);
+    Ok(backup_path.to_string_lossy().to_string())
+}
+
+#[cfg(test)]
+mod network_tests;
+#[cfg(test)]
+mod file_tests;
+#[cfg(test)]
+mod db_tests;
@@ -0,0 +1,577 @@
+// Drives handle_message with crafted datagrams and checks the resulting state
+use super::*;
+use tauri::test::{mock_app, MockRuntime};
+
+const LOCAL_ID: u32 = 1;
+const PEER_ID: u32 = 2;
+
+fn test_app() -> tauri::App<MockRuntime> {
+    let app = mock_app();
+    app.manage(AppState::default());
+    *app.state::<AppState>().local_device.lock_or_recover() = Some(device(LOCAL_ID, "127.0.0.1", DeviceStatus::Connected));
+    app
+}
+
+fn device(id: u32, ip: &str, status: DeviceStatus) -> Device {
+    Device {
+        id,
+        name: format!("device-{}", id),
+        icon: "laptop".to_string(),
+        ip: ip.to_string(),
+        status,
+        sync_mode: SyncMode::PartialSync,
+        last_seen: get_current_timestamp(),
+        trusted: false,
+        last_synced: None,
+        display_name: None,
+        disabled: false,
+        relay_key: None,
+    }
+}
+
+fn message(msg_type: MessageType, device_id: u32, data: Option<String>) -> NetworkMessage {
+    NetworkMessage {
+        msg_type,
+        device_id,
+        device_name: format!("device-{}", device_id),
+        data,
+        display_name: None,
+    }
+}
+
+fn text_item(content: &str) -> String {
+    serde_json::to_string(&ClipboardItem {
+        id: generate_id().to_string(),
+        content: content.to_string(),
+        timestamp: get_current_timestamp().to_string(),
+        device: "peer".to_string(),
+        content_type: "text".to_string(),
+        file_path: None,
+        file_size: None,
+        file_name: None,
+        use_count: 0,
+        content_size: content.len() as u64,
+        mime_type: None,
+        metadata: HashMap::new(),
+        expires_at: None,
+        no_sync: false,
+    })
+    .unwrap()
+}
+
+// The server socket and a socket standing in for the remote peer
+async fn sockets() -> (UdpSocket, UdpSocket) {
+    let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let peer = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    (server, peer)
+}
+
+#[tokio::test]
+async fn discovery_lists_sender_and_replies() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let peer_addr = peer.local_addr().unwrap();
+
+    let scan_id = Some("scan-1".to_string());
+    handle_message(app.handle(), &server, message(MessageType::Discovery, PEER_ID, scan_id.clone()), peer_addr).await;
+
+    let discovered = app.state::<AppState>().discovered_devices.lock_or_recover().clone();
+    assert_eq!(discovered.len(), 1);
+    assert_eq!(discovered[0].id, PEER_ID);
+    assert_eq!(discovered[0].ip, "127.0.0.1");
+
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+    let reply: NetworkMessage = serde_json::from_slice(&buf[..len]).unwrap();
+    assert!(matches!(reply.msg_type, MessageType::DiscoveryResponse));
+    assert_eq!(reply.device_id, LOCAL_ID);
+    assert_eq!(reply.data, scan_id);
+}
+
+#[tokio::test]
+async fn display_name_from_wrong_ip_is_ignored() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "10.0.0.5", DeviceStatus::Connected));
+
+    let mut announce = message(MessageType::Discovery, PEER_ID, None);
+    announce.display_name = Some("Spoofed".to_string());
+    handle_message(app.handle(), &server, announce, peer.local_addr().unwrap()).await;
+
+    assert_eq!(state.devices.lock_or_recover()[&PEER_ID].display_name, None);
+}
+
+#[tokio::test]
+async fn discovery_from_own_device_is_not_listed() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+
+    handle_message(app.handle(), &server, message(MessageType::Discovery, LOCAL_ID, None), peer.local_addr().unwrap()).await;
+
+    assert!(app.state::<AppState>().discovered_devices.lock_or_recover().is_empty());
+}
+
+#[tokio::test]
+async fn connection_request_becomes_pending() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+
+    handle_message(app.handle(), &server, message(MessageType::ConnectionRequest, PEER_ID, None), peer.local_addr().unwrap()).await;
+
+    let state = app.state::<AppState>();
+    let pending = state.pending_connections.lock_or_recover().clone();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].id, PEER_ID);
+    assert!(matches!(pending[0].status, DeviceStatus::Pending));
+    assert!(!state.devices.lock_or_recover().contains_key(&PEER_ID));
+}
+
+#[tokio::test]
+async fn connection_accept_connects_device() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.discovered_devices.lock_or_recover().push(device(PEER_ID, "127.0.0.1", DeviceStatus::Offline));
+
+    handle_message(app.handle(), &server, message(MessageType::ConnectionAccept, PEER_ID, None), peer.local_addr().unwrap()).await;
+
+    let devices = state.devices.lock_or_recover();
+    let accepted = devices.get(&PEER_ID).expect("accepted device is listed");
+    assert!(matches!(accepted.status, DeviceStatus::Connected));
+    assert_eq!(accepted.ip, "127.0.0.1");
+    assert!(state.discovered_devices.lock_or_recover().is_empty());
+}
+
+// Every device id is in at most one of the paired, pending and discovered lists
+fn assert_no_id_in_two_lists(state: &AppState) {
+    let mut seen = HashSet::new();
+    let ids = state.devices.lock_or_recover().keys().copied()
+        .chain(state.pending_connections.lock_or_recover().iter().map(|d| d.id))
+        .chain(state.discovered_devices.lock_or_recover().iter().map(|d| d.id))
+        .collect::<Vec<_>>();
+    for id in ids {
+        assert!(seen.insert(id), "device {} is listed twice", id);
+    }
+}
+
+#[tokio::test]
+async fn accepted_device_leaves_pending_and_discovered_lists() {
+    // Accepted by the peer
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.pending_connections.lock_or_recover().push(device(PEER_ID, "127.0.0.1", DeviceStatus::Pending));
+    state.discovered_devices.lock_or_recover().push(device(PEER_ID, "127.0.0.1", DeviceStatus::Offline));
+
+    handle_message(app.handle(), &server, message(MessageType::ConnectionAccept, PEER_ID, None), peer.local_addr().unwrap()).await;
+    assert!(state.devices.lock_or_recover().contains_key(&PEER_ID));
+    assert_no_id_in_two_lists(&state);
+
+    // Accepted by us
+    let app = test_app();
+    let state = app.state::<AppState>();
+    state.pending_connections.lock_or_recover().push(device(PEER_ID, "127.0.0.1", DeviceStatus::Pending));
+    state.discovered_devices.lock_or_recover().push(device(PEER_ID, "127.0.0.1", DeviceStatus::Offline));
+
+    accept_connection(app.state(), PEER_ID).await.unwrap();
+    assert!(state.devices.lock_or_recover().contains_key(&PEER_ID));
+    assert_no_id_in_two_lists(&state);
+}
+
+#[tokio::test]
+async fn connection_remove_unpairs_device() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "127.0.0.1", DeviceStatus::Connected));
+
+    handle_message(app.handle(), &server, message(MessageType::ConnectionRemove, PEER_ID, None), peer.local_addr().unwrap()).await;
+
+    assert!(!state.devices.lock_or_recover().contains_key(&PEER_ID));
+}
+
+#[tokio::test]
+async fn connection_remove_from_wrong_ip_is_ignored() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "10.0.0.5", DeviceStatus::Connected));
+
+    handle_message(app.handle(), &server, message(MessageType::ConnectionRemove, PEER_ID, None), peer.local_addr().unwrap()).await;
+
+    assert!(state.devices.lock_or_recover().contains_key(&PEER_ID));
+    assert!(state.discovered_devices.lock_or_recover().is_empty());
+}
+
+#[tokio::test]
+async fn clipboard_sync_from_wrong_ip_is_ignored() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "10.0.0.5", DeviceStatus::Connected));
+
+    let sync = message(MessageType::ClipboardSync, PEER_ID, Some(text_item("spoofed")));
+    handle_message(app.handle(), &server, sync, peer.local_addr().unwrap()).await;
+
+    assert!(state.devices.lock_or_recover()[&PEER_ID].last_synced.is_none());
+    assert!(!*state.ignore_next_clipboard_change.lock_or_recover());
+}
+
+#[tokio::test]
+async fn clipboard_sync_from_unknown_device_is_ignored() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "127.0.0.1", DeviceStatus::Connected));
+
+    let sync = message(MessageType::ClipboardSync, PEER_ID + 1, Some(text_item("unknown")));
+    handle_message(app.handle(), &server, sync, peer.local_addr().unwrap()).await;
+
+    assert!(state.devices.lock_or_recover()[&PEER_ID].last_synced.is_none());
+    assert!(!*state.ignore_next_clipboard_change.lock_or_recover());
+}
+
+#[tokio::test]
+async fn clipboard_sync_from_disabled_device_is_ignored() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    let mut muted = device(PEER_ID, "127.0.0.1", DeviceStatus::Connected);
+    muted.disabled = true;
+    state.devices.lock_or_recover().insert(PEER_ID, muted);
+
+    let sync = message(MessageType::ClipboardSync, PEER_ID, Some(text_item("muted")));
+    handle_message(app.handle(), &server, sync, peer.local_addr().unwrap()).await;
+
+    assert!(state.devices.lock_or_recover()[&PEER_ID].last_synced.is_none());
+    assert!(!*state.ignore_next_clipboard_change.lock_or_recover());
+}
+
+#[tokio::test]
+async fn oversized_clipboard_sync_is_refused() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.settings.lock_or_recover().max_item_size = 16;
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "127.0.0.1", DeviceStatus::Connected));
+
+    let sync = message(MessageType::ClipboardSync, PEER_ID, Some(text_item("far more than sixteen bytes")));
+    handle_message(app.handle(), &server, sync, peer.local_addr().unwrap()).await;
+
+    // Claiming to be a file doesn't get the text past the limit
+    let mut file: ClipboardItem = serde_json::from_str(&text_item("far more than sixteen bytes")).unwrap();
+    file.content_type = "file".to_string();
+    let sync = message(MessageType::ClipboardSync, PEER_ID, Some(serde_json::to_string(&file).unwrap()));
+    handle_message(app.handle(), &server, sync, peer.local_addr().unwrap()).await;
+
+    assert!(state.devices.lock_or_recover()[&PEER_ID].last_synced.is_none());
+    assert!(!*state.ignore_next_clipboard_change.lock_or_recover());
+}
+
+#[tokio::test]
+async fn local_device_never_becomes_a_connected_peer() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    // A looped-back acceptance carrying our own id
+    state.discovered_devices.lock_or_recover().push(device(LOCAL_ID, "127.0.0.1", DeviceStatus::Offline));
+
+    handle_message(app.handle(), &server, message(MessageType::ConnectionAccept, LOCAL_ID, None), peer.local_addr().unwrap()).await;
+    handle_message(app.handle(), &server, message(MessageType::ConnectionRequest, LOCAL_ID, None), peer.local_addr().unwrap()).await;
+
+    assert!(!state.devices.lock_or_recover().contains_key(&LOCAL_ID));
+    assert!(state.pending_connections.lock_or_recover().is_empty());
+
+    // Even if it got into the map some other way, it is never listed or synced to
+    let local = state.local_device.lock_or_recover().clone().unwrap();
+    state.devices.lock_or_recover().insert(LOCAL_ID, local);
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "127.0.0.1", DeviceStatus::Connected));
+    let connected: Vec<u32> = get_connected_devices(app.state()).iter().map(|d| d.id).collect();
+    assert_eq!(connected, vec![PEER_ID]);
+    let targets = sync::sync_targets(&state.devices.lock_or_recover(), Some(LOCAL_ID), false, true);
+    assert!(targets.iter().all(|d| d.id != LOCAL_ID));
+}
+
+#[tokio::test]
+async fn changing_interface_only_updates_the_local_device() {
+    let app = test_app();
+    let state = app.state::<AppState>();
+
+    set_network_interface(app.state(), "127.0.0.1".to_string()).await.unwrap();
+
+    assert_eq!(state.local_device.lock_or_recover().as_ref().unwrap().ip, "127.0.0.1");
+    assert!(state.devices.lock_or_recover().is_empty());
+}
+
+#[tokio::test]
+async fn repeated_total_sync_sends_history_once() {
+    let app = test_app();
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "127.0.0.1", DeviceStatus::Connected));
+    let item: ClipboardItem = serde_json::from_str(&text_item("history")).unwrap();
+    state.clipboard_history.lock_or_recover().push(item);
+
+    apply_sync_mode(app.handle(), PEER_ID, SyncMode::TotalSync).await.unwrap();
+    apply_sync_mode(app.handle(), PEER_ID, SyncMode::TotalSync).await.unwrap();
+    // Flapping back through partial sync within the debounce window doesn't resend either
+    apply_sync_mode(app.handle(), PEER_ID, SyncMode::PartialSync).await.unwrap();
+    apply_sync_mode(app.handle(), PEER_ID, SyncMode::TotalSync).await.unwrap();
+
+    let counters = state.network_stats.counters.lock_or_recover();
+    assert_eq!(counters.by_message_type["ClipboardSync"].messages_sent, 1);
+    assert!(matches!(state.devices.lock_or_recover()[&PEER_ID].sync_mode, SyncMode::TotalSync));
+}
+
+#[tokio::test]
+async fn paused_sync_neither_sends_nor_accepts_history() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "127.0.0.1", DeviceStatus::Connected));
+    let item: ClipboardItem = serde_json::from_str(&text_item("history")).unwrap();
+    state.clipboard_history.lock_or_recover().push(item);
+    *state.sync_paused_untrusted.lock_or_recover() = true;
+
+    apply_sync_mode(app.handle(), PEER_ID, SyncMode::TotalSync).await.unwrap();
+    assert!(!state.network_stats.counters.lock_or_recover().by_message_type.contains_key("ClipboardSync"));
+    assert!(matches!(state.devices.lock_or_recover()[&PEER_ID].sync_mode, SyncMode::TotalSync));
+
+    let history = format!("[{}]", text_item("incoming"));
+    handle_message(app.handle(), &server, message(MessageType::HistoryResponse, PEER_ID, Some(history)), peer.local_addr().unwrap()).await;
+    assert_eq!(state.clipboard_history.lock_or_recover().len(), 1);
+}
+
+fn transfer_header(total_size: u64) -> FileTransferHeader {
+    let item: ClipboardItem = serde_json::from_str(&text_item("file")).unwrap();
+    FileTransferHeader {
+        item,
+        total_size,
+        chunk_count: (total_size as usize).div_ceil(FILE_CHUNK_SIZE),
+    }
+}
+
+#[tokio::test]
+async fn oversized_incoming_transfer_is_refused() {
+    let app = test_app();
+
+    begin_incoming_transfer(app.handle(), PEER_ID, transfer_header(MAX_FILE_ITEM_SIZE + 1));
+
+    assert!(app.state::<AppState>().incoming_transfers.lock_or_recover().is_empty());
+}
+
+#[tokio::test]
+async fn stalled_incoming_transfer_is_dropped() {
+    let app = test_app();
+    let state = app.state::<AppState>();
+    let header = transfer_header(1024);
+    let file_id = header.item.id.clone();
+    begin_incoming_transfer(app.handle(), PEER_ID, header);
+
+    expire_stale_incoming_transfers(app.handle());
+    assert!(state.incoming_transfers.lock_or_recover().contains_key(&file_id));
+
+    let stalled_since = std::time::Instant::now().checked_sub(INCOMING_TRANSFER_TIMEOUT).unwrap();
+    state.incoming_transfers.lock_or_recover().get_mut(&file_id).unwrap().last_chunk_at = stalled_since;
+    expire_stale_incoming_transfers(app.handle());
+    assert!(state.incoming_transfers.lock_or_recover().is_empty());
+}
+
+#[test]
+fn peer_traffic_is_capped_and_pruned_to_known_devices() {
+    let app = test_app();
+    let state = app.state::<AppState>();
+    for i in 0..MAX_TRACKED_PEERS + 10 {
+        state.network_stats.record_received(&MessageType::Discovery, &format!("10.1.{}.{}", i / 256, i % 256), 10);
+    }
+    assert_eq!(state.network_stats.counters.lock_or_recover().by_peer.len(), MAX_TRACKED_PEERS);
+    assert_eq!(state.network_stats.counters.lock_or_recover().total.messages_received, MAX_TRACKED_PEERS as u64 + 10);
+
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "10.1.0.1", DeviceStatus::Connected));
+    prune_peer_stats(app.handle());
+    let by_peer = state.network_stats.counters.lock_or_recover().by_peer.clone();
+    assert_eq!(by_peer.keys().collect::<Vec<_>>(), vec!["10.1.0.1"]);
+}
+
+fn relayed_transfer(key: Option<&str>) -> (String, String) {
+    let header = transfer_header(1024);
+    let file_id = header.item.id.clone();
+    let message = message(MessageType::FileTransfer, PEER_ID, Some(serde_json::to_string(&header).unwrap()));
+    let mac = key.map(|key| relay_mac(key, LOCAL_ID, &message).unwrap());
+    (file_id, serde_json::to_string(&RelayFrame::Forward { to: LOCAL_ID, message, mac }).unwrap())
+}
+
+#[tokio::test]
+async fn relayed_message_must_be_signed_with_the_pairing_key() {
+    let app = test_app();
+    let state = app.state::<AppState>();
+    let key = generate_relay_key();
+    let mut peer = device(PEER_ID, "10.0.0.5", DeviceStatus::Offline);
+    peer.relay_key = Some(key.clone());
+    state.devices.lock_or_recover().insert(PEER_ID, peer);
+
+    let (_, unsigned) = relayed_transfer(None);
+    handle_relay_frame(app.handle(), &unsigned);
+    let (_, forged) = relayed_transfer(Some(&generate_relay_key()));
+    handle_relay_frame(app.handle(), &forged);
+    assert!(state.incoming_transfers.lock_or_recover().is_empty());
+
+    let (file_id, signed) = relayed_transfer(Some(&key));
+    handle_relay_frame(app.handle(), &signed);
+    assert!(state.incoming_transfers.lock_or_recover().contains_key(&file_id));
+}
+
+#[tokio::test]
+async fn concurrent_discovery_scans_share_one_sweep() {
+    let app = test_app();
+    let state = app.state::<AppState>();
+    {
+        let mut settings = state.settings.lock_or_recover();
+        settings.discovery_timeout_ms = 200;
+        settings.discovery_retries = 1;
+    }
+    let probes_sent = || {
+        let counters = state.network_stats.counters.lock_or_recover();
+        counters.by_message_type.get("Discovery").map(|c| c.messages_sent + c.send_failures).unwrap_or(0)
+    };
+
+    let (first, second) = tokio::join!(scan_for_devices(app.handle(), &state), scan_for_devices(app.handle(), &state));
+    assert_eq!(first.unwrap().len(), second.unwrap().len());
+    assert!(!*state.discovery_in_progress.lock_or_recover());
+    let concurrent_probes = probes_sent();
+
+    scan_for_devices(app.handle(), &state).await.unwrap();
+    assert_eq!(probes_sent(), concurrent_probes * 2);
+}
+
+#[tokio::test]
+async fn commands_still_answer_after_a_lock_is_poisoned() {
+    let app = test_app();
+    let state = app.state::<AppState>();
+    let history = state.clipboard_history.clone();
+    let _ = std::thread::spawn(move || {
+        let _guard = history.lock().unwrap();
+        panic!("poisoning the history lock");
+    }).join();
+    let devices = state.devices.clone();
+    let _ = std::thread::spawn(move || {
+        let _guard = devices.lock().unwrap();
+        panic!("poisoning the devices lock");
+    }).join();
+    assert!(state.clipboard_history.is_poisoned() && state.devices.is_poisoned());
+
+    assert!(get_clipboard_history(app.state()).await.unwrap().is_empty());
+    assert_eq!(get_clipboard_history_count(app.state()).await, Err("Database not initialized".to_string()));
+
+    let (server, peer) = sockets().await;
+    handle_message(app.handle(), &server, message(MessageType::ConnectionRequest, PEER_ID, None), peer.local_addr().unwrap()).await;
+    assert_eq!(state.pending_connections.lock_or_recover().len(), 1);
+}
+
+#[tokio::test]
+async fn keyless_pairing_at_new_address_waits_for_the_user() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    state.devices.lock_or_recover().insert(PEER_ID, device(PEER_ID, "10.0.0.5", DeviceStatus::Connected));
+
+    handle_message(app.handle(), &server, message(MessageType::Heartbeat, PEER_ID, None), peer.local_addr().unwrap()).await;
+
+    assert_eq!(state.devices.lock_or_recover()[&PEER_ID].ip, "10.0.0.5");
+    assert_eq!(state.unconfirmed_ip_changes.lock_or_recover().get(&PEER_ID).map(String::as_str), Some("127.0.0.1"));
+    assert!(state.ip_change_checks.lock_or_recover().is_empty());
+}
+
+#[tokio::test]
+async fn address_change_is_checked_once_at_a_time() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    let mut paired = device(PEER_ID, "10.0.0.5", DeviceStatus::Connected);
+    paired.relay_key = Some(generate_relay_key());
+    state.devices.lock_or_recover().insert(PEER_ID, paired);
+
+    check_device_ip_change(app.handle(), PEER_ID, "127.0.0.1");
+    check_device_ip_change(app.handle(), PEER_ID, "127.0.0.1");
+    assert_eq!(state.ip_change_checks.lock_or_recover().len(), 1);
+
+    // Claiming the id from the new address doesn't move the pairing without the key
+    handle_message(app.handle(), &server, message(MessageType::Heartbeat, PEER_ID, None), peer.local_addr().unwrap()).await;
+    assert_eq!(state.devices.lock_or_recover()[&PEER_ID].ip, "10.0.0.5");
+}
+
+#[tokio::test]
+async fn identity_check_is_answered_with_the_pairing_key() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    let key = generate_relay_key();
+    let mut paired = device(PEER_ID, "127.0.0.1", DeviceStatus::Connected);
+    paired.relay_key = Some(key.clone());
+    state.devices.lock_or_recover().insert(PEER_ID, paired);
+
+    let challenge = serde_json::to_string(&IdentityPayload { nonce: "nonce-1".to_string(), mac: None }).unwrap();
+    handle_message(app.handle(), &server, message(MessageType::IdentityCheck, PEER_ID, Some(challenge)), peer.local_addr().unwrap()).await;
+
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+    let reply: NetworkMessage = serde_json::from_slice(&buf[..len]).unwrap();
+    let proof: IdentityPayload = serde_json::from_str(reply.data.as_deref().unwrap()).unwrap();
+    assert_eq!(proof.nonce, "nonce-1");
+    assert!(verify_relay_mac(&key, PEER_ID, &identity_message(LOCAL_ID, "nonce-1"), &proof.mac.unwrap()));
+}
+
+fn key_rotation_request(signing_key: &str, new_key: &str) -> NetworkMessage {
+    let mac = relay_mac(signing_key, LOCAL_ID, &key_rotation_message(PEER_ID, new_key)).unwrap();
+    let payload = KeyRotationPayload { key: Some(new_key.to_string()), mac };
+    message(MessageType::KeyRotation, PEER_ID, Some(serde_json::to_string(&payload).unwrap()))
+}
+
+#[tokio::test]
+async fn key_rotation_must_be_signed_with_the_pairing_key() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    let old_key = generate_relay_key();
+    let new_key = generate_relay_key();
+    let mut paired = device(PEER_ID, "127.0.0.1", DeviceStatus::Connected);
+    paired.relay_key = Some(old_key.clone());
+    state.devices.lock_or_recover().insert(PEER_ID, paired);
+
+    handle_message(app.handle(), &server, key_rotation_request(&generate_relay_key(), &new_key), peer.local_addr().unwrap()).await;
+    assert_eq!(state.devices.lock_or_recover()[&PEER_ID].relay_key.as_deref(), Some(old_key.as_str()));
+
+    handle_message(app.handle(), &server, key_rotation_request(&old_key, &new_key), peer.local_addr().unwrap()).await;
+    assert_eq!(state.devices.lock_or_recover()[&PEER_ID].relay_key.as_deref(), Some(new_key.as_str()));
+
+    // The only reply is the confirmation, signed with the new key
+    let mut buf = vec![0; MAX_DATAGRAM_SIZE];
+    let (len, _) = tokio::time::timeout(Duration::from_secs(1), peer.recv_from(&mut buf)).await.unwrap().unwrap();
+    let reply: NetworkMessage = serde_json::from_slice(&buf[..len]).unwrap();
+    let confirmation: KeyRotationPayload = serde_json::from_str(reply.data.as_deref().unwrap()).unwrap();
+    assert!(confirmation.key.is_none());
+    assert!(verify_relay_mac(&new_key, PEER_ID, &key_rotation_message(LOCAL_ID, &new_key), &confirmation.mac));
+}
+
+#[tokio::test]
+async fn rotated_out_key_is_accepted_until_the_device_pairs_again() {
+    let app = test_app();
+    let (server, peer) = sockets().await;
+    let state = app.state::<AppState>();
+    let old_key = generate_relay_key();
+    let mut paired = device(PEER_ID, "127.0.0.1", DeviceStatus::Connected);
+    paired.relay_key = Some(old_key.clone());
+    state.devices.lock_or_recover().insert(PEER_ID, paired);
+    handle_message(app.handle(), &server, key_rotation_request(&old_key, &generate_relay_key()), peer.local_addr().unwrap()).await;
+
+    // Frames signed before the rotation reached the peer still get through
+    let (file_id, signed) = relayed_transfer(Some(&old_key));
+    handle_relay_frame(app.handle(), &signed);
+    assert!(state.incoming_transfers.lock_or_recover().contains_key(&file_id));
+
+    // Pairing again replaces the key, and the rotated-out one goes with it
+    state.devices.lock_or_recover().get_mut(&PEER_ID).unwrap().relay_key = Some(generate_relay_key());
+    let (file_id, signed) = relayed_transfer(Some(&old_key));
+    handle_relay_frame(app.handle(), &signed);
+    assert!(!state.incoming_transfers.lock_or_recover().contains_key(&file_id));
+}
@@ -0,0 +1,304 @@
+// Deciding which devices an item goes to and sending it there. The filtering
+// works on plain device data so it can be tested without a running app.
+use super::*;
+
+// Connected devices still need the user's trust to sync when explicit trust is
+// required, and never sync while the user has disabled them
+pub(crate) fn is_sync_allowed(device: &Device, require_trust: bool) -> bool {
+    !device.disabled && (!require_trust || device.trusted)
+}
+
+// Whether a new item should be sent to `device`: it must be reachable (connected
+// on the LAN, or paired with a relay key while the relay is up), have sync enabled, be allowed
+// to sync, and not be this machine
+pub(crate) fn is_sync_target(device: &Device, local_id: Option<u32>, require_trust: bool, relay_connected: bool) -> bool {
+    (matches!(device.status, DeviceStatus::Connected) || (relay_connected && device.relay_key.is_some()))
+        && !matches!(device.sync_mode, SyncMode::Disabled)
+        && is_sync_allowed(device, require_trust)
+        && Some(device.id) != local_id
+}
+
+pub(crate) fn sync_targets(
+    devices: &HashMap<u32, Device>,
+    local_id: Option<u32>,
+    require_trust: bool,
+    relay_connected: bool,
+) -> Vec<Device> {
+    devices
+        .values()
+        .filter(|device| is_sync_target(device, local_id, require_trust, relay_connected))
+        .cloned()
+        .collect()
+}
+
+// Drops earlier copies of `content` from the `window` most recent history items
+pub(crate) fn dedup_history(history: &mut Vec<ClipboardItem>, content: &str, window: usize) {
+    let mut index = 0;
+    history.retain(|existing| {
+        let in_window = index < window;
+        index += 1;
+        !(in_window && existing.content == content)
+    });
+}
+
+// Returns each targeted device with whether the item reached it
+pub(crate) async fn sync_to_connected_devices(
+    stats: &NetworkStats,
+    interface: Option<&str>,
+    devices: &Arc<Mutex<HashMap<u32, Device>>>,
+    local_device: &Arc<Mutex<Option<Device>>>,
+    item: &ClipboardItem,
+    require_trust: bool,
+    relay: Option<&RelayOutbox>
+) -> Vec<(u32, Result<(), String>)> {
+    let mut results = Vec::new();
+    if item.no_sync {
+        println!("Item is marked no-sync - keeping it on this device");
+        return results;
+    }
+
+    // Get fresh device data each time; paired devices off the LAN are reached through the relay
+    let local = local_device.lock_or_recover().clone();
+    let devices_to_sync = sync_targets(&devices.lock_or_recover(), local.as_ref().map(|l| l.id), require_trust, relay.is_some());
+
+    // If no connected devices, don't send any broadcasts
+    if devices_to_sync.is_empty() {
+        println!("No connected devices with sync enabled - skipping all clipboard sync broadcasts");
+        return results;
+    }
+
+    if let Some(local) = local {
+        println!("Syncing clipboard item to {} connected devices", devices_to_sync.len());
+
+        // Only send to specific connected devices, no broadcasting
+        for device in devices_to_sync {
+            // Create sync message
+            let message = NetworkMessage {
+                msg_type: MessageType::ClipboardSync,
+                device_id: local.id,
+                device_name: local.name.clone(),
+                data: Some(serde_json::to_string(item).unwrap_or_default()),
+                display_name: None,
+            };
+
+            if let (false, Some(relay)) = (matches!(device.status, DeviceStatus::Connected), relay) {
+                let result = RelayFrame::forward(&device, message).and_then(|frame| relay.send(frame)
+                    .map_err(|_| "Relay connection closed".to_string()));
+                if result.is_ok() {
+                    println!("Synced clipboard to {} through the relay", device.name);
+                }
+                results.push((device.id, result));
+                continue;
+            }
+
+            // Send directly to specific device IP
+            let result = match bind_udp_socket(interface).await {
+                Ok(socket) => {
+                    let message_json = serde_json::to_string(&message).unwrap_or_default();
+                    let target_addr = format!("{}:51847", device.ip);
+                    send_datagram(stats, &socket, &message.msg_type, &message_json, &target_addr, Some(&device.ip)).await
+                },
+                Err(e) => Err(format!("Failed to create UDP socket: {}", e)),
+            };
+            if result.is_ok() {
+                println!("Synced clipboard to connected device: {} at {}", device.name, device.ip);
+            }
+            results.push((device.id, result));
+        }
+    }
+
+    results
+}
+
+pub(crate) async fn sync_file_to_connected_devices(
+    app_handle: &AppHandle,
+    devices: &Arc<Mutex<HashMap<u32, Device>>>,
+    local_device: &Arc<Mutex<Option<Device>>>,
+    item: &ClipboardItem,
+    file_content: &[u8]
+) {
+    let state = app_handle.state::<AppState>();
+    if item.no_sync {
+        println!("File is marked no-sync - keeping it on this device");
+        return;
+    }
+    if is_sync_paused(&state) {
+        println!("Sync paused on an untrusted network - not sending file");
+        return;
+    }
+    let require_trust = state.settings.lock_or_recover().require_explicit_trust;
+    let relay_connected = relay_outbox(&state).is_some();
+
+    // Paired devices off the LAN are reached through the relay
+    let local = local_device.lock_or_recover().clone();
+    let devices_to_sync = sync_targets(&devices.lock_or_recover(), local.as_ref().map(|l| l.id), require_trust, relay_connected);
+
+    if devices_to_sync.is_empty() {
+        println!("No connected devices with sync enabled - skipping file sync");
+        return;
+    }
+
+    if let Some(local) = local {
+        println!("Syncing file to {} connected devices: {} ({} bytes)",
+                devices_to_sync.len(),
+                item.file_name.as_deref().unwrap_or("unknown"),
+                file_content.len());
+
+        for device in devices_to_sync {
+            match send_file_to_device(app_handle, &local, &device, item, file_content).await {
+                Ok(()) => {
+                    finish_transfer(app_handle, &item.id, device.id, Ok(()));
+                    println!("Synced file to connected device: {} at {}", device.name, device.ip);
+                },
+                Err(e) => {
+                    eprintln!("Failed to sync file to {}: {}", device.name, e);
+                    finish_transfer(app_handle, &item.id, device.id, Err(e));
+                }
+            }
+        }
+    }
+
+    state.cancelled_transfers.lock_or_recover().retain(|(file_id, _)| file_id != &item.id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL_ID: u32 = 1;
+
+    fn device(id: u32, status: DeviceStatus, sync_mode: SyncMode) -> Device {
+        Device {
+            id,
+            name: format!("device-{}", id),
+            icon: "laptop".to_string(),
+            ip: format!("192.168.1.{}", id),
+            status,
+            sync_mode,
+            last_seen: 0,
+            trusted: false,
+            last_synced: None,
+            display_name: None,
+            disabled: false,
+            relay_key: None,
+        }
+    }
+
+    fn item(content: &str) -> ClipboardItem {
+        ClipboardItem {
+            id: content.to_string(),
+            content: content.to_string(),
+            timestamp: "0".to_string(),
+            device: "test".to_string(),
+            content_type: "text".to_string(),
+            file_path: None,
+            file_size: None,
+            file_name: None,
+            use_count: 0,
+            content_size: content.len() as u64,
+            mime_type: None,
+            metadata: HashMap::new(),
+            expires_at: None,
+            no_sync: false,
+        }
+    }
+
+    #[test]
+    fn connected_device_with_sync_enabled_is_a_target() {
+        let peer = device(2, DeviceStatus::Connected, SyncMode::PartialSync);
+        assert!(is_sync_target(&peer, Some(LOCAL_ID), false, false));
+    }
+
+    #[test]
+    fn local_device_is_never_a_target() {
+        let local = device(LOCAL_ID, DeviceStatus::Connected, SyncMode::PartialSync);
+        assert!(!is_sync_target(&local, Some(LOCAL_ID), false, true));
+    }
+
+    #[test]
+    fn device_with_sync_disabled_is_not_a_target() {
+        let peer = device(2, DeviceStatus::Connected, SyncMode::Disabled);
+        assert!(!is_sync_target(&peer, Some(LOCAL_ID), false, false));
+    }
+
+    #[test]
+    fn offline_device_is_a_target_only_through_the_relay() {
+        let mut peer = device(2, DeviceStatus::Offline, SyncMode::PartialSync);
+        peer.relay_key = Some("key".to_string());
+        assert!(!is_sync_target(&peer, Some(LOCAL_ID), false, false));
+        assert!(is_sync_target(&peer, Some(LOCAL_ID), false, true));
+    }
+
+    #[test]
+    fn device_paired_without_a_relay_key_is_not_a_relay_target() {
+        let peer = device(2, DeviceStatus::Offline, SyncMode::PartialSync);
+        assert!(!is_sync_target(&peer, Some(LOCAL_ID), false, true));
+    }
+
+    #[test]
+    fn untrusted_device_is_not_a_target_when_trust_is_required() {
+        let mut peer = device(2, DeviceStatus::Connected, SyncMode::PartialSync);
+        assert!(!is_sync_target(&peer, Some(LOCAL_ID), true, false));
+        peer.trusted = true;
+        assert!(is_sync_target(&peer, Some(LOCAL_ID), true, false));
+    }
+
+    #[test]
+    fn disabled_device_is_not_a_target() {
+        let mut peer = device(2, DeviceStatus::Connected, SyncMode::PartialSync);
+        peer.disabled = true;
+        assert!(!is_sync_target(&peer, Some(LOCAL_ID), false, true));
+    }
+
+    #[test]
+    fn sync_targets_filters_device_map() {
+        let devices: HashMap<u32, Device> = [
+            device(LOCAL_ID, DeviceStatus::Connected, SyncMode::PartialSync),
+            device(2, DeviceStatus::Connected, SyncMode::PartialSync),
+            device(3, DeviceStatus::Connected, SyncMode::Disabled),
+            device(4, DeviceStatus::Pending, SyncMode::PartialSync),
+        ]
+        .into_iter()
+        .map(|d| (d.id, d))
+        .collect();
+
+        let targets = sync_targets(&devices, Some(LOCAL_ID), false, false);
+        assert_eq!(targets.iter().map(|d| d.id).collect::<Vec<_>>(), vec![2]);
+    }
+
+    #[test]
+    fn dedup_history_only_looks_inside_window() {
+        let mut history = vec![item("a"), item("b"), item("a")];
+        dedup_history(&mut history, "a", 2);
+        let contents: Vec<&str> = history.iter().map(|i| i.content.as_str()).collect();
+        assert_eq!(contents, vec!["b", "a"]);
+    }
+
+    #[test]
+    fn dedup_history_with_zero_window_keeps_everything() {
+        let mut history = vec![item("a"), item("a")];
+        dedup_history(&mut history, "a", 0);
+        assert_eq!(history.len(), 2);
+    }
+
+    #[test]
+    fn dedup_history_with_window_of_five_ignores_older_copies() {
+        let mut history: Vec<ClipboardItem> = ["a", "b", "c", "a", "d", "e", "a"].into_iter().map(item).collect();
+        dedup_history(&mut history, "a", 5);
+        let contents: Vec<&str> = history.iter().map(|i| i.content.as_str()).collect();
+        assert_eq!(contents, vec!["b", "c", "d", "e", "a"]);
+    }
+
+    #[test]
+    fn dedup_history_with_full_window_drops_every_copy() {
+        let mut history: Vec<ClipboardItem> = ["a", "b", "c", "a", "d", "e", "a"].into_iter().map(item).collect();
+        dedup_history(&mut history, "a", usize::MAX);
+        let contents: Vec<&str> = history.iter().map(|i| i.content.as_str()).collect();
+        assert_eq!(contents, vec!["b", "c", "d", "e"]);
+
+        let mut history: Vec<ClipboardItem> = ["a", "b", "a"].into_iter().map(item).collect();
+        let len = history.len();
+        dedup_history(&mut history, "a", len);
+        assert_eq!(history.len(), 1);
+    }
+}
@@ -10,6 +10,9 @@ interface Device {
   status?: string;
   sync_mode?: string;
   last_seen?: number;
+  last_synced?: number | null;
+  display_name?: string | null;
+  disabled?: boolean;
 }
 
 interface SettingsPageProps {
@@ -49,11 +52,19 @@ const SettingsPage: React.FC<SettingsPageProps> = ({ onBack }) => {
           loadDevices();
         });
         
+        // Show devices as they answer instead of waiting for the scan to finish
+        const unlistenDeviceDiscovered = await listen<Device>("device-discovered", (event) => {
+          setAvailableDevices((current) =>
+            current.some((d) => d.id === event.payload.id) ? current : [...current, event.payload]
+          );
+        });
+        
         // Cleanup listeners on unmount
         return () => {
           unlistenConnectionRequest();
           unlistenConnectionAccepted();
           unlistenDeviceDisconnected();
+          unlistenDeviceDiscovered();
         };
       } catch (error) {
         console.error("Failed to setup event listener:", error);
@@ -111,6 +122,7 @@ const SettingsPage: React.FC<SettingsPageProps> = ({ onBack }) => {
 
   const discoverDevices = async () => {
     setIsDiscovering(true);
+    setAvailableDevices([]);
     try {
       const discovered = await invoke<Device[]>("discover_devices");
       // First refresh connected devices to get latest status
@@ -303,7 +315,7 @@ const SettingsPage: React.FC<SettingsPageProps> = ({ onBack }) => {
                 <div className="device-avatar">🔔</div>
                 <div className="device-details">
                   <p>
-                    <strong>{device.name}</strong> wants to connect
+                    <strong>{device.display_name || device.name}</strong> wants to connect
                   </p>
                   <p>IP: {device.ip}</p>
                 </div>
@@ -347,7 +359,7 @@ const SettingsPage: React.FC<SettingsPageProps> = ({ onBack }) => {
                 <div className="device-avatar">🖥️</div>
                 <div className="device-details">
                   <p>
-                    <strong>{device.name}</strong> (#{device.id})
+                    <strong>{device.display_name || device.name}</strong> (#{device.id})
                   </p>
                   <p>IP: {device.ip}</p>
                 </div>
@@ -387,7 +399,7 @@ const SettingsPage: React.FC<SettingsPageProps> = ({ onBack }) => {
                 <div className="device-avatar">📱</div>
                 <div className="device-details">
                   <p>
-                    <strong>{device.name}</strong>
+                    <strong>{device.display_name || device.name}</strong>
                   </p>
                   <p>IP: {device.ip}</p>
                 </div>
@@ -1,5 +1,6 @@
 import { useEffect, useState } from "react";
 import { invoke } from "@tauri-apps/api/core";
+import { listen } from "@tauri-apps/api/event";
 import { ClipboardItem } from "../types";
 
 interface NotificationState {
@@ -60,6 +61,22 @@ export function useClipboard() {
     loadHistory();
   }, []);
 
+  // Follow the monitor loop itself so a monitor that died shows as disabled
+  useEffect(() => {
+    const unlisteners = [
+      listen("monitoring-started", () => setIsEnabled(true)),
+      listen("monitoring-stopped", () => setIsEnabled(false)),
+      listen<string>("monitoring-error", (event) => {
+        console.error("Clipboard monitoring failed:", event.payload);
+        setIsEnabled(false);
+      }),
+    ];
+
+    return () => {
+      unlisteners.forEach((unlisten) => unlisten.then((fn) => fn()));
+    };
+  }, []);
+
   // Auto-refresh clipboard history every 500ms (reduced frequency for better performance with pagination)
   useEffect(() => {
     console.log("useClipboard: Setting up auto-refresh polling...");
@@ -202,18 +219,18 @@ export function useClipboard() {
 
   const selectItem = async (content: string) => {
     try {
-      await invoke("set_clipboard_content", { content });
-      
-      // Find the item by content and move it to top
+      // Find the item by content and activate it (copies it and moves it to top)
       const itemToMove = items.find(item => item.content === content);
       if (itemToMove) {
-        await invoke("move_clipboard_item_to_top", { id: itemToMove.id });
+        const activated = await invoke<ClipboardItem>("activate_item", { id: itemToMove.id });
         
         // Update local state to reflect the change immediately
         setItems(prev => {
-          const filtered = prev.filter(item => item.id !== itemToMove.id);
-          return [itemToMove, ...filtered];
+          const filtered = prev.filter(item => item.id !== activated.id);
+          return [activated, ...filtered];
         });
+      } else {
+        await invoke("set_clipboard_content", { content });
       }
       
       // Note: Window stays open for continuous use
@@ -7,8 +7,67 @@ export interface ClipboardItem {
   file_path?: string;
   file_size?: number;
   file_name?: string;
+  use_count?: number;
+  content_size?: number;
+  mime_type?: string;
+  metadata?: Record<string, string>;
+  expires_at?: number | null;
+  no_sync?: boolean;
 }
 
 export interface ClipboardStore {
   items: ClipboardItem[];
 }
+
+export interface HistoryQuery {
+  search?: string;
+  content_types?: string[];
+  device?: string;
+  collection_id?: number;
+  sort_by?: "newest" | "oldest" | "most_used" | "largest";
+  date_range?: { start?: number; end?: number };
+  offset?: number;
+  limit?: number;
+}
+
+export interface HistoryPage {
+  items: ClipboardItem[];
+  total: number;
+}
+
+export interface Collection {
+  id: number;
+  name: string;
+  parent_id: number | null;
+  timestamp: string;
+  item_count: number;
+}
+
+export interface ConnectionAuditEntry {
+  id: number;
+  timestamp: number;
+  event:
+    | "request_received"
+    | "accepted"
+    | "accepted_by_peer"
+    | "denied"
+    | "denied_by_peer"
+    | "removed"
+    | "removed_by_peer"
+    | "expired";
+  device_id: number;
+  device_name: string;
+  ip: string;
+  detail: string | null;
+}
+
+export type TransformAction =
+  | { type: "trim" }
+  | { type: "lowercase" }
+  | { type: "regex_replace"; pattern: string; replacement: string }
+  | { type: "strip_url_params"; params?: string[] };
+
+export interface TransformRule {
+  matches?: string | null;
+  action: TransformAction;
+}